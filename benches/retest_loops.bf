@@ -0,0 +1,8 @@
+Benchmark for thread_jumps: counts n down from 200 and each time empties a cell
+holding n through an inner loop that prints one byte per step
+The outer loop body starts with a loop on the cell the outer loop tests so
+each jump back lands straight in the inner loop once jumps are threaded
+Time it with the repeat and quiet and time flags and stdin from dev null
+
+>>++++++++++[<++++++++++++++++++++>-]<[->+<]<+
+[[-.]>>[-<+<+>>]<[->+<]>-<<]
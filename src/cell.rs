@@ -0,0 +1,73 @@
+/// The width of a single tape cell; arithmetic wraps at `2^bits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellWidth {
+    #[default]
+    U8,
+    U16,
+    U32,
+}
+
+impl CellWidth {
+    /// The largest value a cell of this width can hold.
+    pub fn mask(self) -> u32 {
+        match self {
+            CellWidth::U8 => u8::MAX as u32,
+            CellWidth::U16 => u16::MAX as u32,
+            CellWidth::U32 => u32::MAX,
+        }
+    }
+}
+
+/// What happens when arithmetic takes a cell out of its range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellOverflow {
+    /// Wrap around modulo the cell width.
+    #[default]
+    Wrap,
+    /// Stop with `ExecutionResult::CellOverflow`.
+    ///
+    /// Programs should be parsed with the same setting, which keeps runs of `+` and `-` apart so they trap where they would one step at a time.
+    Trap,
+}
+
+pub(crate) trait Cell: Copy + Default + Eq {
+    const MAX: Self;
+    
+    /// Truncates `value` to the cell width.
+    fn from_u32(value: u32) -> Self;
+    fn to_u32(self) -> u32;
+    fn wrapping_add(self, rhs: Self) -> Self;
+    fn wrapping_mul(self, rhs: Self) -> Self;
+    
+    /// Adds `delta`, or returns `None` if the result doesn't fit in the cell.
+    fn checked_add_signed(self, delta: i64) -> Option<Self> {
+        let sum = (self.to_u32() as i64).checked_add(delta)?;
+        (0..=Self::MAX.to_u32() as i64).contains(&sum).then(|| Self::from_u32(sum as u32))
+    }
+}
+
+macro_rules! impl_cell {
+    ($($ty:ty),*) => {$(
+        impl Cell for $ty {
+            const MAX: Self = <$ty>::MAX;
+            
+            fn from_u32(value: u32) -> Self {
+                value as $ty
+            }
+            
+            fn to_u32(self) -> u32 {
+                self as u32
+            }
+            
+            fn wrapping_add(self, rhs: Self) -> Self {
+                <$ty>::wrapping_add(self, rhs)
+            }
+            
+            fn wrapping_mul(self, rhs: Self) -> Self {
+                <$ty>::wrapping_mul(self, rhs)
+            }
+        }
+    )*};
+}
+
+impl_cell!(u8, u16, u32);
@@ -0,0 +1,146 @@
+use std::io;
+use std::io::{BufRead, Write};
+
+use crate::cell::CellWidth;
+use crate::parser::BFInstruction;
+
+/// The first line of every saved checkpoint.
+const MAGIC: &str = "bfint checkpoint 1";
+
+/// Everything needed to continue a run that stopped early, taken by [`Machine::checkpoint`](crate::Machine::checkpoint)
+/// and resumed with [`Machine::restore`](crate::Machine::restore) and [`Machine::resume`](crate::Machine::resume).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// The [`program_hash`] of the program that was running, since resuming any other program is meaningless.
+    pub program_hash: u64,
+    pub cell_width: CellWidth,
+    /// The instruction to continue from.
+    pub program_counter: usize,
+    pub data_pointer: usize,
+    /// Index in `cells` of the cell the data pointer started at; nonzero once a bidirectional tape grew left.
+    pub origin: usize,
+    /// Every allocated cell.
+    pub cells: Vec<u32>,
+}
+
+/// A 64-bit FNV-1a hash of the printed instructions, which tells apart programs that parsed differently.
+pub fn program_hash(program: &[BFInstruction]) -> u64 {
+    fnv1a(program.iter().flat_map(|instruction| format!("{instruction:?};").into_bytes()))
+}
+
+/// The 64-bit FNV-1a hash of `bytes`.
+pub(crate) fn fnv1a(bytes: impl IntoIterator<Item=u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Checkpoint {
+    /// Writes the checkpoint as a few lines of text, one `key value` pair per line with the cells last.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{MAGIC}")?;
+        writeln!(out, "program {:016x}", self.program_hash)?;
+        writeln!(out, "width {}", self.cell_width.mask().count_ones())?;
+        writeln!(out, "pc {}", self.program_counter)?;
+        writeln!(out, "dp {}", self.data_pointer)?;
+        writeln!(out, "origin {}", self.origin)?;
+        write!(out, "cells")?;
+        for cell in &self.cells {
+            write!(out, " {cell}")?;
+        }
+        writeln!(out)
+    }
+    
+    /// Reads a checkpoint written by [`Checkpoint::write_to`], failing with `InvalidData` if it's malformed.
+    pub fn read_from(input: &mut impl BufRead) -> io::Result<Self> {
+        let mut lines = input.lines();
+        if lines.next().transpose()?.as_deref() != Some(MAGIC) {
+            return Err(invalid("not a checkpoint"));
+        }
+        let mut field = |key: &str| -> io::Result<String> {
+            let line = lines.next().ok_or_else(|| invalid("truncated checkpoint"))??;
+            match line.split_once(' ') {
+                Some((found, value)) if found == key => Ok(value.to_string()),
+                None if line == key => Ok(String::new()),
+                _ => Err(invalid("malformed checkpoint")),
+            }
+        };
+        let number = |value: String| value.parse().map_err(|_| invalid("malformed checkpoint"));
+        
+        let program_hash = u64::from_str_radix(&field("program")?, 16).map_err(|_| invalid("malformed checkpoint"))?;
+        let cell_width = match field("width")?.as_str() {
+            "8" => CellWidth::U8,
+            "16" => CellWidth::U16,
+            "32" => CellWidth::U32,
+            _ => return Err(invalid("malformed checkpoint")),
+        };
+        let program_counter = number(field("pc")?)?;
+        let data_pointer = number(field("dp")?)?;
+        let origin = number(field("origin")?)?;
+        let cells = field("cells")?.split(' ').filter(|cell| !cell.is_empty()).map(|cell| cell.parse().map_err(|_| invalid("malformed checkpoint"))).collect::<io::Result<Vec<u32>>>()?;
+        if cells.iter().any(|&cell| cell > cell_width.mask()) || origin > cells.len() {
+            return Err(invalid("malformed checkpoint"));
+        }
+        Ok(Self { program_hash, cell_width, program_counter, data_pointer, origin, cells })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{ExecutionResult, Machine, RunOptions};
+    use crate::parser::parse_data;
+    use crate::tape::TapeMode;
+    
+    #[test]
+    fn round_trip() {
+        let checkpoint = Checkpoint { program_hash: 0xfeed, cell_width: CellWidth::U16, program_counter: 3, data_pointer: usize::MAX, origin: 1, cells: vec![7, 0, 65535] };
+        let mut out = Vec::new();
+        checkpoint.write_to(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out.clone()).unwrap(), "\
+bfint checkpoint 1
+program 000000000000feed
+width 16
+pc 3
+dp 18446744073709551615
+origin 1
+cells 7 0 65535
+");
+        assert_eq!(Checkpoint::read_from(&mut &out[..]).unwrap(), checkpoint);
+        
+        let malformed = String::from_utf8(out).unwrap().replace("width 16", "width 8");
+        assert_eq!(Checkpoint::read_from(&mut malformed.as_bytes()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(Checkpoint::read_from(&mut &b"bfint checkpoint 1\nprogram 1\n"[..]).is_err());
+        assert!(Checkpoint::read_from(&mut &b"+++"[..]).is_err());
+    }
+    
+    #[test]
+    fn resume() {
+        let program = parse_data(b"<+++++[->++<]>>,[.,]").unwrap();
+        let options = RunOptions { tape_mode: TapeMode::Bidirectional, ..RunOptions::default() };
+        let mut expected = Vec::new();
+        let finished = Machine::new(2, &options).resume(&program, 0, &options, &mut &b"hi"[..], &mut expected, &mut ());
+        assert_eq!(finished.result, ExecutionResult::Ok);
+        
+        let limited = RunOptions { max_steps: Some(8), ..options.clone() };
+        let mut machine = Machine::new(2, &options);
+        let mut output = Vec::new();
+        let mut input = &b"hi"[..];
+        let stopped = machine.resume(&program, 0, &limited, &mut input, &mut output, &mut ());
+        assert_eq!(stopped.result, ExecutionResult::StepLimitExceeded);
+        
+        let mut saved = Vec::new();
+        machine.checkpoint(&program, stopped.program_counter).write_to(&mut saved).unwrap();
+        let checkpoint = Checkpoint::read_from(&mut &saved[..]).unwrap();
+        assert_eq!(checkpoint.program_hash, program_hash(&program));
+        assert_ne!(checkpoint.program_hash, program_hash(&parse_data(b"<+++++[->++<]>>,[.,]+").unwrap()));
+        
+        let resumed = Machine::restore(&checkpoint, &options).resume(&program, checkpoint.program_counter, &options, &mut input, &mut output, &mut ());
+        assert_eq!(resumed.result, ExecutionResult::Ok);
+        assert_eq!(output, expected);
+        assert_eq!(resumed.memory, finished.memory);
+        assert_eq!(resumed.data_pointer, finished.data_pointer);
+    }
+}
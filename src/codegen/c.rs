@@ -0,0 +1,180 @@
+use std::io;
+use std::io::Write;
+
+use crate::cell::CellWidth;
+use crate::interpreter::{EofBehavior, RunOptions};
+use crate::parser::BFInstruction;
+
+fn cell_type(cell_width: CellWidth) -> &'static str {
+    match cell_width {
+        CellWidth::U8 => "uint8_t",
+        CellWidth::U16 => "uint16_t",
+        CellWidth::U32 => "uint32_t",
+    }
+}
+
+/// `p` moved by a wrapping offset, written the way a person would.
+fn pointer(offset: isize) -> String {
+    match offset {
+        0 => "p".to_string(),
+        offset @ 1.. => format!("p + {offset}"),
+        offset => format!("p - {}", offset.unsigned_abs()),
+    }
+}
+
+fn emit_input(indent: &str, options: &RunOptions, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{indent}{{")?;
+    writeln!(out, "{indent}    int c = getchar();")?;
+    match options.eof_behavior {
+        EofBehavior::Zero => writeln!(out, "{indent}    *cell(p) = c == EOF ? 0 : c;")?,
+        EofBehavior::Unchanged => writeln!(out, "{indent}    if (c != EOF) *cell(p) = c;")?,
+        EofBehavior::NegativeOne => writeln!(out, "{indent}    *cell(p) = c == EOF ? (cell_t)-1 : c;")?,
+    }
+    writeln!(out, "{indent}}}")
+}
+
+/// Writes a C program equivalent to `program` running on a fixed tape of `memory_size` cells.
+///
+/// The tape starts out holding `options.initial_memory`, cells use `options.cell_width` and `,` follows `options.eof_behavior`; I/O is always byte-based and arithmetic always wraps.
+/// Like the interpreter, leaving the tape is caught and reported, exiting with status 1.
+pub fn emit_c(program: &[BFInstruction], memory_size: usize, options: &RunOptions, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "#include <stdint.h>")?;
+    writeln!(out, "#include <stdio.h>")?;
+    writeln!(out, "#include <stdlib.h>")?;
+    writeln!(out)?;
+    writeln!(out, "#define MEM_SIZE {memory_size}")?;
+    writeln!(out)?;
+    writeln!(out, "typedef {} cell_t;", cell_type(options.cell_width))?;
+    writeln!(out)?;
+    match &options.initial_memory[..options.initial_memory.len().min(memory_size)] {
+        [] => writeln!(out, "static cell_t mem[MEM_SIZE];")?,
+        initial => {
+            let values: Vec<_> = initial.iter().map(u8::to_string).collect();
+            writeln!(out, "static cell_t mem[MEM_SIZE] = {{{}}};", values.join(", "))?;
+        }
+    }
+    writeln!(out)?;
+    writeln!(out, "static cell_t *cell(size_t p) {{")?;
+    writeln!(out, "    if (p >= MEM_SIZE) {{")?;
+    writeln!(out, "        fputs(\"memory access error\\n\", stderr);")?;
+    writeln!(out, "        exit(1);")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "    return &mem[p];")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "int main(void) {{")?;
+    writeln!(out, "    size_t p = 0;")?;
+    
+    let mut depth = 1;
+    for instruction in program {
+        if let BFInstruction::LoopEnd(_) = instruction {
+            depth -= 1;
+        }
+        let indent = "    ".repeat(depth);
+        match *instruction {
+            BFInstruction::Add(val) => writeln!(out, "{indent}*cell(p) += {val}u;")?,
+            BFInstruction::IncrementPointer(by) => match by as isize {
+                by @ 1.. => writeln!(out, "{indent}p += {by};")?,
+                by => writeln!(out, "{indent}p -= {};", by.unsigned_abs())?,
+            },
+            BFInstruction::Output => writeln!(out, "{indent}putchar(*cell(p) & 0xFF);")?,
+            BFInstruction::Input => emit_input(&indent, options, out)?,
+            BFInstruction::LoopStart(_) => {
+                writeln!(out, "{indent}while (*cell(p)) {{")?;
+                depth += 1;
+            }
+            BFInstruction::LoopEnd(_) => writeln!(out, "{indent}}}")?,
+            BFInstruction::ClearCell => writeln!(out, "{indent}*cell(p) = 0;")?,
+            BFInstruction::SetCell(val) => writeln!(out, "{indent}*cell(p) = {val}u;")?,
+            BFInstruction::MulAdd(ref targets) => {
+                writeln!(out, "{indent}if (*cell(p)) {{")?;
+                writeln!(out, "{indent}    cell_t v = *cell(p);")?;
+                for &(offset, multiplier) in targets.iter() {
+                    writeln!(out, "{indent}    *cell({}) += v * {multiplier}u;", pointer(offset))?;
+                }
+                writeln!(out, "{indent}    *cell(p) = 0;")?;
+                writeln!(out, "{indent}}}")?;
+            }
+            BFInstruction::AddAtOffset { offset, value } => writeln!(out, "{indent}*cell({}) += {value}u;", pointer(offset))?,
+            BFInstruction::ClearCellAtOffset(offset) => writeln!(out, "{indent}*cell({}) = 0;", pointer(offset))?,
+            BFInstruction::SetCellAtOffset { offset, value } => writeln!(out, "{indent}*cell({}) = {value}u;", pointer(offset))?,
+            BFInstruction::Debug => writeln!(out, "{indent}fprintf(stderr, \"dp %zu: %lu\\n\", p, (unsigned long)*cell(p));")?,
+            BFInstruction::ScanRight(step) => writeln!(out, "{indent}while (*cell(p)) p += {step};")?,
+            BFInstruction::ScanLeft(step) => writeln!(out, "{indent}while (*cell(p)) p -= {step};")?,
+            BFInstruction::EchoInput => {
+                writeln!(out, "{indent}while (*cell(p)) {{")?;
+                emit_input(&format!("{indent}    "), options, out)?;
+                writeln!(out, "{indent}    putchar(*cell(p) & 0xFF);")?;
+                writeln!(out, "{indent}}}")?;
+            }
+            BFInstruction::OutputN(count) => writeln!(out, "{indent}for (int i = 0; i < {count}; i++) putchar(*cell(p) & 0xFF);")?,
+        }
+    }
+    
+    writeln!(out, "    fflush(stdout);")?;
+    writeln!(out, "    return 0;")?;
+    writeln!(out, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_data;
+    
+    #[test]
+    fn initial_memory() {
+        let mut out = Vec::new();
+        let options = RunOptions { initial_memory: b"hi!".to_vec(), ..RunOptions::default() };
+        emit_c(&parse_data(b".").unwrap(), 2, &options, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("static cell_t mem[MEM_SIZE] = {104, 105};\n"));
+    }
+    
+    #[test]
+    fn small_program() {
+        let mut out = Vec::new();
+        emit_c(&parse_data(b"+++[->++<]>[<,.>-]<<").unwrap(), 16, &RunOptions::default(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#define MEM_SIZE 16
+
+typedef uint8_t cell_t;
+
+static cell_t mem[MEM_SIZE];
+
+static cell_t *cell(size_t p) {
+    if (p >= MEM_SIZE) {
+        fputs(\"memory access error\\n\", stderr);
+        exit(1);
+    }
+    return &mem[p];
+}
+
+int main(void) {
+    size_t p = 0;
+    *cell(p) += 3u;
+    if (*cell(p)) {
+        cell_t v = *cell(p);
+        *cell(p + 1) += v * 2u;
+        *cell(p) = 0;
+    }
+    p += 1;
+    while (*cell(p)) {
+        p -= 1;
+        {
+            int c = getchar();
+            *cell(p) = c == EOF ? 0 : c;
+        }
+        putchar(*cell(p) & 0xFF);
+        p += 1;
+        *cell(p) += 255u;
+    }
+    p -= 2;
+    fflush(stdout);
+    return 0;
+}
+");
+    }
+}
@@ -0,0 +1,88 @@
+use std::io;
+use std::io::Write;
+
+use crate::parser::BFInstruction;
+
+/// Writes `program` as a JSON array with one object per instruction, each naming its variant in `"op"`.
+///
+/// Pointer moves and offsets are signed, `Add` values are the raw wrapping operands and
+/// loop targets are indices into the array.
+pub fn emit_json(program: &[BFInstruction], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (idx, instruction) in program.iter().enumerate() {
+        write!(out, "  ")?;
+        match *instruction {
+            BFInstruction::Add(value) => write!(out, r#"{{"op":"Add","value":{value}}}"#)?,
+            BFInstruction::IncrementPointer(by) => write!(out, r#"{{"op":"IncrementPointer","value":{}}}"#, by as isize)?,
+            BFInstruction::Output => write!(out, r#"{{"op":"Output"}}"#)?,
+            BFInstruction::Input => write!(out, r#"{{"op":"Input"}}"#)?,
+            BFInstruction::LoopStart(target) => write!(out, r#"{{"op":"LoopStart","target":{target}}}"#)?,
+            BFInstruction::LoopEnd(target) => write!(out, r#"{{"op":"LoopEnd","target":{target}}}"#)?,
+            BFInstruction::ClearCell => write!(out, r#"{{"op":"ClearCell"}}"#)?,
+            BFInstruction::SetCell(value) => write!(out, r#"{{"op":"SetCell","value":{value}}}"#)?,
+            BFInstruction::MulAdd(ref targets) => {
+                write!(out, r#"{{"op":"MulAdd","targets":["#)?;
+                for (target_idx, &(offset, multiplier)) in targets.iter().enumerate() {
+                    if target_idx > 0 {
+                        write!(out, ",")?;
+                    }
+                    write!(out, r#"{{"offset":{offset},"multiplier":{multiplier}}}"#)?;
+                }
+                write!(out, "]}}")?;
+            }
+            BFInstruction::AddAtOffset { offset, value } => write!(out, r#"{{"op":"AddAtOffset","offset":{offset},"value":{value}}}"#)?,
+            BFInstruction::ClearCellAtOffset(offset) => write!(out, r#"{{"op":"ClearCellAtOffset","offset":{offset}}}"#)?,
+            BFInstruction::SetCellAtOffset { offset, value } => write!(out, r#"{{"op":"SetCellAtOffset","offset":{offset},"value":{value}}}"#)?,
+            BFInstruction::Debug => write!(out, r#"{{"op":"Debug"}}"#)?,
+            BFInstruction::ScanRight(step) => write!(out, r#"{{"op":"ScanRight","step":{step}}}"#)?,
+            BFInstruction::ScanLeft(step) => write!(out, r#"{{"op":"ScanLeft","step":{step}}}"#)?,
+            BFInstruction::OutputN(count) => write!(out, r#"{{"op":"OutputN","count":{count}}}"#)?,
+            BFInstruction::EchoInput => write!(out, r#"{{"op":"EchoInput"}}"#)?,
+        }
+        if idx + 1 < program.len() {
+            write!(out, ",")?;
+        }
+        writeln!(out)?;
+    }
+    writeln!(out, "]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_data;
+    
+    #[test]
+    fn every_instruction() {
+        let mut program = parse_data(b"++++++<[.,>[-][->++>+++<<]>+<[>][<<]]").unwrap();
+        program.push(BFInstruction::AddAtOffset { offset: -2, value: 255 });
+        program.push(BFInstruction::ClearCellAtOffset(1));
+        program.push(BFInstruction::SetCellAtOffset { offset: 3, value: 4 });
+        let mut out = Vec::new();
+        emit_json(&program, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), r#"[
+  {"op":"Add","value":6},
+  {"op":"IncrementPointer","value":-1},
+  {"op":"LoopStart","target":13},
+  {"op":"Output"},
+  {"op":"Input"},
+  {"op":"IncrementPointer","value":1},
+  {"op":"ClearCell"},
+  {"op":"MulAdd","targets":[{"offset":1,"multiplier":2},{"offset":2,"multiplier":3}]},
+  {"op":"IncrementPointer","value":1},
+  {"op":"Add","value":1},
+  {"op":"IncrementPointer","value":-1},
+  {"op":"ScanRight","step":1},
+  {"op":"ScanLeft","step":2},
+  {"op":"LoopEnd","target":2},
+  {"op":"AddAtOffset","offset":-2,"value":255},
+  {"op":"ClearCellAtOffset","offset":1},
+  {"op":"SetCellAtOffset","offset":3,"value":4}
+]
+"#);
+
+        let mut out = Vec::new();
+        emit_json(&[], &mut out).unwrap();
+        assert_eq!(out, b"[\n]\n");
+    }
+}
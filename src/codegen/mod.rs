@@ -0,0 +1,5 @@
+//! Translators from parsed instructions to other languages and formats.
+
+pub mod c;
+pub mod json;
+pub mod wat;
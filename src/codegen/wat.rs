@@ -0,0 +1,198 @@
+use std::io;
+use std::io::Write;
+
+use crate::cell::CellWidth;
+use crate::interpreter::{EofBehavior, RunOptions};
+use crate::parser::BFInstruction;
+
+const PAGE_SIZE: usize = 1 << 16;
+
+fn cell_bytes(cell_width: CellWidth) -> usize {
+    match cell_width {
+        CellWidth::U8 => 1,
+        CellWidth::U16 => 2,
+        CellWidth::U32 => 4,
+    }
+}
+
+/// The load and store that read and write a single cell.
+fn access(cell_width: CellWidth) -> (&'static str, &'static str) {
+    match cell_width {
+        CellWidth::U8 => ("i32.load8_u", "i32.store8"),
+        CellWidth::U16 => ("i32.load16_u", "i32.store16"),
+        CellWidth::U32 => ("i32.load", "i32.store"),
+    }
+}
+
+/// `$p` moved by a wrapping offset, as an expression.
+fn pointer(offset: isize) -> String {
+    match offset {
+        0 => "(local.get $p)".to_string(),
+        offset => format!("(i32.add (local.get $p) (i32.const {}))", offset as i32),
+    }
+}
+
+fn emit_input(indent: &str, options: &RunOptions, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "{indent}(local.set $v (call $input))")?;
+    match options.eof_behavior {
+        EofBehavior::Zero => writeln!(out, "{indent}(call $set (local.get $p) (select (i32.const 0) (local.get $v) (i32.lt_s (local.get $v) (i32.const 0))))"),
+        EofBehavior::Unchanged => writeln!(out, "{indent}(if (i32.ge_s (local.get $v) (i32.const 0)) (then (call $set (local.get $p) (local.get $v))))"),
+        // Storing -1 truncates it to the largest cell value.
+        EofBehavior::NegativeOne => writeln!(out, "{indent}(call $set (local.get $p) (local.get $v))"),
+    }
+}
+
+/// Writes a WebAssembly text module equivalent to `program` running on a fixed tape of `memory_size` cells.
+///
+/// The module exports its linear memory as `memory`, holding the tape from address 0, and a `run` function that runs
+/// the program once. The host provides these functions in the `env` namespace:
+///
+/// - `output (param i32)` writes a byte, the low byte of the current cell.
+/// - `input (result i32)` reads a byte, returning it as 0 to 255, or -1 at end of input.
+/// - `debug (param i32 i32)` is passed the data pointer and the current cell for `#`; it's only imported by programs
+///   that use it.
+///
+/// Like [`emit_c`](super::c::emit_c), it follows `options.initial_memory`, `options.cell_width` and
+/// `options.eof_behavior`, and arithmetic always wraps. Leaving the tape traps with `unreachable`.
+pub fn emit_wat(program: &[BFInstruction], memory_size: usize, options: &RunOptions, out: &mut impl Write) -> io::Result<()> {
+    let bytes = cell_bytes(options.cell_width);
+    let (load, store) = access(options.cell_width);
+    writeln!(out, "(module")?;
+    writeln!(out, "  (import \"env\" \"output\" (func $output (param i32)))")?;
+    writeln!(out, "  (import \"env\" \"input\" (func $input (result i32)))")?;
+    if program.iter().any(|instruction| matches!(instruction, BFInstruction::Debug)) {
+        writeln!(out, "  (import \"env\" \"debug\" (func $debug (param i32 i32)))")?;
+    }
+    writeln!(out, "  (memory (export \"memory\") {})", memory_size.saturating_mul(bytes).div_ceil(PAGE_SIZE).max(1))?;
+    let initial = &options.initial_memory[..options.initial_memory.len().min(memory_size)];
+    if !initial.is_empty() {
+        // Cells are little-endian, so each byte is followed by the rest of its cell's zero bytes.
+        let padding = "\\00".repeat(bytes - 1);
+        let data: String = initial.iter().map(|byte| format!("\\{byte:02x}{padding}")).collect();
+        writeln!(out, "  (data (i32.const 0) \"{data}\")")?;
+    }
+    writeln!(out, "  (func $addr (param $p i32) (result i32)")?;
+    writeln!(out, "    (if (i32.ge_u (local.get $p) (i32.const {memory_size})) (then unreachable))")?;
+    writeln!(out, "    (i32.mul (local.get $p) (i32.const {bytes})))")?;
+    writeln!(out, "  (func $get (param $p i32) (result i32)")?;
+    writeln!(out, "    ({load} (call $addr (local.get $p))))")?;
+    writeln!(out, "  (func $set (param $p i32) (param $v i32)")?;
+    writeln!(out, "    ({store} (call $addr (local.get $p)) (local.get $v)))")?;
+    writeln!(out, "  (func (export \"run\")")?;
+    writeln!(out, "    (local $p i32) (local $v i32) (local $i i32)")?;
+    
+    let mut depth = 2;
+    for (idx, instruction) in program.iter().enumerate() {
+        if let BFInstruction::LoopEnd(_) = instruction {
+            depth -= 1;
+        }
+        let indent = "  ".repeat(depth);
+        match *instruction {
+            BFInstruction::Add(val) => writeln!(out, "{indent}(call $set (local.get $p) (i32.add (call $get (local.get $p)) (i32.const {val})))")?,
+            BFInstruction::IncrementPointer(by) => writeln!(out, "{indent}(local.set $p {})", pointer(by as isize))?,
+            BFInstruction::Output => writeln!(out, "{indent}(call $output (i32.and (call $get (local.get $p)) (i32.const 255)))")?,
+            BFInstruction::Input => emit_input(&indent, options, out)?,
+            BFInstruction::LoopStart(_) => {
+                writeln!(out, "{indent}(block $b{idx} (loop $l{idx}")?;
+                writeln!(out, "{indent}  (br_if $b{idx} (i32.eqz (call $get (local.get $p))))")?;
+                depth += 1;
+            }
+            BFInstruction::LoopEnd(start) => writeln!(out, "{indent}  (br $l{start})))")?,
+            BFInstruction::ClearCell => writeln!(out, "{indent}(call $set (local.get $p) (i32.const 0))")?,
+            BFInstruction::SetCell(val) => writeln!(out, "{indent}(call $set (local.get $p) (i32.const {val}))")?,
+            BFInstruction::MulAdd(ref targets) => {
+                writeln!(out, "{indent}(local.set $v (call $get (local.get $p)))")?;
+                writeln!(out, "{indent}(if (local.get $v) (then")?;
+                for &(offset, multiplier) in targets.iter() {
+                    let target = pointer(offset);
+                    writeln!(out, "{indent}  (call $set {target} (i32.add (call $get {target}) (i32.mul (local.get $v) (i32.const {multiplier}))))")?;
+                }
+                writeln!(out, "{indent}  (call $set (local.get $p) (i32.const 0))))")?;
+            }
+            BFInstruction::AddAtOffset { offset, value } => {
+                let target = pointer(offset);
+                writeln!(out, "{indent}(call $set {target} (i32.add (call $get {target}) (i32.const {value})))")?;
+            }
+            BFInstruction::ClearCellAtOffset(offset) => writeln!(out, "{indent}(call $set {} (i32.const 0))", pointer(offset))?,
+            BFInstruction::SetCellAtOffset { offset, value } => writeln!(out, "{indent}(call $set {} (i32.const {value}))", pointer(offset))?,
+            BFInstruction::Debug => writeln!(out, "{indent}(call $debug (local.get $p) (call $get (local.get $p)))")?,
+            BFInstruction::ScanRight(step) => {
+                writeln!(out, "{indent}(block (loop (br_if 1 (i32.eqz (call $get (local.get $p))))")?;
+                writeln!(out, "{indent}  (local.set $p {}) (br 0)))", pointer(step as isize))?;
+            }
+            BFInstruction::ScanLeft(step) => {
+                writeln!(out, "{indent}(block (loop (br_if 1 (i32.eqz (call $get (local.get $p))))")?;
+                writeln!(out, "{indent}  (local.set $p {}) (br 0)))", pointer((step as isize).wrapping_neg()))?;
+            }
+            BFInstruction::EchoInput => {
+                writeln!(out, "{indent}(block (loop (br_if 1 (i32.eqz (call $get (local.get $p))))")?;
+                emit_input(&format!("{indent}  "), options, out)?;
+                writeln!(out, "{indent}  (call $output (i32.and (call $get (local.get $p)) (i32.const 255))) (br 0)))")?;
+            }
+            BFInstruction::OutputN(count) => {
+                writeln!(out, "{indent}(local.set $i (i32.const {count}))")?;
+                writeln!(out, "{indent}(loop (call $output (i32.and (call $get (local.get $p)) (i32.const 255)))")?;
+                writeln!(out, "{indent}  (br_if 0 (local.tee $i (i32.sub (local.get $i) (i32.const 1)))))")?;
+            }
+        }
+    }
+    
+    writeln!(out, "  )")?;
+    writeln!(out, ")")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_data;
+    
+    #[test]
+    fn initial_memory() {
+        let mut out = Vec::new();
+        let options = RunOptions { initial_memory: b"hi!".to_vec(), cell_width: CellWidth::U16, ..RunOptions::default() };
+        emit_wat(&parse_data(b".").unwrap(), 2, &options, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("  (data (i32.const 0) \"\\68\\00\\69\\00\")\n"));
+        assert!(out.contains("(i32.load16_u "));
+        assert!(!out.contains("$debug"));
+    }
+    
+    #[test]
+    fn small_program() {
+        let mut out = Vec::new();
+        emit_wat(&parse_data(b"+++[->++<]>[<,.>-]<<").unwrap(), 16, &RunOptions::default(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+(module
+  (import \"env\" \"output\" (func $output (param i32)))
+  (import \"env\" \"input\" (func $input (result i32)))
+  (memory (export \"memory\") 1)
+  (func $addr (param $p i32) (result i32)
+    (if (i32.ge_u (local.get $p) (i32.const 16)) (then unreachable))
+    (i32.mul (local.get $p) (i32.const 1)))
+  (func $get (param $p i32) (result i32)
+    (i32.load8_u (call $addr (local.get $p))))
+  (func $set (param $p i32) (param $v i32)
+    (i32.store8 (call $addr (local.get $p)) (local.get $v)))
+  (func (export \"run\")
+    (local $p i32) (local $v i32) (local $i i32)
+    (call $set (local.get $p) (i32.add (call $get (local.get $p)) (i32.const 3)))
+    (local.set $v (call $get (local.get $p)))
+    (if (local.get $v) (then
+      (call $set (i32.add (local.get $p) (i32.const 1)) (i32.add (call $get (i32.add (local.get $p) (i32.const 1))) (i32.mul (local.get $v) (i32.const 2))))
+      (call $set (local.get $p) (i32.const 0))))
+    (local.set $p (i32.add (local.get $p) (i32.const 1)))
+    (block $b3 (loop $l3
+      (br_if $b3 (i32.eqz (call $get (local.get $p))))
+      (local.set $p (i32.add (local.get $p) (i32.const -1)))
+      (local.set $v (call $input))
+      (call $set (local.get $p) (select (i32.const 0) (local.get $v) (i32.lt_s (local.get $v) (i32.const 0))))
+      (call $output (i32.and (call $get (local.get $p)) (i32.const 255)))
+      (local.set $p (i32.add (local.get $p) (i32.const 1)))
+      (call $set (local.get $p) (i32.add (call $get (local.get $p)) (i32.const 255)))
+      (br $l3)))
+    (local.set $p (i32.add (local.get $p) (i32.const -2)))
+  )
+)
+");
+    }
+}
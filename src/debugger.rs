@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::io::{BufRead, Write};
+use std::ops::ControlFlow;
+
+use crate::interpreter::{ExecutionResult, Hook, MachineState};
+
+/// How many cells on each side of the data pointer are shown when paused.
+const WINDOW: isize = 4;
+
+/// An interactive step debugger.
+///
+/// When paused, it prints the machine state to `out` and reads a command line from `commands`:
+/// an empty line (or `s`) steps one instruction and `c` continues until the next breakpoint.
+/// If `commands` is exhausted, the program runs to completion.
+pub struct Debugger<R, W> {
+    commands: R,
+    out: W,
+    breakpoints: BTreeSet<usize>,
+    stepping: bool,
+}
+
+impl<R: BufRead, W: Write> Debugger<R, W> {
+    /// Creates a debugger that pauses at each index in `breakpoints`, and before the first instruction if `stepping`.
+    pub fn new(commands: R, out: W, breakpoints: impl IntoIterator<Item=usize>, stepping: bool) -> Self {
+        Self {
+            commands,
+            out,
+            breakpoints: breakpoints.into_iter().collect(),
+            stepping,
+        }
+    }
+    
+    fn pause(&mut self, state: &MachineState<'_>) -> io::Result<()> {
+        writeln!(self.out, "pc {}  {:?}", state.program_counter, state.program[state.program_counter])?;
+        // Pointers that wrapped below zero are easier to read as negative.
+        let data_pointer = state.data_pointer as isize;
+        write!(self.out, "dp {data_pointer} ")?;
+        for pointer in data_pointer.saturating_sub(WINDOW)..=data_pointer.saturating_add(WINDOW) {
+            let Some(value) = state.cell(pointer as usize) else {
+                continue;
+            };
+            
+            if pointer == data_pointer {
+                write!(self.out, " [{pointer}:{value}]")?;
+            } else {
+                write!(self.out, " {pointer}:{value}")?;
+            }
+        }
+        write!(self.out, "\n(enter: step, c: continue) ")?;
+        self.out.flush()?;
+        
+        let mut command = String::new();
+        if self.commands.read_line(&mut command)? == 0 {
+            self.stepping = false;
+            return Ok(());
+        }
+        
+        match command.trim() {
+            "c" => self.stepping = false,
+            _ => self.stepping = true,
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead, W: Write> Hook for Debugger<R, W> {
+    fn before_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        if !self.stepping && !self.breakpoints.contains(&state.program_counter) {
+            return ControlFlow::Continue(());
+        }
+        
+        match self.pause(state) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(err) => ControlFlow::Break(ExecutionResult::IOError(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{RunOptions, run_program_with_hook};
+    use crate::parser::parse_data;
+    use crate::tape::TapeMode;
+    
+    #[test]
+    fn breakpoint_and_step() {
+        let program = parse_data(b"+>++>+++.").unwrap();
+        let mut out = Vec::new();
+        let mut debugger = Debugger::new(&b"\nc\n"[..], &mut out, [2], false);
+        let mut output = Vec::new();
+        assert_eq!(run_program_with_hook(&program, 3, &RunOptions::default(), &mut io::empty(), &mut output, &mut debugger), ExecutionResult::Ok);
+        assert_eq!(output, [3]);
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+pc 2  Add(2)
+dp 1  0:1 [1:0] 2:0
+(enter: step, c: continue) pc 3  IncrementPointer(1)
+dp 1  0:1 [1:2] 2:0
+(enter: step, c: continue) ");
+    }
+    
+    #[test]
+    fn left_of_origin() {
+        let program = parse_data(b"+<++<+++>").unwrap();
+        let mut out = Vec::new();
+        let mut debugger = Debugger::new(&b"c\n"[..], &mut out, [5], false);
+        let options = RunOptions { tape_mode: TapeMode::Bidirectional, ..RunOptions::default() };
+        assert_eq!(run_program_with_hook(&program, 2, &options, &mut io::empty(), &mut io::sink(), &mut debugger), ExecutionResult::Ok);
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+pc 5  IncrementPointer(1)
+dp -2  -6:0 -5:0 -4:0 -3:0 [-2:3] -1:2 0:1 1:0 2:0
+(enter: step, c: continue) ");
+    }
+}
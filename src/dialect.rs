@@ -0,0 +1,73 @@
+use std::fmt;
+
+/// The bytes a [`Dialect`] can map aliases to: the eight commands plus `#` and `!`,
+/// which only mean something with [`ParseOptions::debug_char`](crate::ParseOptions::debug_char)
+/// and [`ParseOptions::bang_input`](crate::ParseOptions::bang_input).
+const COMMANDS: &[u8] = b"+-<>.,[]#!";
+
+/// A spelling of Brainfuck that uses other bytes for its commands.
+///
+/// Only the bytes given a command are commands; every other byte, including the standard symbols, is a comment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dialect {
+    commands: [Option<u8>; 256],
+}
+
+/// A line of a dialect spec that isn't a command followed by a single-byte alias.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DialectError {
+    /// One-based line number.
+    pub line: usize,
+}
+
+impl fmt::Display for DialectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid mapping on line {}", self.line)
+    }
+}
+
+impl std::error::Error for DialectError {}
+
+impl Dialect {
+    /// Reads a spec with one mapping per line: a standard command, whitespace, then the byte that stands for it, like `+ a`.
+    ///
+    /// A command may have several aliases on separate lines. Blank lines are ignored.
+    pub fn parse(spec: &[u8]) -> Result<Self, DialectError> {
+        let mut commands = [None; 256];
+        for (idx, line) in spec.split(|&byte| byte == b'\n').enumerate() {
+            let mut fields = line.split(u8::is_ascii_whitespace).filter(|field| !field.is_empty());
+            match (fields.next(), fields.next(), fields.next()) {
+                (None, _, _) => {}
+                (Some(&[command]), Some(&[alias]), None) if COMMANDS.contains(&command) => commands[alias as usize] = Some(command),
+                _ => return Err(DialectError { line: idx + 1 }),
+            }
+        }
+        Ok(Self { commands })
+    }
+    
+    /// The standard command `byte` stands for, if any.
+    pub fn command(&self, byte: u8) -> Option<u8> {
+        self.commands[byte as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn parse() {
+        let dialect = Dialect::parse(b"+ a\n- b\r\n\n  [ (\n] )\n] }\n").unwrap();
+        assert_eq!(dialect.command(b'a'), Some(b'+'));
+        assert_eq!(dialect.command(b'b'), Some(b'-'));
+        assert_eq!(dialect.command(b'('), Some(b'['));
+        assert_eq!(dialect.command(b')'), Some(b']'));
+        assert_eq!(dialect.command(b'}'), Some(b']'));
+        assert_eq!(dialect.command(b'+'), None);
+        
+        assert_eq!(Dialect::parse(b"+ a\nx y\n"), Err(DialectError { line: 2 }));
+        assert_eq!(Dialect::parse(b"+ ab"), Err(DialectError { line: 1 }));
+        assert_eq!(Dialect::parse(b"+"), Err(DialectError { line: 1 }));
+        assert_eq!(Dialect::parse(b"+ a b").unwrap_err().to_string(), "invalid mapping on line 1");
+    }
+}
@@ -0,0 +1,161 @@
+use std::io;
+use std::io::Write;
+use std::ops::ControlFlow;
+
+use crate::cell::CellWidth;
+use crate::interpreter::{ExecutionResult, Hook, MachineState, RunOutcome};
+use crate::parser::BFInstruction;
+
+/// How many cells on each side of the data pointer are dumped.
+const WINDOW: isize = 32;
+/// How many cells are shown per line.
+const ROW: isize = 16;
+
+/// Writes the machine state and a hex view of the tape around the data pointer
+/// at every `Debug` instruction and, optionally, when a program fails.
+pub struct MemoryDump<W> {
+    out: W,
+    cell_width: CellWidth,
+    on_error: bool,
+}
+
+impl<W: Write> MemoryDump<W> {
+    /// Creates a dump writing to `out`, showing cells as hex numbers wide enough for `cell_width`.
+    pub fn new(out: W, cell_width: CellWidth, on_error: bool) -> Self {
+        Self { out, cell_width, on_error }
+    }
+    
+    /// Dumps the state a finished run stopped in, such as after an interrupt.
+    ///
+    /// Only `outcome.memory` is shown, so cells left of the starting one on a bidirectional tape show as missing.
+    pub fn dump_outcome(&mut self, program: &[BFInstruction], outcome: &RunOutcome) -> io::Result<()> {
+        self.write(program, outcome.program_counter, outcome.data_pointer, |pointer| outcome.memory.get(pointer).copied())
+    }
+    
+    /// Dumps the first `cells` cells of the tape a finished run left behind, marking the cell the data pointer is on.
+    ///
+    /// Cells past the end of `outcome.memory` show as missing, and the data pointer is marked wherever it falls in the rows shown.
+    pub fn dump_cells(&mut self, outcome: &RunOutcome, cells: usize) -> io::Result<()> {
+        let data_pointer = outcome.data_pointer as isize;
+        writeln!(self.out, "dp {data_pointer}")?;
+        let rows = (0..cells as isize).step_by(ROW as usize);
+        self.write_rows(rows, data_pointer, |pointer| outcome.memory.get(pointer).copied().filter(|_| pointer < cells))?;
+        self.out.flush()
+    }
+    
+    fn dump(&mut self, state: &MachineState<'_>) -> io::Result<()> {
+        self.write(state.program, state.program_counter, state.data_pointer, |pointer| state.cell(pointer))
+    }
+    
+    fn write(&mut self, program: &[BFInstruction], program_counter: usize, data_pointer: usize, cell: impl Fn(usize) -> Option<u32>) -> io::Result<()> {
+        match program.get(program_counter) {
+            Some(instruction) => writeln!(self.out, "pc {program_counter}  {instruction:?}")?,
+            None => writeln!(self.out, "pc {program_counter}")?,
+        }
+        // Pointers that wrapped below zero are easier to read as negative.
+        let data_pointer = data_pointer as isize;
+        writeln!(self.out, "dp {data_pointer}")?;
+        
+        let first_row = data_pointer.saturating_sub(WINDOW).div_euclid(ROW) * ROW;
+        let rows = (first_row..=data_pointer.saturating_add(WINDOW)).step_by(ROW as usize);
+        self.write_rows(rows, data_pointer, cell)?;
+        self.out.flush()
+    }
+    
+    /// Writes a line of cells for each row start, skipping rows with no cells on the tape that the data pointer isn't in.
+    fn write_rows(&mut self, rows: impl Iterator<Item=isize>, data_pointer: isize, cell: impl Fn(usize) -> Option<u32>) -> io::Result<()> {
+        let digits = self.cell_width.mask().count_ones() as usize / 4;
+        for row in rows {
+            let cells: Vec<_> = (row..row + ROW).map(|pointer| cell(pointer as usize)).collect();
+            if cells.iter().all(Option::is_none) && !(row..row + ROW).contains(&data_pointer) {
+                continue;
+            }
+            
+            write!(self.out, "{row:>8}:")?;
+            for (pointer, cell) in (row..).zip(cells) {
+                let (open, close) = if pointer == data_pointer { ('[', ']') } else { (' ', ' ') };
+                match cell {
+                    Some(value) => write!(self.out, "{open}{value:0digits$x}{close}")?,
+                    None => write!(self.out, "{open}{:-<digits$}{close}", "")?,
+                }
+            }
+            writeln!(self.out)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Hook for MemoryDump<W> {
+    fn before_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        if let BFInstruction::Debug = state.program[state.program_counter] {
+            if let Err(err) = self.dump(state) {
+                return ControlFlow::Break(ExecutionResult::IOError(err));
+            }
+        }
+        ControlFlow::Continue(())
+    }
+    
+    fn after_execution(&mut self, state: &MachineState<'_>, result: &ExecutionResult) {
+        if self.on_error && !matches!(result, ExecutionResult::Ok) {
+            // The dump is best effort; the caller still reports the failure itself.
+            let _ = self.dump(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{RunOptions, run_program_outcome, run_program_with_hook};
+    use crate::parser::{ParseOptions, parse_data, parse_data_with_options};
+    
+    #[test]
+    fn dump_on_error() {
+        let mut out = Vec::new();
+        let mut dump = MemoryDump::new(&mut out, CellWidth::U8, true);
+        assert_eq!(run_program_with_hook(&parse_data(b"+>++<<+").unwrap(), 3, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut dump), ExecutionResult::MemoryUnderflow(4));
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+pc 4  Add(1)
+dp -1
+     -16: --  --  --  --  --  --  --  --  --  --  --  --  --  --  -- [--]
+       0: 01  02  00  --  --  --  --  --  --  --  --  --  --  --  --  -- 
+");
+
+        let mut out = Vec::new();
+        let mut dump = MemoryDump::new(&mut out, CellWidth::U16, true);
+        assert_eq!(run_program_with_hook(&parse_data(b"+>++<").unwrap(), 3, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut dump), ExecutionResult::Ok);
+        assert!(out.is_empty());
+    }
+    
+    #[test]
+    fn final_cells() {
+        let outcome = run_program_outcome(&parse_data(b"++++++++[>++++<-]>+>>-<").unwrap(), 20, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut ());
+        let mut out = Vec::new();
+        MemoryDump::new(&mut out, CellWidth::U8, false).dump_cells(&outcome, 20).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+dp 2
+       0: 00  21 [00] ff  00  00  00  00  00  00  00  00  00  00  00  00 
+      16: 00  00  00  00  --  --  --  --  --  --  --  --  --  --  --  -- 
+");
+
+        let mut out = Vec::new();
+        MemoryDump::new(&mut out, CellWidth::U8, false).dump_cells(&outcome, 2).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "dp 2\n       0: 00  21 [--] --  --  --  --  --  --  --  --  --  --  --  --  -- \n");
+    }
+    
+    #[test]
+    fn debug_char() {
+        let program = parse_data_with_options(b"+>#++#>-", &ParseOptions { debug_char: true, ..ParseOptions::default() }).unwrap();
+        let mut out = Vec::new();
+        let mut dump = MemoryDump::new(&mut out, CellWidth::U16, false);
+        assert_eq!(run_program_with_hook(&program, 2, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut dump), ExecutionResult::MemoryAccessError(6));
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+pc 2  Debug
+dp 1
+       0: 0001 [0000] ----  ----  ----  ----  ----  ----  ----  ----  ----  ----  ----  ----  ----  ---- 
+pc 4  Debug
+dp 1
+       0: 0001 [0002] ----  ----  ----  ----  ----  ----  ----  ----  ----  ----  ----  ----  ----  ---- 
+");
+    }
+}
@@ -0,0 +1,138 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::dialect::DialectError;
+use crate::interpreter::ExecutionResult;
+use crate::ir::IrError;
+use crate::parser::ParseError;
+
+/// Anything that can go wrong getting a program from source to the end of its run, as one error type.
+///
+/// The parsers and the interpreter keep their own, more specific results; this gathers them up so a caller can use `?`
+/// across both. A run converts with [`ExecutionResult::into_result`], the rest with `From`.
+#[derive(Debug)]
+pub enum BfError {
+    /// The source didn't parse.
+    Parse(ParseError),
+    /// A dialect's tokens couldn't be used.
+    Dialect(DialectError),
+    /// Textual IR didn't parse.
+    Ir(IrError),
+    /// The instruction at the given index accessed a cell past the right end of the tape.
+    MemoryAccess(usize),
+    /// The instruction at the given index accessed a cell left of the first one.
+    MemoryUnderflow(usize),
+    /// Reading input or writing output failed.
+    Io(io::Error),
+    /// The run hit `RunOptions::max_steps`.
+    StepLimitExceeded,
+    /// The run hit `RunOptions::max_output`.
+    OutputLimitExceeded,
+    /// The instruction at the given index overflowed a cell while overflow traps.
+    CellOverflow(usize),
+    /// The run took longer than `RunOptions::timeout`.
+    Timeout,
+    /// The run was interrupted through `RunOptions::interrupt`.
+    Interrupted,
+    /// The instruction at the given index tried to do I/O while `RunOptions::no_io` is set.
+    IoForbidden(usize),
+}
+
+impl fmt::Display for BfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BfError::Parse(err) => write!(f, "couldn't parse program: {err}"),
+            BfError::Dialect(err) => write!(f, "couldn't parse dialect: {err}"),
+            BfError::Ir(err) => write!(f, "couldn't parse IR: {err}"),
+            BfError::MemoryAccess(instruction) => write!(f, "memory access error at instruction {instruction}: past the end of the tape"),
+            BfError::MemoryUnderflow(instruction) => write!(f, "memory access error at instruction {instruction}: left of the start of the tape"),
+            BfError::Io(err) => write!(f, "I/O error: {err}"),
+            BfError::StepLimitExceeded => write!(f, "step limit exceeded"),
+            BfError::OutputLimitExceeded => write!(f, "output limit exceeded"),
+            BfError::CellOverflow(instruction) => write!(f, "cell overflow at instruction {instruction}"),
+            BfError::Timeout => write!(f, "timed out"),
+            BfError::Interrupted => write!(f, "interrupted"),
+            BfError::IoForbidden(instruction) => write!(f, "I/O forbidden at instruction {instruction}"),
+        }
+    }
+}
+
+impl Error for BfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BfError::Parse(err) => Some(err),
+            BfError::Dialect(err) => Some(err),
+            BfError::Ir(err) => Some(err),
+            BfError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError> for BfError {
+    fn from(err: ParseError) -> Self {
+        BfError::Parse(err)
+    }
+}
+
+impl From<DialectError> for BfError {
+    fn from(err: DialectError) -> Self {
+        BfError::Dialect(err)
+    }
+}
+
+impl From<IrError> for BfError {
+    fn from(err: IrError) -> Self {
+        BfError::Ir(err)
+    }
+}
+
+impl From<io::Error> for BfError {
+    fn from(err: io::Error) -> Self {
+        BfError::Io(err)
+    }
+}
+
+impl ExecutionResult {
+    /// `Ok(())` for a run that finished, or what stopped it as a [`BfError`].
+    pub fn into_result(self) -> Result<(), BfError> {
+        Err(match self {
+            ExecutionResult::Ok => return Ok(()),
+            ExecutionResult::MemoryAccessError(instruction) => BfError::MemoryAccess(instruction),
+            ExecutionResult::MemoryUnderflow(instruction) => BfError::MemoryUnderflow(instruction),
+            ExecutionResult::IOError(err) => BfError::Io(err),
+            ExecutionResult::StepLimitExceeded => BfError::StepLimitExceeded,
+            ExecutionResult::OutputLimitExceeded => BfError::OutputLimitExceeded,
+            ExecutionResult::CellOverflow(instruction) => BfError::CellOverflow(instruction),
+            ExecutionResult::Timeout => BfError::Timeout,
+            ExecutionResult::Interrupted => BfError::Interrupted,
+            ExecutionResult::IoForbidden(instruction) => BfError::IoForbidden(instruction),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{RunOptions, run_program_outcome};
+    use crate::parser::parse_data;
+    
+    fn run(source: &[u8], memory_size: usize) -> Result<Vec<u8>, BfError> {
+        let program = parse_data(source)?;
+        let mut output = Vec::new();
+        run_program_outcome(&program, memory_size, &RunOptions::default(), &mut io::empty(), &mut output, &mut ()).result.into_result()?;
+        Ok(output)
+    }
+    
+    #[test]
+    fn question_mark() {
+        assert_eq!(run(b"+++.", 1).unwrap(), [3]);
+        let err = run(b"+[", 1).unwrap_err();
+        assert_eq!(err.to_string(), "couldn't parse program: unclosed '[' at line 1, column 2");
+        assert!(err.source().is_some());
+        let err = run(b">>+", 2).unwrap_err();
+        assert_eq!(err.to_string(), "memory access error at instruction 1: past the end of the tape");
+        assert!(err.source().is_none());
+    }
+}
@@ -0,0 +1,1324 @@
+use std::io;
+use std::io::{BufWriter, Read, Write};
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::cell::{Cell, CellOverflow, CellWidth};
+use crate::checkpoint::{Checkpoint, fnv1a, program_hash};
+use crate::parser::BFInstruction;
+use crate::tape::{Tape, TapeMode, TapeView};
+
+/// The outcome of running a program.
+#[cfg_attr(test, derive(Debug))]
+pub enum ExecutionResult {
+    /// The program ran to completion.
+    Ok,
+    /// The instruction at the given index accessed a cell past the right end of the tape.
+    MemoryAccessError(usize),
+    /// The instruction at the given index accessed a cell left of the first one, which only a bidirectional tape has.
+    MemoryUnderflow(usize),
+    /// Reading from the input or writing to the output failed, or a hook's own I/O did.
+    IOError(io::Error),
+    /// The program executed `RunOptions::max_steps` instructions without finishing.
+    StepLimitExceeded,
+    /// The program tried to write more than `RunOptions::max_output` bytes; the bytes up to the limit were written.
+    OutputLimitExceeded,
+    /// The instruction at the given index took a cell past its maximum or below zero while overflow traps.
+    CellOverflow(usize),
+    /// The program ran for longer than `RunOptions::timeout`.
+    Timeout,
+    /// `RunOptions::interrupt` was set while the program ran.
+    Interrupted,
+    /// The instruction at the given index would have read input or written output while `RunOptions::no_io` is set.
+    IoForbidden(usize),
+}
+
+/// Compares I/O errors by kind, since `io::Error` itself can't be compared.
+#[cfg(test)]
+impl PartialEq for ExecutionResult {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ExecutionResult::MemoryAccessError(a), ExecutionResult::MemoryAccessError(b)) => a == b,
+            (ExecutionResult::MemoryUnderflow(a), ExecutionResult::MemoryUnderflow(b)) => a == b,
+            (ExecutionResult::IOError(a), ExecutionResult::IOError(b)) => a.kind() == b.kind(),
+            (ExecutionResult::CellOverflow(a), ExecutionResult::CellOverflow(b)) => a == b,
+            (ExecutionResult::IoForbidden(a), ExecutionResult::IoForbidden(b)) => a == b,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+/// The result of a run together with the machine state it stopped in.
+#[cfg_attr(test, derive(Debug, PartialEq))]
+pub struct RunOutcome {
+    pub result: ExecutionResult,
+    /// The tape, where `memory[i]` is the cell at pointer `i`.
+    ///
+    /// Growing tapes include every cell allocated so far; cells left of the starting one on a bidirectional tape aren't included.
+    pub memory: Vec<u32>,
+    pub data_pointer: usize,
+    /// The instruction that stopped the program, or `program.len()` if it ran to completion.
+    pub program_counter: usize,
+    /// The value of the cell at `data_pointer`, or `None` if the pointer is off the tape.
+    pub current_cell: Option<u32>,
+    /// How many instructions were executed, counting the one that stopped the program.
+    pub steps: u64,
+}
+
+impl RunOutcome {
+    /// A hash of the tape up to its last nonzero cell, so two runs leaving the same values compare equal whatever their
+    /// tape sizes.
+    ///
+    /// This is the 64-bit FNV-1a hash of each cell of `memory` as four little-endian bytes, a format that won't change,
+    /// so it can be kept to check later runs against. A blank tape hashes to the FNV-1a offset basis, `cbf29ce484222325`.
+    pub fn checksum(&self) -> u64 {
+        let len = self.memory.iter().rposition(|&cell| cell != 0).map_or(0, |last| last + 1);
+        fnv1a(self.memory[..len].iter().flat_map(|cell| cell.to_le_bytes()))
+    }
+}
+
+/// What `Input` stores in the current cell once the input is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofBehavior {
+    /// Store `0`.
+    #[default]
+    Zero,
+    /// Leave the cell as it was.
+    Unchanged,
+    /// Store `-1`, i.e. the maximum cell value.
+    NegativeOne,
+}
+
+/// Makes `Output` print cells as decimal lines and `Input` parse a decimal line into the cell.
+///
+/// Input may be negative either way, and numbers outside the cell's range wrap into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NumericIo {
+    /// Store `0` for input lines that aren't a number instead of failing with `IOError`.
+    pub malformed_as_zero: bool,
+    /// Print cells as two's complement signed numbers, so an 8-bit cell holding 255 prints as `-1`.
+    pub signed: bool,
+}
+
+/// Makes `Output` write the current cell as a Unicode scalar value encoded as UTF-8, instead of its low byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Utf8Output {
+    /// Write U+FFFD for cells that aren't a valid scalar value (surrogates and values past U+10FFFF) instead of failing with `IOError`.
+    pub replace_invalid: bool,
+}
+
+/// Settings controlling how a program is executed.
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    pub eof_behavior: EofBehavior,
+    /// Should match the width the program was parsed for. `Output` writes the low byte of a cell and `Input` zero-extends the byte it reads.
+    pub cell_width: CellWidth,
+    pub tape_mode: TapeMode,
+    /// Treat cells at this index or past it as off the tape, failing with `MemoryAccessError` even on a tape that has
+    /// them or could grow to them.
+    pub cap_index: Option<usize>,
+    /// Use decimal numbers instead of raw bytes for `Output` and `Input`.
+    pub numeric_io: Option<NumericIo>,
+    /// Encode `Output` as UTF-8; ignored when `numeric_io` is set.
+    pub utf8_output: Option<Utf8Output>,
+    /// Write a newline from `Output` as `\r\n`, like C's text-mode streams; ignored when `numeric_io` is set.
+    pub crlf: bool,
+    /// Clear the high bit of every byte `Output` writes, for terminals that choke on bytes past 127.
+    ///
+    /// Ignored when `numeric_io` is set; it takes precedence over `crlf` and `utf8_output`, which it isn't meant to be combined with.
+    pub ascii_mask: bool,
+    /// Stop with `Ok` instead of `IOError` when the output is closed (a `BrokenPipe` error), the way command-line tools piped into `head` do.
+    pub ignore_broken_pipe: bool,
+    /// Stop with `StepLimitExceeded` after this many instructions; a fused instruction counts as one.
+    pub max_steps: Option<u64>,
+    /// Stop with `OutputLimitExceeded` instead of writing more than this many bytes, counted after any CRLF or UTF-8 expansion.
+    pub max_output: Option<u64>,
+    /// Stop with `IoForbidden` at the first instruction that would read or write, for running programs as pure computation.
+    pub no_io: bool,
+    /// Stop with `Timeout` once this much wall-clock time has passed.
+    ///
+    /// The clock is only checked every [`TIMEOUT_CHECK_INTERVAL`] instructions, and not while waiting for input,
+    /// so a run can overshoot the timeout by however long that takes.
+    pub timeout: Option<Duration>,
+    /// Stop with `Interrupted` once this flag is set, for example from a signal handler.
+    ///
+    /// Like the timeout, the flag is only checked every [`TIMEOUT_CHECK_INTERVAL`] instructions and not while waiting for input.
+    pub interrupt: Option<&'static AtomicBool>,
+    pub cell_overflow: CellOverflow,
+    /// Bytes loaded into the first cells of the tape before the program starts; the rest of the tape is zero.
+    /// Bytes beyond `memory_size` are dropped.
+    pub initial_memory: Vec<u8>,
+}
+
+/// How many instructions run between checks of `RunOptions::timeout` and `RunOptions::interrupt`.
+pub const TIMEOUT_CHECK_INTERVAL: u64 = 1 << 16;
+
+/// A read-only snapshot of the machine, handed to a [`Hook`].
+pub struct MachineState<'a> {
+    pub program: &'a [BFInstruction],
+    pub program_counter: usize,
+    pub data_pointer: usize,
+    tape: &'a dyn TapeView,
+}
+
+impl MachineState<'_> {
+    /// The value of the cell at `pointer`, or `None` if it's outside the tape.
+    pub fn cell(&self, pointer: usize) -> Option<u32> {
+        self.tape.cell(pointer)
+    }
+}
+
+/// Observes or interrupts execution. Returning `Break` stops the program with the given result.
+pub trait Hook {
+    /// Called before the instruction at `state.program_counter` runs.
+    fn before_instruction(&mut self, _state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        ControlFlow::Continue(())
+    }
+    
+    /// Called after an instruction ran without stopping the program, with `state.program_counter` at the next one.
+    fn after_instruction(&mut self, _state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        ControlFlow::Continue(())
+    }
+    
+    /// Called once execution stops, with the final state.
+    ///
+    /// `state.program_counter` is the instruction that stopped the program, or `program.len()` if it ran to completion.
+    fn after_execution(&mut self, _state: &MachineState<'_>, _result: &ExecutionResult) {}
+}
+
+impl Hook for () {}
+
+impl<H: Hook> Hook for Option<H> {
+    fn before_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        match self {
+            Some(hook) => hook.before_instruction(state),
+            None => ControlFlow::Continue(()),
+        }
+    }
+    
+    fn after_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        match self {
+            Some(hook) => hook.after_instruction(state),
+            None => ControlFlow::Continue(()),
+        }
+    }
+    
+    fn after_execution(&mut self, state: &MachineState<'_>, result: &ExecutionResult) {
+        if let Some(hook) = self {
+            hook.after_execution(state, result);
+        }
+    }
+}
+
+macro_rules! impl_hook_tuple {
+    ($($hook:ident),*) => {
+        /// Runs each hook in order; the first to break stops the rest.
+        impl<$($hook: Hook),*> Hook for ($($hook,)*) {
+            fn before_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+                #[allow(non_snake_case)]
+                let ($($hook,)*) = self;
+                $($hook.before_instruction(state)?;)*
+                ControlFlow::Continue(())
+            }
+            
+            fn after_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+                #[allow(non_snake_case)]
+                let ($($hook,)*) = self;
+                $($hook.after_instruction(state)?;)*
+                ControlFlow::Continue(())
+            }
+            
+            fn after_execution(&mut self, state: &MachineState<'_>, result: &ExecutionResult) {
+                #[allow(non_snake_case)]
+                let ($($hook,)*) = self;
+                $($hook.after_execution(state, result);)*
+            }
+        }
+    };
+}
+
+impl_hook_tuple!(A, B);
+impl_hook_tuple!(A, B, C);
+impl_hook_tuple!(A, B, C, D);
+impl_hook_tuple!(A, B, C, D, E);
+impl_hook_tuple!(A, B, C, D, E, F);
+impl_hook_tuple!(A, B, C, D, E, F, G);
+
+/// Runs a parsed program on a zeroed tape of `memory_size` cells, using stdin and (buffered) stdout for I/O.
+pub fn run_program(program: &[BFInstruction], memory_size: usize) -> ExecutionResult {
+    run_program_io(program, memory_size, &mut io::stdin().lock(), &mut BufWriter::new(io::stdout().lock()))
+}
+
+/// Runs a parsed program on a zeroed tape of `memory_size` cells, using the given streams for I/O.
+///
+/// `output` is only flushed before each `Input` and once execution stops, so wrap slow writers in a `BufWriter`.
+pub fn run_program_io(program: &[BFInstruction], memory_size: usize, input: &mut impl Read, output: &mut impl Write) -> ExecutionResult {
+    run_program_with_options(program, memory_size, &RunOptions::default(), input, output)
+}
+
+/// Runs a parsed program on a zeroed tape of `memory_size` cells with the given options and I/O streams.
+pub fn run_program_with_options(program: &[BFInstruction], memory_size: usize, options: &RunOptions, input: &mut impl Read, output: &mut impl Write) -> ExecutionResult {
+    run_program_with_hook(program, memory_size, options, input, output, &mut ())
+}
+
+/// Like [`run_program_with_options`], calling `hook` before every executed instruction.
+pub fn run_program_with_hook(program: &[BFInstruction], memory_size: usize, options: &RunOptions, input: &mut impl Read, output: &mut impl Write, hook: &mut impl Hook) -> ExecutionResult {
+    run_program_outcome(program, memory_size, options, input, output, hook).result
+}
+
+/// Like [`run_program_with_hook`], also returning the final tape and registers.
+pub fn run_program_outcome(program: &[BFInstruction], memory_size: usize, options: &RunOptions, input: &mut impl Read, output: &mut impl Write, hook: &mut impl Hook) -> RunOutcome {
+    Machine::new(memory_size, options).resume(program, 0, options, input, output, hook)
+}
+
+enum AnyTape {
+    U8(Tape<u8>),
+    U16(Tape<u16>),
+    U32(Tape<u32>),
+}
+
+/// A tape and data pointer that outlive a single run, so that programs can run one after another on the same memory.
+pub struct Machine {
+    tape: AnyTape,
+    data_pointer: usize,
+    /// Input an `EchoInput` read past the zero byte it stopped at, which later reads see first.
+    pending_input: Vec<u8>,
+}
+
+impl Machine {
+    /// Creates a machine whose tape of `memory_size` cells follows `options.cell_width`, `options.tape_mode`,
+    /// `options.cap_index` and `options.initial_memory`, with the data pointer on the first cell.
+    pub fn new(memory_size: usize, options: &RunOptions) -> Self {
+        let tape = match options.cell_width {
+            CellWidth::U8 => AnyTape::U8(Tape::new(memory_size, options.tape_mode, &options.initial_memory, options.cap_index)),
+            CellWidth::U16 => AnyTape::U16(Tape::new(memory_size, options.tape_mode, &options.initial_memory, options.cap_index)),
+            CellWidth::U32 => AnyTape::U32(Tape::new(memory_size, options.tape_mode, &options.initial_memory, options.cap_index)),
+        };
+        Self { tape, data_pointer: 0, pending_input: Vec::new() }
+    }
+    
+    /// Recreates the machine a [`Checkpoint`] was taken of, with cells truncated to `options.cell_width`
+    /// and a tape that follows `options.tape_mode` and `options.cap_index`.
+    pub fn restore(checkpoint: &Checkpoint, options: &RunOptions) -> Self {
+        fn tape<C: Cell>(checkpoint: &Checkpoint, options: &RunOptions) -> Tape<C> {
+            Tape::from_snapshot(checkpoint.cells.iter().map(|&cell| C::from_u32(cell)).collect(), checkpoint.origin, options.tape_mode, options.cap_index)
+        }
+        let tape = match options.cell_width {
+            CellWidth::U8 => AnyTape::U8(tape(checkpoint, options)),
+            CellWidth::U16 => AnyTape::U16(tape(checkpoint, options)),
+            CellWidth::U32 => AnyTape::U32(tape(checkpoint, options)),
+        };
+        Self { tape, data_pointer: checkpoint.data_pointer, pending_input: Vec::new() }
+    }
+    
+    /// Captures the whole tape and data pointer, so that running `program` can later continue from `program_counter`.
+    pub fn checkpoint(&self, program: &[BFInstruction], program_counter: usize) -> Checkpoint {
+        let (cells, origin) = self.tape().snapshot();
+        let cell_width = match self.tape {
+            AnyTape::U8(_) => CellWidth::U8,
+            AnyTape::U16(_) => CellWidth::U16,
+            AnyTape::U32(_) => CellWidth::U32,
+        };
+        Checkpoint {
+            program_hash: program_hash(program),
+            cell_width,
+            program_counter,
+            data_pointer: self.data_pointer,
+            origin,
+            cells,
+        }
+    }
+    
+    pub fn data_pointer(&self) -> usize {
+        self.data_pointer
+    }
+    
+    /// The value of the cell at `pointer`, or `None` if it's outside the tape.
+    pub fn cell(&self, pointer: usize) -> Option<u32> {
+        self.tape().cell(pointer)
+    }
+    
+    /// Runs `program` from its first instruction on the current tape and data pointer, which keep the state it stops in.
+    ///
+    /// The cell width, tape mode, index cap and initial memory in `options` are ignored; those were fixed by [`Machine::new`].
+    pub fn run(&mut self, program: &[BFInstruction], options: &RunOptions, input: &mut impl Read, output: &mut impl Write, hook: &mut impl Hook) -> ExecutionResult {
+        self.resume(program, 0, options, input, output, hook).result
+    }
+    
+    /// Like [`Machine::run`], starting at instruction `program_counter` and returning the state the run stopped in.
+    pub fn resume(&mut self, program: &[BFInstruction], program_counter: usize, options: &RunOptions, input: &mut impl Read, output: &mut impl Write, hook: &mut impl Hook) -> RunOutcome {
+        let (mut result, program_counter, steps) = self.run_from(program, program_counter, options, input, output, hook);
+        if let (Err(err), ExecutionResult::Ok) = (output.flush(), &result) {
+            result = ExecutionResult::IOError(err);
+        }
+        if options.ignore_broken_pipe && matches!(&result, ExecutionResult::IOError(err) if err.kind() == io::ErrorKind::BrokenPipe) {
+            result = ExecutionResult::Ok;
+        }
+        RunOutcome {
+            result,
+            memory: self.tape().memory(),
+            data_pointer: self.data_pointer,
+            program_counter,
+            current_cell: self.cell(self.data_pointer),
+            steps,
+        }
+    }
+    
+    /// Runs `program` from `program_counter`, returning the result, where it stopped and how many steps it took.
+    ///
+    /// Unlike [`Machine::resume`], this leaves `output` unflushed and doesn't copy the tape.
+    pub(crate) fn run_from(&mut self, program: &[BFInstruction], program_counter: usize, options: &RunOptions, input: &mut impl Read, output: &mut impl Write, hook: &mut impl Hook) -> (ExecutionResult, usize, u64) {
+        let input = &mut PendingInput { pending: &mut self.pending_input, input };
+        let output = &mut LimitedOutput { output, remaining: options.max_output, exceeded: false };
+        let (result, program_counter, steps) = match &mut self.tape {
+            AnyTape::U8(tape) => execute(program, program_counter, tape, &mut self.data_pointer, options, input, output, hook),
+            AnyTape::U16(tape) => execute(program, program_counter, tape, &mut self.data_pointer, options, input, output, hook),
+            AnyTape::U32(tape) => execute(program, program_counter, tape, &mut self.data_pointer, options, input, output, hook),
+        };
+        match result {
+            ExecutionResult::IOError(_) if output.exceeded => (ExecutionResult::OutputLimitExceeded, program_counter, steps),
+            result => (result, program_counter, steps),
+        }
+    }
+    
+    fn tape(&self) -> &dyn TapeView {
+        match &self.tape {
+            AnyTape::U8(tape) => tape,
+            AnyTape::U16(tape) => tape,
+            AnyTape::U32(tape) => tape,
+        }
+    }
+}
+
+/// Input with bytes read too far put back in front of it.
+struct PendingInput<'a, R> {
+    pending: &'a mut Vec<u8>,
+    input: &'a mut R,
+}
+
+impl<R: Read> PendingInput<'_, R> {
+    fn unread(&mut self, bytes: &[u8]) {
+        self.pending.splice(0..0, bytes.iter().copied());
+    }
+}
+
+impl<R: Read> Read for PendingInput<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            return self.input.read(buf);
+        }
+        let len = self.pending.len().min(buf.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+/// Output that fails once `remaining` bytes have been written, setting `exceeded`.
+struct LimitedOutput<'a, W> {
+    output: &'a mut W,
+    remaining: Option<u64>,
+    exceeded: bool,
+}
+
+impl<W: Write> Write for LimitedOutput<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(remaining) = self.remaining else {
+            return self.output.write(buf);
+        };
+        if remaining == 0 && !buf.is_empty() {
+            self.exceeded = true;
+            return Err(io::Error::other("output limit exceeded"));
+        }
+        
+        let written = self.output.write(&buf[..buf.len().min(remaining.try_into().unwrap_or(usize::MAX))])?;
+        self.remaining = Some(remaining - written as u64);
+        Ok(written)
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+fn write_cell<C: Cell>(output: &mut impl Write, cell: C, options: &RunOptions) -> io::Result<()> {
+    if let Some(numeric_io) = options.numeric_io {
+        return match numeric_io.signed {
+            true => writeln!(output, "{}", signed_delta::<C>(cell.to_u32())),
+            false => writeln!(output, "{}", cell.to_u32()),
+        };
+    }
+    
+    if options.ascii_mask {
+        return output.write_all(&[cell.to_u32() as u8 & 0x7F]);
+    }
+    if options.crlf && cell.to_u32() == u32::from(b'\n') {
+        return output.write_all(b"\r\n");
+    }
+    let Some(utf8_output) = options.utf8_output else {
+        return output.write_all(&[cell.to_u32() as u8]);
+    };
+    
+    let c = match char::from_u32(cell.to_u32()) {
+        Some(c) => c,
+        None if utf8_output.replace_invalid => char::REPLACEMENT_CHARACTER,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData, "cell isn't a Unicode scalar value")),
+    };
+    output.write_all(c.encode_utf8(&mut [0; 4]).as_bytes())
+}
+
+/// Writes `cell` `count` times, in one write when it's a plain byte.
+fn write_cells<C: Cell>(output: &mut impl Write, cell: C, count: usize, options: &RunOptions) -> io::Result<()> {
+    let plain = options.numeric_io.is_none() && options.utf8_output.is_none() && !options.ascii_mask && !(options.crlf && cell.to_u32() == u32::from(b'\n'));
+    match plain {
+        true => output.write_all(&vec![cell.to_u32() as u8; count]),
+        false => (0..count).try_for_each(|_| write_cell(output, cell, options)),
+    }
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut read_byte = [0; 1];
+    match input.read(&mut read_byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(read_byte[0])),
+    }
+}
+
+/// Reads into `cell` the way `Input` does, flushing `output` first so prompts show up before the program blocks on input.
+fn input_cell<C: Cell>(input: &mut impl Read, output: &mut impl Write, cell: &mut C, options: &RunOptions) -> io::Result<()> {
+    output.flush()?;
+    match read_cell(input, options)? {
+        None => match options.eof_behavior {
+            EofBehavior::Zero => *cell = C::default(),
+            EofBehavior::Unchanged => {}
+            EofBehavior::NegativeOne => *cell = C::MAX,
+        }
+        Some(value) => *cell = C::from_u32(value),
+    }
+    Ok(())
+}
+
+/// Runs some iterations of `EchoInput` on a nonzero `cell`, returning whether it reached zero.
+///
+/// With byte I/O and zero at end of input, this copies whatever one read of `input` returns at once, like `cat`,
+/// and puts back anything after a zero byte. Otherwise it runs a single iteration.
+fn echo_input<C: Cell>(input: &mut PendingInput<'_, impl Read>, output: &mut impl Write, cell: &mut C, options: &RunOptions) -> io::Result<bool> {
+    let plain = options.numeric_io.is_none() && options.utf8_output.is_none() && !options.crlf && !options.ascii_mask && options.eof_behavior == EofBehavior::Zero;
+    if !plain {
+        input_cell(input, output, cell, options)?;
+        write_cell(output, *cell, options)?;
+        return Ok(*cell == C::default());
+    }
+    
+    output.flush()?;
+    let mut buf = [0; 8192];
+    let len = input.read(&mut buf)?;
+    let (copied, done) = match buf[..len].iter().position(|&byte| byte == 0) {
+        // End of input reads as a zero, which is written like any other.
+        _ if len == 0 => (&[0][..], true),
+        Some(zero) => {
+            input.unread(&buf[zero + 1..len]);
+            (&buf[..=zero], true)
+        }
+        None => (&buf[..len], false),
+    };
+    output.write_all(copied)?;
+    *cell = C::from_u32(copied[copied.len() - 1].into());
+    Ok(done)
+}
+
+/// Returns `None` at end of input.
+fn read_cell(input: &mut impl Read, options: &RunOptions) -> io::Result<Option<u32>> {
+    let Some(numeric_io) = options.numeric_io else {
+        return Ok(read_byte(input)?.map(u32::from));
+    };
+    
+    let mut line = Vec::new();
+    loop {
+        match read_byte(input)? {
+            None if line.is_empty() => return Ok(None),
+            None | Some(b'\n') => break,
+            Some(byte) => line.push(byte),
+        }
+    }
+    
+    // Parsing as i64 and truncating wraps the number into the cell.
+    match std::str::from_utf8(&line).ok().and_then(|line| line.trim().parse::<i64>().ok()) {
+        Some(value) => Ok(Some(value as u32)),
+        None if numeric_io.malformed_as_zero => Ok(Some(0)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "malformed numeric input")),
+    }
+}
+
+/// Reads an `Add` operand as a signed change, so that `-` is `-1` rather than the maximum cell value.
+fn signed_delta<C: Cell>(val: u32) -> i64 {
+    let max = C::MAX.to_u32() as i64;
+    match val as i64 {
+        val if val > max / 2 => val - max - 1,
+        val => val,
+    }
+}
+
+/// Adds `times` times `val` to `cell`, or returns `None` if that overflows and `overflow` traps.
+fn add_to_cell<C: Cell>(cell: C, val: u32, times: C, overflow: CellOverflow) -> Option<C> {
+    match overflow {
+        CellOverflow::Wrap => Some(cell.wrapping_add(times.wrapping_mul(C::from_u32(val)))),
+        CellOverflow::Trap => cell.checked_add_signed(signed_delta::<C>(val).checked_mul(times.to_u32() as i64)?),
+    }
+}
+
+/// The error for the instruction at `program_counter` failing to access the cell at `pointer`.
+///
+/// Pointers wrap, so one that went left of the first cell is a huge one, and reads as negative.
+fn access_error(program_counter: usize, pointer: usize) -> ExecutionResult {
+    match (pointer as isize) < 0 {
+        true => ExecutionResult::MemoryUnderflow(program_counter),
+        false => ExecutionResult::MemoryAccessError(program_counter),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn execute<C: Cell>(program: &[BFInstruction], start: usize, tape: &mut Tape<C>, pointer: &mut usize, options: &RunOptions, input: &mut PendingInput<'_, impl Read>, output: &mut impl Write, hook: &mut impl Hook) -> (ExecutionResult, usize, u64) {
+    let mut program_counter = start;
+    let mut data_pointer = *pointer;
+    let max_steps = options.max_steps.unwrap_or(u64::MAX);
+    let mut steps = 0;
+    let deadline = options.timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    let result = 'run: {
+        while program_counter < program.len() {
+            if steps == max_steps {
+                break 'run ExecutionResult::StepLimitExceeded;
+            }
+            if steps % TIMEOUT_CHECK_INTERVAL == 0 {
+                if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    break 'run ExecutionResult::Timeout;
+                }
+                if options.interrupt.is_some_and(|interrupt| interrupt.load(Ordering::Relaxed)) {
+                    break 'run ExecutionResult::Interrupted;
+                }
+            }
+            steps += 1;
+            
+            let state = MachineState { program, program_counter, data_pointer, tape };
+            if let ControlFlow::Break(result) = hook.before_instruction(&state) {
+                break 'run result;
+            }
+            
+            match program[program_counter] {
+                BFInstruction::Output | BFInstruction::OutputN(_) | BFInstruction::Input if options.no_io => break 'run ExecutionResult::IoForbidden(program_counter),
+                BFInstruction::Add(val) => {
+                    let Some(current_cell) = tape.get_mut(data_pointer) else {
+                        break 'run access_error(program_counter, data_pointer);
+                    };
+                    
+                    match add_to_cell(*current_cell, val, C::from_u32(1), options.cell_overflow) {
+                        Some(value) => *current_cell = value,
+                        None => break 'run ExecutionResult::CellOverflow(program_counter),
+                    }
+                }
+                BFInstruction::IncrementPointer(by) => data_pointer = tape.normalize(data_pointer.wrapping_add(by)),
+                BFInstruction::Output => {
+                    let Some(current_cell) = tape.get(data_pointer) else {
+                        break 'run access_error(program_counter, data_pointer);
+                    };
+                    
+                    if let Err(err) = write_cell(output, current_cell, options) {
+                        break 'run ExecutionResult::IOError(err);
+                    }
+                }
+                BFInstruction::OutputN(count) => {
+                    let Some(current_cell) = tape.get(data_pointer) else {
+                        break 'run access_error(program_counter, data_pointer);
+                    };
+                    
+                    if let Err(err) = write_cells(output, current_cell, count, options) {
+                        break 'run ExecutionResult::IOError(err);
+                    }
+                }
+                BFInstruction::Input => {
+                    let Some(current_cell) = tape.get_mut(data_pointer) else {
+                        break 'run access_error(program_counter, data_pointer);
+                    };
+                    
+                    if let Err(err) = input_cell(input, output, current_cell, options) {
+                        break 'run ExecutionResult::IOError(err);
+                    }
+                }
+                BFInstruction::LoopStart(idx) => {
+                    let Some(current_cell) = tape.get(data_pointer) else {
+                        break 'run access_error(program_counter, data_pointer);
+                    };
+                    
+                    if current_cell == C::default() {
+                        program_counter = idx;
+                    }
+                }
+                BFInstruction::LoopEnd(idx) => {
+                    let Some(current_cell) = tape.get(data_pointer) else {
+                        break 'run access_error(program_counter, data_pointer);
+                    };
+                    
+                    if current_cell != C::default() {
+                        program_counter = idx;
+                    }
+                }
+                BFInstruction::ClearCell => {
+                    let Some(current_cell) = tape.get_mut(data_pointer) else {
+                        break 'run access_error(program_counter, data_pointer);
+                    };
+                    
+                    *current_cell = C::default();
+                }
+                BFInstruction::SetCell(val) => {
+                    let Some(current_cell) = tape.get_mut(data_pointer) else {
+                        break 'run access_error(program_counter, data_pointer);
+                    };
+                    
+                    *current_cell = C::from_u32(val);
+                }
+                BFInstruction::MulAdd(ref targets) => {
+                    let Some(current_cell) = tape.get(data_pointer) else {
+                        break 'run access_error(program_counter, data_pointer);
+                    };
+                    
+                    if current_cell != C::default() {
+                        for &(offset, multiplier) in targets.iter() {
+                            let Some(target) = tape.get_mut(data_pointer.wrapping_add_signed(offset)) else {
+                                break 'run access_error(program_counter, data_pointer.wrapping_add_signed(offset));
+                            };
+                            
+                            match add_to_cell(*target, multiplier, current_cell, options.cell_overflow) {
+                                Some(value) => *target = value,
+                                None => break 'run ExecutionResult::CellOverflow(program_counter),
+                            }
+                        }
+                        if let Some(current_cell) = tape.get_mut(data_pointer) {
+                            *current_cell = C::default();
+                        }
+                    }
+                }
+                BFInstruction::AddAtOffset { offset, value } => {
+                    let Some(target) = tape.get_mut(data_pointer.wrapping_add_signed(offset)) else {
+                        break 'run access_error(program_counter, data_pointer.wrapping_add_signed(offset));
+                    };
+                    
+                    match add_to_cell(*target, value, C::from_u32(1), options.cell_overflow) {
+                        Some(value) => *target = value,
+                        None => break 'run ExecutionResult::CellOverflow(program_counter),
+                    }
+                }
+                BFInstruction::ClearCellAtOffset(offset) => {
+                    let Some(target) = tape.get_mut(data_pointer.wrapping_add_signed(offset)) else {
+                        break 'run access_error(program_counter, data_pointer.wrapping_add_signed(offset));
+                    };
+                    
+                    *target = C::default();
+                }
+                BFInstruction::SetCellAtOffset { offset, value } => {
+                    let Some(target) = tape.get_mut(data_pointer.wrapping_add_signed(offset)) else {
+                        break 'run access_error(program_counter, data_pointer.wrapping_add_signed(offset));
+                    };
+                    
+                    *target = C::from_u32(value);
+                }
+                BFInstruction::Debug => {}
+                BFInstruction::EchoInput => {
+                    let Some(current_cell) = tape.get_mut(data_pointer) else {
+                        break 'run access_error(program_counter, data_pointer);
+                    };
+                    
+                    if *current_cell != C::default() {
+                        // Like `[,.]`, it only does I/O if the loop is entered.
+                        if options.no_io {
+                            break 'run ExecutionResult::IoForbidden(program_counter);
+                        }
+                        match echo_input(input, output, current_cell, options) {
+                            Ok(true) => {}
+                            // Run it again rather than loop here, so step limits, timeouts and hooks still apply.
+                            Ok(false) => program_counter = program_counter.wrapping_sub(1),
+                            Err(err) => break 'run ExecutionResult::IOError(err),
+                        }
+                    }
+                }
+                BFInstruction::ScanRight(step) => match tape.scan_right(data_pointer, step) {
+                    Some(pointer) => {
+                        data_pointer = pointer;
+                        // Only a circular tape without a zero cell in reach stops a scan on a nonzero cell. That loop
+                        // never ends, so run the scan again rather than leave it; step limits and hooks still apply.
+                        if tape.get(pointer) != Some(C::default()) {
+                            program_counter = program_counter.wrapping_sub(1);
+                        }
+                    }
+                    None => break 'run access_error(program_counter, data_pointer)
+                }
+                BFInstruction::ScanLeft(step) => match tape.scan_left(data_pointer, step) {
+                    Some(pointer) => {
+                        data_pointer = pointer;
+                        if tape.get(pointer) != Some(C::default()) {
+                            program_counter = program_counter.wrapping_sub(1);
+                        }
+                    }
+                    // Starting on a cell, the scan can only have run off the left end.
+                    None if tape.get(data_pointer).is_some() => break 'run ExecutionResult::MemoryUnderflow(program_counter),
+                    None => break 'run access_error(program_counter, data_pointer)
+                }
+            }
+            program_counter += 1;
+            
+            let state = MachineState { program, program_counter, data_pointer, tape };
+            if let ControlFlow::Break(result) = hook.after_instruction(&state) {
+                break 'run result;
+            }
+        }
+        ExecutionResult::Ok
+    };
+    
+    hook.after_execution(&MachineState { program, program_counter, data_pointer, tape }, &result);
+    *pointer = data_pointer;
+    (result, program_counter, steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ParseOptions, parse_data, parse_data_with_options};
+    
+    #[test]
+    fn invalid_memory_access() {
+        assert_eq!(run_program(&parse_data(b">+").unwrap(), 1), ExecutionResult::MemoryAccessError(1));
+        assert_eq!(run_program(&parse_data(b"<+").unwrap(), 1), ExecutionResult::MemoryUnderflow(1));
+        assert_eq!(run_program(&parse_data(b"<>+").unwrap(), 1), ExecutionResult::Ok);
+        assert_eq!(run_program(&parse_data(b">[]").unwrap(), 1), ExecutionResult::MemoryAccessError(1));
+        assert_eq!(run_program(&parse_data(b"+[->>+<<]").unwrap(), 2), ExecutionResult::MemoryAccessError(1));
+        assert_eq!(run_program(&parse_data(b"+[-<+>]").unwrap(), 2), ExecutionResult::MemoryUnderflow(1));
+    }
+    
+    #[test]
+    fn fused_pointer_moves() {
+        assert_eq!(run_program(&parse_data(b">>><<+").unwrap(), 2), ExecutionResult::Ok);
+        assert_eq!(run_program(&parse_data(b">>><<+").unwrap(), 1), ExecutionResult::MemoryAccessError(1));
+        assert_eq!(run_program(&parse_data(b">><<<+").unwrap(), 2), ExecutionResult::MemoryUnderflow(1));
+        assert_eq!(run_program(&parse_data(b">><<<+>+").unwrap(), 2), ExecutionResult::MemoryUnderflow(1));
+        assert_eq!(run_program(&parse_data(b">><<<>+").unwrap(), 2), ExecutionResult::Ok);
+    }
+    
+    #[test]
+    fn hello_world() {
+        let program = parse_data(b"++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.").unwrap();
+        let mut output = Vec::new();
+        assert_eq!(run_program_io(&program, 7, &mut io::empty(), &mut output), ExecutionResult::Ok);
+        assert_eq!(output, b"Hello World!\n");
+    }
+    
+    #[test]
+    fn clear_cell() {
+        let program = parse_data(b"+++++[-].>-[+].").unwrap();
+        assert_eq!(program.len(), 5);
+        let mut output = Vec::new();
+        assert_eq!(run_program_io(&program, 2, &mut io::empty(), &mut output), ExecutionResult::Ok);
+        assert_eq!(output, [0, 0]);
+        assert_eq!(run_program(&parse_data(b">[-]").unwrap(), 1), ExecutionResult::MemoryAccessError(1));
+    }
+    
+    #[test]
+    fn eof_behavior() {
+        let program = parse_data(b"+,.").unwrap();
+        for (eof_behavior, expected) in [(EofBehavior::Zero, 0), (EofBehavior::Unchanged, 1), (EofBehavior::NegativeOne, 255)] {
+            let mut output = Vec::new();
+            let options = RunOptions { eof_behavior, ..RunOptions::default() };
+            assert_eq!(run_program_with_options(&program, 1, &options, &mut io::empty(), &mut output), ExecutionResult::Ok);
+            assert_eq!(output, [expected]);
+        }
+    }
+    
+    #[test]
+    fn multiply_loops() {
+        let run = |source: &[u8], memory_size| {
+            let mut output = Vec::new();
+            let result = run_program_io(&parse_data(source).unwrap(), memory_size, &mut io::empty(), &mut output);
+            (result, output)
+        };
+        assert_eq!(run(b"+++++[->+<]>.<.", 2), (ExecutionResult::Ok, vec![5, 0]));
+        assert_eq!(run(b"++++[->+++<]>.", 2), (ExecutionResult::Ok, vec![12]));
+        assert_eq!(run(b"[->+++<]>.", 2), (ExecutionResult::Ok, vec![0]));
+        assert_eq!(run(b"[->+<]", 1), (ExecutionResult::Ok, vec![]));
+        assert_eq!(run(b"+[->+<]", 1), (ExecutionResult::MemoryAccessError(1), vec![]));
+        assert_eq!(run(b"++++++++++++++++[->++++++++++++++++<]>.", 2), (ExecutionResult::Ok, vec![0]));
+    }
+    
+    /// One instruction per command, so loops run step by step.
+    fn unoptimized(source: &[u8]) -> Vec<BFInstruction> {
+        parse_data_with_options(source, &ParseOptions { unoptimized: true, ..ParseOptions::default() }).unwrap()
+    }
+    
+    #[test]
+    fn multiply_loops_match_naive() {
+        let sources: [&[u8]; 11] = [
+            b">+++++<++++[->+>++>+++<<<]>.>.>.",
+            b"+++++++[->>++++++++++<<]>>.",
+            b">>>+++[-<<+<+++>>>]<.<.",
+            b"+++++++++++++++++++++++++++++++++++++++++++++++++++++++[->+++>+++++<<]>.>.",
+            b">+[-<+++++>>+<]<.>>.",
+            b"++[->+<]++[->>++++<<]>.>.",
+            b"+[+++>+<]>.",
+            b"++++[--->++<]>.",
+            b"+++++[+++++++>+>---<<]>.>.",
+            b"++[>+++<-----]>.",
+            b">>+++++++<<++++++++++[>>[--->+<]<<-]>>>.",
+        ];
+        for source in sources {
+            let optimized = parse_data(source).unwrap();
+            assert!(optimized.iter().any(|instruction| matches!(instruction, BFInstruction::MulAdd(_))));
+            
+            let mut expected = Vec::new();
+            let mut output = Vec::new();
+            assert_eq!(run_program_io(&unoptimized(source), 4, &mut io::empty(), &mut expected), ExecutionResult::Ok);
+            assert_eq!(run_program_io(&optimized, 4, &mut io::empty(), &mut output), ExecutionResult::Ok);
+            assert_eq!(output, expected);
+        }
+    }
+    
+    #[test]
+    fn set_cell() {
+        let sources: [&[u8]; 4] = [b"+++[-]+++++.", b"++[+]---.", b">+[>++[-]+++<-]>.", b"[-]+.+.-.[-]-."];
+        for source in sources {
+            let optimized = parse_data(source).unwrap();
+            assert!(optimized.iter().any(|instruction| matches!(instruction, BFInstruction::SetCell(_))));
+            
+            let mut expected = Vec::new();
+            let mut output = Vec::new();
+            assert_eq!(run_program_io(&unoptimized(source), 3, &mut io::empty(), &mut expected), ExecutionResult::Ok);
+            assert_eq!(run_program_io(&optimized, 3, &mut io::empty(), &mut output), ExecutionResult::Ok);
+            assert_eq!(output, expected);
+        }
+        assert_eq!(run_program(&parse_data(b"<[-]+").unwrap(), 1), ExecutionResult::MemoryUnderflow(1));
+    }
+    
+    #[test]
+    fn wide_cells() {
+        let source = [&[b'+'; 256][..], b"[>+<[-]]>.<-."].concat();
+        let run = |cell_width| {
+            let program = parse_data_with_options(&source, &ParseOptions { cell_width, ..ParseOptions::default() }).unwrap();
+            let mut output = Vec::new();
+            let options = RunOptions { cell_width, ..RunOptions::default() };
+            assert_eq!(run_program_with_options(&program, 2, &options, &mut io::empty(), &mut output), ExecutionResult::Ok);
+            output
+        };
+        assert_eq!(run(CellWidth::U8), [0, 255]);
+        assert_eq!(run(CellWidth::U16), [1, 255]);
+        assert_eq!(run(CellWidth::U32), [1, 255]);
+    }
+    
+    #[test]
+    fn scan_loops() {
+        let run = |source: &[u8], memory_size, tape_mode| {
+            let mut output = Vec::new();
+            let options = RunOptions { tape_mode, ..RunOptions::default() };
+            let result = run_program_with_options(&parse_data(source).unwrap(), memory_size, &options, &mut io::empty(), &mut output);
+            (result, output)
+        };
+        assert_eq!(run(b"+>+>+<<[>]-.", 5, TapeMode::Fixed), (ExecutionResult::Ok, vec![255]));
+        assert_eq!(run(b"+>>+>>+>+<<<<<[>>]-.", 8, TapeMode::Fixed), (ExecutionResult::Ok, vec![255]));
+        assert_eq!(run(b"+>>+>+[<]-.", 4, TapeMode::Fixed), (ExecutionResult::Ok, vec![255]));
+        assert_eq!(run(b"+>+[>]", 2, TapeMode::Fixed), (ExecutionResult::MemoryAccessError(3), vec![]));
+        assert_eq!(run(b"+>+[<]", 2, TapeMode::Fixed), (ExecutionResult::MemoryUnderflow(3), vec![]));
+        assert_eq!(run(b"+>>+<<[>>]+.", 3, TapeMode::Growing), (ExecutionResult::Ok, vec![1]));
+        assert_eq!(run(b"+[<]", 2, TapeMode::Growing), (ExecutionResult::MemoryUnderflow(1), vec![]));
+        assert_eq!(run(b">+>+[<<]+.>>.", 3, TapeMode::Bidirectional), (ExecutionResult::Ok, vec![1, 1]));
+    }
+    
+    #[test]
+    fn growing_tape() {
+        let program = parse_data(&[&[b'>'; 100][..], b"+.[<]"].concat()).unwrap();
+        let options = RunOptions { tape_mode: TapeMode::Growing, ..RunOptions::default() };
+        let mut output = Vec::new();
+        assert_eq!(run_program_with_options(&program, 1, &options, &mut io::empty(), &mut output), ExecutionResult::Ok);
+        assert_eq!(output, [1]);
+        assert_eq!(run_program_io(&program, 1, &mut io::empty(), &mut Vec::new()), ExecutionResult::MemoryAccessError(1));
+        
+        let program = parse_data(b"<+").unwrap();
+        assert_eq!(run_program_with_options(&program, 1, &options, &mut io::empty(), &mut Vec::new()), ExecutionResult::MemoryUnderflow(1));
+    }
+    
+    #[test]
+    fn bidirectional_tape() {
+        let program = parse_data(b"<<+++>>+<<.>>.<<<[-]>.").unwrap();
+        let options = RunOptions { tape_mode: TapeMode::Bidirectional, ..RunOptions::default() };
+        let mut output = Vec::new();
+        assert_eq!(run_program_with_options(&program, 1, &options, &mut io::empty(), &mut output), ExecutionResult::Ok);
+        assert_eq!(output, [3, 1, 3]);
+        assert_eq!(run_program_io(&program, 1, &mut io::empty(), &mut Vec::new()), ExecutionResult::MemoryUnderflow(1));
+    }
+    
+    #[test]
+    fn circular_tape() {
+        let run = |source: &[u8], max_steps| {
+            let options = RunOptions { tape_mode: TapeMode::Circular, max_steps, ..RunOptions::default() };
+            let mut output = Vec::new();
+            (run_program_with_options(&parse_data(source).unwrap(), 3, &options, &mut io::empty(), &mut output), output)
+        };
+        assert_eq!(run(b"+>>>+.", None), (ExecutionResult::Ok, vec![2]));
+        assert_eq!(run(b"+<+.>>++.", None), (ExecutionResult::Ok, vec![1, 2]));
+        assert_eq!(run(b">>>>>>>+<<<<<<.>.", None), (ExecutionResult::Ok, vec![1, 0]));
+        assert_eq!(run(b"++>+[>>]+<.", None), (ExecutionResult::Ok, vec![1]));
+        assert_eq!(run(b"+>+[<<]<.", None), (ExecutionResult::Ok, vec![1]));
+        assert_eq!(run(b"+>+>+[>]", Some(100)), (ExecutionResult::StepLimitExceeded, vec![]));
+        assert_eq!(run(b"+[->>>>+<<<<]>.", None), (ExecutionResult::Ok, vec![1]));
+    }
+    
+    #[test]
+    fn cap_index() {
+        let run = |source: &[u8], tape_mode| {
+            let options = RunOptions { tape_mode, cap_index: Some(3), ..RunOptions::default() };
+            let mut output = Vec::new();
+            (run_program_with_options(&parse_data(source).unwrap(), 10, &options, &mut io::empty(), &mut output), output)
+        };
+        assert_eq!(run(b">>+.", TapeMode::Fixed), (ExecutionResult::Ok, vec![1]));
+        assert_eq!(run(b">>>+", TapeMode::Fixed), (ExecutionResult::MemoryAccessError(1), vec![]));
+        assert_eq!(run(b"+>+>+[>]", TapeMode::Fixed), (ExecutionResult::MemoryAccessError(5), vec![]));
+        assert_eq!(run(b"+[->>>+<<<]", TapeMode::Fixed), (ExecutionResult::MemoryAccessError(1), vec![]));
+        // Tapes that grow stop growing at the cap, and circular ones still wrap at their size.
+        assert_eq!(run(b"+>+>+[>]", TapeMode::Growing), (ExecutionResult::MemoryAccessError(5), vec![]));
+        assert_eq!(run(b"+>+[>]<.", TapeMode::Growing), (ExecutionResult::Ok, vec![1]));
+        assert_eq!(run(b"<<<<<<<+", TapeMode::Circular), (ExecutionResult::MemoryAccessError(1), vec![]));
+        assert_eq!(run(b"<<<<<<<<+.", TapeMode::Circular), (ExecutionResult::Ok, vec![1]));
+        assert_eq!(run(b"+>+>+[>]", TapeMode::Circular), (ExecutionResult::MemoryAccessError(5), vec![]));
+    }
+    
+    #[test]
+    fn utf8_output() {
+        let run = |source: &[u8], replace_invalid| {
+            let options = RunOptions { cell_width: CellWidth::U32, utf8_output: Some(Utf8Output { replace_invalid }), ..RunOptions::default() };
+            let program = parse_data_with_options(source, &ParseOptions { cell_width: CellWidth::U32, ..ParseOptions::default() }).unwrap();
+            let mut output = Vec::new();
+            (run_program_with_options(&program, 2, &options, &mut io::empty(), &mut output), output)
+        };
+        // 65 = 'A', 233 = 'é', 8364 = '€', and 55296 is the first surrogate.
+        let set = |value: usize| [&b"+".repeat(value)[..], b"."].concat();
+        assert_eq!(run(&set(65), false), (ExecutionResult::Ok, "A".as_bytes().to_vec()));
+        assert_eq!(run(&[set(233), b"[-]".to_vec(), set(8364)].concat(), false), (ExecutionResult::Ok, "é€".as_bytes().to_vec()));
+        assert_eq!(run(&set(55296), false), (ExecutionResult::IOError(io::ErrorKind::InvalidData.into()), vec![]));
+        assert_eq!(run(&set(55296), true), (ExecutionResult::Ok, "\u{FFFD}".as_bytes().to_vec()));
+    }
+    
+    #[test]
+    fn numeric_io() {
+        let program = parse_data(b",.,.,.").unwrap();
+        let options = RunOptions { numeric_io: Some(NumericIo::default()), ..RunOptions::default() };
+        let mut output = Vec::new();
+        assert_eq!(run_program_with_options(&program, 1, &options, &mut &b"42\n 300 \n-1"[..], &mut output), ExecutionResult::Ok);
+        assert_eq!(output, b"42\n44\n255\n");
+        
+        let mut output = Vec::new();
+        assert_eq!(run_program_with_options(&program, 1, &options, &mut &b"7\nseven\n"[..], &mut output), ExecutionResult::IOError(io::ErrorKind::InvalidData.into()));
+        assert_eq!(output, b"7\n");
+        
+        let options = RunOptions { numeric_io: Some(NumericIo { malformed_as_zero: true, ..NumericIo::default() }), ..RunOptions::default() };
+        let mut output = Vec::new();
+        assert_eq!(run_program_with_options(&program, 1, &options, &mut &b"7\nseven\n"[..], &mut output), ExecutionResult::Ok);
+        assert_eq!(output, b"7\n0\n0\n");
+    }
+    
+    #[test]
+    fn output_runs() {
+        let run = |options: &RunOptions| {
+            let mut output = Vec::new();
+            let outcome = run_program_outcome(&parse_data(b"++++++++++...+++..").unwrap(), 1, options, &mut io::empty(), &mut output, &mut ());
+            (output, outcome.steps)
+        };
+        assert_eq!(run(&RunOptions::default()), (b"\n\n\n\r\r".to_vec(), 4));
+        assert_eq!(run(&RunOptions { crlf: true, ..RunOptions::default() }).0, b"\r\n\r\n\r\n\r\r");
+        assert_eq!(run(&RunOptions { numeric_io: Some(NumericIo::default()), ..RunOptions::default() }).0, b"10\n10\n10\n13\n13\n");
+    }
+    
+    #[test]
+    fn echo_input() {
+        let run = |source: &[u8], input: &[u8], options: &RunOptions| {
+            let mut output = Vec::new();
+            let outcome = run_program_outcome(&parse_data(source).unwrap(), 2, options, &mut &input[..], &mut output, &mut ());
+            (outcome.result, output)
+        };
+        
+        let file: Vec<u8> = (0..100_000).map(|idx| (idx % 255 + 1) as u8).collect();
+        let mut expected = file.clone();
+        expected.push(0);
+        assert_eq!(run(b"+[,.]", &file, &RunOptions::default()), (ExecutionResult::Ok, expected));
+        
+        // Bytes read past a zero byte are still there for the next `,`.
+        assert_eq!(run(b"+[,.],.>,.", b"ab\0cd", &RunOptions::default()), (ExecutionResult::Ok, b"ab\0cd".to_vec()));
+        
+        let options = RunOptions { eof_behavior: EofBehavior::Unchanged, max_steps: Some(5), ..RunOptions::default() };
+        assert_eq!(run(b"+[,.]", b"a", &options), (ExecutionResult::StepLimitExceeded, b"aaaa".to_vec()));
+        let options = RunOptions { numeric_io: Some(NumericIo::default()), ..RunOptions::default() };
+        assert_eq!(run(b"+[,.]", b"7\n0\n", &options), (ExecutionResult::Ok, b"7\n0\n".to_vec()));
+    }
+    
+    #[test]
+    fn broken_pipe() {
+        struct Closed;
+        impl Write for Closed {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+            
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        
+        let program = parse_data(b"+[.]").unwrap();
+        let options = RunOptions { ignore_broken_pipe: true, ..RunOptions::default() };
+        assert_eq!(run_program_with_options(&program, 1, &RunOptions::default(), &mut io::empty(), &mut Closed), ExecutionResult::IOError(io::ErrorKind::BrokenPipe.into()));
+        let outcome = run_program_outcome(&program, 1, &options, &mut io::empty(), &mut Closed, &mut ());
+        assert_eq!((outcome.result, outcome.steps), (ExecutionResult::Ok, 3));
+        
+        // Other errors still fail.
+        let options = RunOptions { numeric_io: Some(NumericIo::default()), ..options };
+        assert_eq!(run_program_with_options(&parse_data(b",").unwrap(), 1, &options, &mut &b"x\n"[..], &mut Vec::new()), ExecutionResult::IOError(io::ErrorKind::InvalidData.into()));
+    }
+    
+    #[test]
+    fn crlf() {
+        let run = |options: &RunOptions| {
+            let mut output = Vec::new();
+            let outcome = run_program_outcome(&parse_data(b"++++++++++.+++.---.").unwrap(), 1, options, &mut io::empty(), &mut output, &mut ());
+            (output, outcome.steps)
+        };
+        assert_eq!(run(&RunOptions::default()), (b"\n\r\n".to_vec(), 6));
+        assert_eq!(run(&RunOptions { crlf: true, ..RunOptions::default() }), (b"\r\n\r\r\n".to_vec(), 6));
+        assert_eq!(run(&RunOptions { crlf: true, utf8_output: Some(Utf8Output::default()), ..RunOptions::default() }).0, b"\r\n\r\r\n");
+        assert_eq!(run(&RunOptions { crlf: true, numeric_io: Some(NumericIo::default()), ..RunOptions::default() }).0, b"10\n13\n10\n");
+    }
+    
+    #[test]
+    fn signed_numeric_io() {
+        let run = |source: &[u8], cell_width, input: &[u8]| {
+            let options = RunOptions { cell_width, numeric_io: Some(NumericIo { signed: true, ..NumericIo::default() }), ..RunOptions::default() };
+            let mut output = Vec::new();
+            assert_eq!(run_program_with_options(&parse_data(source).unwrap(), 1, &options, &mut &input[..], &mut output), ExecutionResult::Ok);
+            String::from_utf8(output).unwrap()
+        };
+        assert_eq!(run(b"-.", CellWidth::U8, b""), "-1\n");
+        assert_eq!(run(b",.,.,.,.", CellWidth::U8, b"127\n128\n-128\n200\n"), "127\n-128\n-128\n-56\n");
+        assert_eq!(run(b",.", CellWidth::U16, b"-300\n"), "-300\n");
+        assert_eq!(run(b",.", CellWidth::U32, b"2147483648\n"), "-2147483648\n");
+    }
+    
+    #[test]
+    fn step_limit() {
+        let run = |source: &[u8], max_steps| {
+            let options = RunOptions { max_steps: Some(max_steps), ..RunOptions::default() };
+            run_program_with_options(&parse_data(source).unwrap(), 2, &options, &mut io::empty(), &mut Vec::new())
+        };
+        assert_eq!(run(b"+[]", 1000), ExecutionResult::StepLimitExceeded);
+        assert_eq!(run(b"+++>-<", 4), ExecutionResult::Ok);
+        assert_eq!(run(b"+++>-<", 3), ExecutionResult::StepLimitExceeded);
+    }
+    
+    #[test]
+    fn output_limit() {
+        let run = |source: &[u8], options: &RunOptions| {
+            let mut output = Vec::new();
+            let result = run_program_with_options(&parse_data(source).unwrap(), 1, options, &mut io::empty(), &mut output);
+            (result, output)
+        };
+        let options = RunOptions { max_output: Some(5), ..RunOptions::default() };
+        assert_eq!(run(b"+[.]", &options), (ExecutionResult::OutputLimitExceeded, vec![1; 5]));
+        assert_eq!(run(b"+.....", &options), (ExecutionResult::Ok, vec![1; 5]));
+        assert_eq!(run(b"+......", &options), (ExecutionResult::OutputLimitExceeded, vec![1; 5]));
+        
+        // Expanded newlines count as the bytes they're written as.
+        let options = RunOptions { crlf: true, max_output: Some(3), ..RunOptions::default() };
+        assert_eq!(run(b"++++++++++..", &options), (ExecutionResult::OutputLimitExceeded, b"\r\n\r".to_vec()));
+    }
+    
+    #[test]
+    fn ascii_mask() {
+        let options = RunOptions { ascii_mask: true, ..RunOptions::default() };
+        let mut output = Vec::new();
+        let program = parse_data(b",.>+++[<.>-]+[,.]").unwrap();
+        assert_eq!(run_program_with_options(&program, 3, &options, &mut &b"\xc1\xff\x8a\0"[..], &mut output), ExecutionResult::Ok);
+        assert_eq!(output, b"AAAA\x7f\n\0");
+    }
+    
+    #[test]
+    fn no_io() {
+        let run = |source: &[u8]| {
+            let options = RunOptions { no_io: true, ..RunOptions::default() };
+            let mut output = Vec::new();
+            let result = run_program_with_options(&parse_data(source).unwrap(), 2, &options, &mut &b"x"[..], &mut output);
+            assert!(output.is_empty());
+            result
+        };
+        assert_eq!(run(b"++[->+<]"), ExecutionResult::Ok);
+        assert_eq!(run(b"+>+."), ExecutionResult::IoForbidden(3));
+        assert_eq!(run(b"+..."), ExecutionResult::IoForbidden(1));
+        assert_eq!(run(b">,"), ExecutionResult::IoForbidden(1));
+        // A copy loop that's never entered does no I/O.
+        assert_eq!(run(b"[,.]"), ExecutionResult::Ok);
+        assert_eq!(run(b"+[,.]"), ExecutionResult::IoForbidden(1));
+    }
+    
+    #[test]
+    fn offset_adds() {
+        let sources: [&[u8]; 5] = [b">+<.", b">>>++<<<.>>>.", b"++[>+++<-]>>>+<<<<>.>[>>+<<-]>>.", b">+++>++<<+[>[-]<-]>.", b"+++>>[-]--<<[>>.<<-]>>[-]+<<>>."];
+        for source in sources {
+            let program = parse_data(source).unwrap();
+            let mut fused = parse_data(source).unwrap();
+            crate::optimizer::fuse_offset_adds(&mut fused);
+            assert!(fused.len() < program.len());
+            
+            let mut expected = Vec::new();
+            let mut output = Vec::new();
+            assert_eq!(run_program_io(&fused, 4, &mut io::empty(), &mut output), run_program_io(&program, 4, &mut io::empty(), &mut expected));
+            assert_eq!(output, expected);
+        }
+        
+        let mut program = parse_data(b"<+>").unwrap();
+        crate::optimizer::fuse_offset_adds(&mut program);
+        assert_eq!(run_program(&program, 4), ExecutionResult::MemoryUnderflow(0));
+        let mut program = parse_data(b">>>>[-]<<<<").unwrap();
+        crate::optimizer::fuse_offset_adds(&mut program);
+        assert_eq!(run_program(&program, 4), ExecutionResult::MemoryAccessError(0));
+    }
+    
+    #[test]
+    fn outcome() {
+        let run = |source: &[u8], tape_mode| {
+            let options = RunOptions { tape_mode, ..RunOptions::default() };
+            run_program_outcome(&parse_data(source).unwrap(), 3, &options, &mut io::empty(), &mut Vec::new(), &mut ())
+        };
+        assert_eq!(run(b"+++", TapeMode::Fixed), RunOutcome { result: ExecutionResult::Ok, memory: vec![3, 0, 0], data_pointer: 0, program_counter: 1, current_cell: Some(3), steps: 1 });
+        assert_eq!(run(b"+>++>>-", TapeMode::Fixed), RunOutcome { result: ExecutionResult::MemoryAccessError(4), memory: vec![1, 2, 0], data_pointer: 3, program_counter: 4, current_cell: None, steps: 5 });
+        
+        let outcome = run(b"<+>>>>+", TapeMode::Bidirectional);
+        assert_eq!(outcome.memory[..4], [0, 0, 0, 1]);
+        assert_eq!(outcome.data_pointer, 3);
+        assert_eq!(run(b">++<<+", TapeMode::Bidirectional).current_cell, Some(1));
+    }
+    
+    #[test]
+    fn checksum() {
+        let run = |source: &[u8], memory_size| run_program_outcome(&parse_data(source).unwrap(), memory_size, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut ());
+        let outcome = run(b"++++++++[>++++++++<-]>+>++", 5);
+        assert_eq!(outcome.memory, [0, 65, 2, 0, 0]);
+        assert_eq!(outcome.checksum(), 0xb6b0_7f9f_02ba_0b56);
+        // Trailing zeros don't count, so the tape size doesn't matter.
+        assert_eq!(run(b"++++++++[>++++++++<-]>+>++", 300).checksum(), 0xb6b0_7f9f_02ba_0b56);
+        assert_eq!(run(b"+>+[-]<-", 4).checksum(), 0xcbf2_9ce4_8422_2325);
+    }
+    
+    #[test]
+    fn machine() {
+        let mut machine = Machine::new(3, &RunOptions::default());
+        let mut output = Vec::new();
+        let mut run = |source: &[u8]| machine.run(&parse_data(source).unwrap(), &RunOptions::default(), &mut io::empty(), &mut output, &mut ());
+        assert_eq!(run(b"+++>"), ExecutionResult::Ok);
+        assert_eq!(run(b"++<."), ExecutionResult::Ok);
+        assert_eq!(run(b">>>"), ExecutionResult::Ok);
+        assert_eq!(run(b"+"), ExecutionResult::MemoryAccessError(0));
+        assert_eq!(run(b"<<.>."), ExecutionResult::Ok);
+        assert_eq!(output, [3, 2, 0]);
+        assert_eq!(machine.data_pointer(), 2);
+        assert_eq!(machine.cell(1), Some(2));
+        assert_eq!(machine.cell(3), None);
+    }
+    
+    #[test]
+    fn initial_memory() {
+        let run = |initial_memory: &[u8], memory_size| {
+            let options = RunOptions { initial_memory: initial_memory.to_vec(), ..RunOptions::default() };
+            let mut output = Vec::new();
+            (run_program_with_options(&parse_data(b"[.>]>.").unwrap(), memory_size, &options, &mut io::empty(), &mut output), output)
+        };
+        assert_eq!(run(b"hi", 4), (ExecutionResult::Ok, b"hi\0".to_vec()));
+        assert_eq!(run(b"", 4), (ExecutionResult::Ok, vec![0]));
+        assert_eq!(run(b"hello", 2), (ExecutionResult::MemoryAccessError(3), b"he".to_vec()));
+    }
+    
+    #[test]
+    fn cell_overflow() {
+        let run = |source: &[u8], cell_overflow, cell_width| {
+            let options = RunOptions { cell_overflow, cell_width, ..RunOptions::default() };
+            let program = parse_data_with_options(source, &ParseOptions { cell_width, cell_overflow, ..ParseOptions::default() }).unwrap();
+            let mut output = Vec::new();
+            (run_program_with_options(&program, 2, &options, &mut io::empty(), &mut output), output)
+        };
+        let full = "+".repeat(255) + ".";
+        assert_eq!(run(format!("{full}+.").as_bytes(), CellOverflow::Wrap, CellWidth::U8), (ExecutionResult::Ok, vec![255, 0]));
+        assert_eq!(run(format!("{full}+.").as_bytes(), CellOverflow::Trap, CellWidth::U8), (ExecutionResult::CellOverflow(4), vec![255]));
+        assert_eq!(run(format!("{full}+.").as_bytes(), CellOverflow::Trap, CellWidth::U16), (ExecutionResult::Ok, vec![255, 0]));
+        assert_eq!(run(b"+-.-", CellOverflow::Trap, CellWidth::U8), (ExecutionResult::CellOverflow(3), vec![0]));
+        assert_eq!(run(b"[+]", CellOverflow::Trap, CellWidth::U8), (ExecutionResult::Ok, vec![]));
+        assert_eq!(run(b"+[+]", CellOverflow::Trap, CellWidth::U8), (ExecutionResult::CellOverflow(2), vec![]));
+        assert_eq!(run(b"++[->++<]>.", CellOverflow::Trap, CellWidth::U8), (ExecutionResult::Ok, vec![4]));
+        assert_eq!(run(b"++++++++++++++++[->++++++++++++++++<]", CellOverflow::Trap, CellWidth::U8), (ExecutionResult::CellOverflow(1), vec![]));
+        assert_eq!(run(b"++[->--<]", CellOverflow::Trap, CellWidth::U8), (ExecutionResult::CellOverflow(1), vec![]));
+    }
+    
+    #[test]
+    fn timeout() {
+        let run = |source: &[u8], timeout| {
+            let options = RunOptions { timeout: Some(timeout), ..RunOptions::default() };
+            run_program_with_options(&parse_data(source).unwrap(), 1, &options, &mut io::empty(), &mut Vec::new())
+        };
+        assert_eq!(run(b"+[]", Duration::from_millis(20)), ExecutionResult::Timeout);
+        assert_eq!(run(b"+[-]", Duration::ZERO), ExecutionResult::Timeout);
+        assert_eq!(run(b"+[->+<]", Duration::from_secs(60)), ExecutionResult::MemoryAccessError(1));
+    }
+    
+    #[test]
+    fn interrupt() {
+        static INTERRUPT: AtomicBool = AtomicBool::new(false);
+        let options = RunOptions { interrupt: Some(&INTERRUPT), ..RunOptions::default() };
+        let run = || run_program_with_options(&parse_data(b"+[]").unwrap(), 1, &options, &mut io::empty(), &mut Vec::new());
+        let interrupter = std::thread::spawn(|| {
+            std::thread::sleep(Duration::from_millis(10));
+            INTERRUPT.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(run(), ExecutionResult::Interrupted);
+        interrupter.join().unwrap();
+        assert_eq!(run(), ExecutionResult::Interrupted);
+    }
+    
+    #[derive(Default)]
+    struct CountingWriter {
+        bytes: Vec<u8>,
+        flushes: usize,
+    }
+    
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+    
+    #[test]
+    fn output_flushes() {
+        let mut output = CountingWriter::default();
+        let program = parse_data(b"-[->.<]").unwrap();
+        assert_eq!(run_program_io(&program, 2, &mut io::empty(), &mut output), ExecutionResult::Ok);
+        assert_eq!(output.bytes.len(), 255);
+        assert_eq!(output.flushes, 1);
+        
+        let mut output = CountingWriter::default();
+        assert_eq!(run_program_io(&parse_data(b".,.,.").unwrap(), 1, &mut &b"ab"[..], &mut output), ExecutionResult::Ok);
+        assert_eq!(output.bytes, b"\0ab");
+        assert_eq!(output.flushes, 3);
+    }
+    
+    #[test]
+    fn custom_input() {
+        let program = parse_data(b",[.,]").unwrap();
+        let mut output = Vec::new();
+        assert_eq!(run_program_io(&program, 1, &mut &b"echo"[..], &mut output), ExecutionResult::Ok);
+        assert_eq!(output, b"echo");
+    }
+}
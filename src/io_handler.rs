@@ -0,0 +1,179 @@
+use std::cell::RefCell;
+use std::io;
+use std::io::{Read, Write};
+
+use crate::interpreter::{ExecutionResult, RunOptions, run_program_with_options};
+use crate::parser::BFInstruction;
+
+/// Handles a program's I/O a byte at a time, for hosts that want each byte as it happens rather than a stream.
+///
+/// Errors stop the program with `ExecutionResult::IOError`.
+pub trait IoHandler {
+    /// Called with every byte the program writes.
+    fn output(&mut self, byte: u8) -> io::Result<()>;
+    
+    /// Called for every byte the program reads; `None` is the end of the input.
+    fn input(&mut self) -> io::Result<Option<u8>>;
+    
+    /// Called before the program reads, and once it stops, the points where streamed output is flushed.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<H: IoHandler + ?Sized> IoHandler for &mut H {
+    fn output(&mut self, byte: u8) -> io::Result<()> {
+        (**self).output(byte)
+    }
+    
+    fn input(&mut self) -> io::Result<Option<u8>> {
+        (**self).input()
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+}
+
+/// An [`IoHandler`] that reads from stdin and writes to (buffered) stdout, like [`run_program`](crate::run_program).
+pub struct StdIo {
+    output: io::BufWriter<io::Stdout>,
+}
+
+impl StdIo {
+    pub fn new() -> Self {
+        Self { output: io::BufWriter::new(io::stdout()) }
+    }
+}
+
+impl Default for StdIo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IoHandler for StdIo {
+    fn output(&mut self, byte: u8) -> io::Result<()> {
+        self.output.write_all(&[byte])
+    }
+    
+    fn input(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match io::stdin().read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// The input side of a handler shared with a [`HandlerOutput`], as a stream for the interpreter.
+struct HandlerInput<'a, H>(&'a RefCell<H>);
+
+/// The output side of a handler shared with a [`HandlerInput`].
+struct HandlerOutput<'a, H>(&'a RefCell<H>);
+
+impl<H: IoHandler> Read for HandlerInput<'_, H> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(first) = buf.first_mut() else {
+            return Ok(0);
+        };
+        match self.0.borrow_mut().input()? {
+            Some(byte) => {
+                *first = byte;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl<H: IoHandler> Write for HandlerOutput<'_, H> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut handler = self.0.borrow_mut();
+        for &byte in buf {
+            handler.output(byte)?;
+        }
+        Ok(buf.len())
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.borrow_mut().flush()
+    }
+}
+
+/// Runs a parsed program on a zeroed tape of `memory_size` cells, handing its I/O to `handler`.
+///
+/// `handler` sees the bytes the streams would, so options like `numeric_io` and `crlf` still apply.
+pub fn run_program_with_handler(program: &[BFInstruction], memory_size: usize, options: &RunOptions, handler: &mut impl IoHandler) -> ExecutionResult {
+    let handler = RefCell::new(handler);
+    run_program_with_options(program, memory_size, options, &mut HandlerInput(&handler), &mut HandlerOutput(&handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::NumericIo;
+    use crate::parser::parse_data;
+    
+    /// Collects the output and hands out the input a byte at a time, logging every call.
+    #[derive(Default)]
+    struct Collect {
+        input: Vec<u8>,
+        output: Vec<u8>,
+        log: String,
+    }
+    
+    impl IoHandler for Collect {
+        fn output(&mut self, byte: u8) -> io::Result<()> {
+            self.output.push(byte);
+            self.log.push('o');
+            Ok(())
+        }
+        
+        fn input(&mut self) -> io::Result<Option<u8>> {
+            self.log.push('i');
+            Ok((!self.input.is_empty()).then(|| self.input.remove(0)))
+        }
+        
+        fn flush(&mut self) -> io::Result<()> {
+            self.log.push('f');
+            Ok(())
+        }
+    }
+    
+    #[test]
+    fn collects_output() {
+        let mut handler = Collect { input: b"hi".to_vec(), ..Collect::default() };
+        let program = parse_data(b"++++++++[>++++++++<-]>+.+.,.,.,.").unwrap();
+        assert_eq!(run_program_with_handler(&program, 2, &RunOptions::default(), &mut handler), ExecutionResult::Ok);
+        assert_eq!(handler.output, b"ABhi\0");
+        assert_eq!(handler.log, "oofiofiofiof");
+        
+        let options = RunOptions { numeric_io: Some(NumericIo::default()), ..RunOptions::default() };
+        let mut handler = Collect::default();
+        assert_eq!(run_program_with_handler(&parse_data(b"+++++[>++<-]>++.").unwrap(), 2, &options, &mut handler), ExecutionResult::Ok);
+        assert_eq!(handler.output, b"12\n");
+    }
+    
+    #[test]
+    fn errors_stop_the_program() {
+        struct Closed;
+        
+        impl IoHandler for Closed {
+            fn output(&mut self, _byte: u8) -> io::Result<()> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+            
+            fn input(&mut self) -> io::Result<Option<u8>> {
+                Ok(None)
+            }
+        }
+        
+        let result = run_program_with_handler(&parse_data(b",.").unwrap(), 1, &RunOptions::default(), &mut Closed);
+        assert_eq!(result, ExecutionResult::IOError(io::ErrorKind::BrokenPipe.into()));
+    }
+}
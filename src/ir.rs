@@ -0,0 +1,210 @@
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+use crate::optimizer::relink_loops;
+use crate::parser::BFInstruction;
+
+/// Why [`parse_ir`] rejected a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrErrorKind {
+    /// Not a known op, or the wrong operands for it.
+    InvalidInstruction,
+    /// A `loop_end` with no `loop_start` to close, or a `loop_start` that is never closed.
+    UnmatchedLoop,
+}
+
+/// A line of textual IR that [`parse_ir`] couldn't make sense of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrError {
+    /// One-based line number.
+    pub line: usize,
+    pub kind: IrErrorKind,
+}
+
+impl fmt::Display for IrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            IrErrorKind::InvalidInstruction => write!(f, "invalid instruction on line {}", self.line),
+            IrErrorKind::UnmatchedLoop => write!(f, "unmatched loop on line {}", self.line),
+        }
+    }
+}
+
+impl std::error::Error for IrError {}
+
+/// Writes `program` as text with one instruction per line, which [`parse_ir`] reads back.
+///
+/// Each line is an op named after its variant followed by its operands: `add 6`, `ptr -1`, `loop_start`,
+/// `mul_add +1:2 +2:3`, `add_at -2 255`, `clear_at +1` and so on. Offsets and pointer moves are signed; `add` values are the
+/// raw wrapping operands. Loop targets are left out, since they follow from where the loops are.
+pub fn emit_ir(program: &[BFInstruction], out: &mut impl Write) -> io::Result<()> {
+    for instruction in program {
+        match *instruction {
+            BFInstruction::Add(value) => writeln!(out, "add {value}")?,
+            BFInstruction::IncrementPointer(by) => writeln!(out, "ptr {:+}", by as isize)?,
+            BFInstruction::Output => writeln!(out, "out")?,
+            BFInstruction::Input => writeln!(out, "in")?,
+            BFInstruction::LoopStart(_) => writeln!(out, "loop_start")?,
+            BFInstruction::LoopEnd(_) => writeln!(out, "loop_end")?,
+            BFInstruction::ClearCell => writeln!(out, "clear")?,
+            BFInstruction::SetCell(value) => writeln!(out, "set {value}")?,
+            BFInstruction::MulAdd(ref targets) => {
+                write!(out, "mul_add")?;
+                for &(offset, multiplier) in targets.iter() {
+                    write!(out, " {offset:+}:{multiplier}")?;
+                }
+                writeln!(out)?;
+            }
+            BFInstruction::AddAtOffset { offset, value } => writeln!(out, "add_at {offset:+} {value}")?,
+            BFInstruction::ClearCellAtOffset(offset) => writeln!(out, "clear_at {offset:+}")?,
+            BFInstruction::SetCellAtOffset { offset, value } => writeln!(out, "set_at {offset:+} {value}")?,
+            BFInstruction::Debug => writeln!(out, "debug")?,
+            BFInstruction::ScanRight(step) => writeln!(out, "scan_right {step}")?,
+            BFInstruction::ScanLeft(step) => writeln!(out, "scan_left {step}")?,
+            BFInstruction::OutputN(count) => writeln!(out, "out_n {count}")?,
+            BFInstruction::EchoInput => writeln!(out, "echo_in")?,
+        }
+    }
+    Ok(())
+}
+
+/// Reads instructions written by [`emit_ir`], working out the loop targets again.
+///
+/// Blank lines are skipped, as is anything after a `;`, so hand-edited IR can carry comments.
+pub fn parse_ir(text: &str) -> Result<Vec<BFInstruction>, IrError> {
+    let mut program = Vec::new();
+    let mut open_loops = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        let error = |kind| IrError { line: idx + 1, kind };
+        let code = line.split(';').next().unwrap_or_default();
+        let mut fields = code.split_whitespace();
+        let Some(op) = fields.next() else {
+            continue;
+        };
+        let operands: Vec<_> = fields.collect();
+        let instruction = match (op, &operands[..]) {
+            ("loop_start", []) => {
+                open_loops.push(idx + 1);
+                Some(BFInstruction::LoopStart(0))
+            }
+            ("loop_end", []) => {
+                open_loops.pop().ok_or(error(IrErrorKind::UnmatchedLoop))?;
+                Some(BFInstruction::LoopEnd(0))
+            }
+            _ => instruction(op, &operands),
+        };
+        program.push(instruction.ok_or(error(IrErrorKind::InvalidInstruction))?);
+    }
+    
+    if let Some(&line) = open_loops.last() {
+        return Err(IrError { line, kind: IrErrorKind::UnmatchedLoop });
+    }
+    relink_loops(&mut program);
+    Ok(program)
+}
+
+/// Parses an op other than the loops, or returns `None` if it or its operands are invalid.
+fn instruction(op: &str, operands: &[&str]) -> Option<BFInstruction> {
+    let instruction = match (op, operands) {
+        ("add", [value]) => BFInstruction::Add(value.parse().ok()?),
+        ("ptr", [by]) => BFInstruction::IncrementPointer(by.parse::<isize>().ok()? as usize),
+        ("out", []) => BFInstruction::Output,
+        ("in", []) => BFInstruction::Input,
+        ("clear", []) => BFInstruction::ClearCell,
+        ("set", [value]) => BFInstruction::SetCell(value.parse().ok()?),
+        ("mul_add", targets) if !targets.is_empty() => {
+            let targets = targets.iter().map(|target| {
+                let (offset, multiplier) = target.split_once(':')?;
+                Some((offset.parse().ok()?, multiplier.parse().ok()?))
+            });
+            BFInstruction::MulAdd(Box::new(targets.collect::<Option<_>>()?))
+        }
+        ("add_at", [offset, value]) => BFInstruction::AddAtOffset { offset: offset.parse().ok()?, value: value.parse().ok()? },
+        ("clear_at", [offset]) => BFInstruction::ClearCellAtOffset(offset.parse().ok()?),
+        ("set_at", [offset, value]) => BFInstruction::SetCellAtOffset { offset: offset.parse().ok()?, value: value.parse().ok()? },
+        ("debug", []) => BFInstruction::Debug,
+        // A scan that doesn't move never ends, and the code generators expect at least one byte of output.
+        ("scan_right", [step]) => BFInstruction::ScanRight(step.parse().ok().filter(|&step| step != 0)?),
+        ("scan_left", [step]) => BFInstruction::ScanLeft(step.parse().ok().filter(|&step| step != 0)?),
+        ("out_n", [count]) => BFInstruction::OutputN(count.parse().ok().filter(|&count| count != 0)?),
+        ("echo_in", []) => BFInstruction::EchoInput,
+        _ => return None,
+    };
+    Some(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{RunOptions, run_program_outcome};
+    use crate::optimizer::{assert_loops_linked, fuse_offset_adds};
+    use crate::parser::parse_data;
+    
+    #[test]
+    fn round_trip() {
+        // Prints the squares up to 10000, which goes through most of the optimizer.
+        let source = b"++++[>+++++<-]>[<+++++>-]+<+[>[>+>+<<-]++>>[<<+>>-]>>>[-]++>[-]+>>>+[[-]++++++>>>]<<<[[<++++++++<++>>-]+<.<[>----<-]<]<<[>>>>>[>>>[-]+++++++++<[>-<-]+++++++++>[-[<->-]+[<<<]]<[>+<-]>]<<-]<<-]";
+        let mut program = parse_data(source).unwrap();
+        fuse_offset_adds(&mut program);
+        program.extend([
+            BFInstruction::AddAtOffset { offset: -2, value: 255 },
+            BFInstruction::ClearCellAtOffset(3),
+            BFInstruction::SetCellAtOffset { offset: -1, value: 7 },
+            BFInstruction::OutputN(3),
+            BFInstruction::EchoInput,
+            BFInstruction::Debug,
+        ]);
+        
+        let mut text = Vec::new();
+        emit_ir(&program, &mut text).unwrap();
+        let parsed = parse_ir(std::str::from_utf8(&text).unwrap()).unwrap();
+        assert_loops_linked(&parsed);
+        assert_eq!(parsed, program);
+        
+        let run = |program: &[BFInstruction]| {
+            let mut output = Vec::new();
+            let outcome = run_program_outcome(program, 100, &RunOptions::default(), &mut &b"echo"[..], &mut output, &mut ());
+            (outcome, output)
+        };
+        assert_eq!(run(&parsed), run(&program));
+    }
+    
+    #[test]
+    fn text_format() {
+        let mut text = Vec::new();
+        emit_ir(&parse_data(b"+++<[-]--[->++<]>[>]>+<.").unwrap(), &mut text).unwrap();
+        assert_eq!(String::from_utf8(text).unwrap(), "\
+add 3
+ptr -1
+set 254
+mul_add +1:2
+ptr +1
+scan_right 1
+ptr +1
+add 1
+ptr -1
+out
+");
+
+        assert_eq!(parse_ir("  add 1 ; comment\n\nloop_start\nptr -1\nloop_end\n").unwrap(), [
+            BFInstruction::Add(1),
+            BFInstruction::LoopStart(3),
+            BFInstruction::IncrementPointer(usize::MAX),
+            BFInstruction::LoopEnd(1),
+        ]);
+    }
+    
+    #[test]
+    fn invalid() {
+        assert_eq!(parse_ir("add 1\nadd\n"), Err(IrError { line: 2, kind: IrErrorKind::InvalidInstruction }));
+        assert_eq!(parse_ir("jump 3"), Err(IrError { line: 1, kind: IrErrorKind::InvalidInstruction }));
+        assert_eq!(parse_ir("mul_add +1"), Err(IrError { line: 1, kind: IrErrorKind::InvalidInstruction }));
+        assert_eq!(parse_ir("add 1\nscan_right 0"), Err(IrError { line: 2, kind: IrErrorKind::InvalidInstruction }));
+        assert_eq!(parse_ir("scan_left 0"), Err(IrError { line: 1, kind: IrErrorKind::InvalidInstruction }));
+        assert_eq!(parse_ir("out_n 0"), Err(IrError { line: 1, kind: IrErrorKind::InvalidInstruction }));
+        assert!(parse_ir("scan_right 1\nscan_left 2\nout_n 1").is_ok());
+        assert_eq!(parse_ir("out\nloop_end"), Err(IrError { line: 2, kind: IrErrorKind::UnmatchedLoop }));
+        assert_eq!(parse_ir("loop_start\nloop_start\nloop_end").unwrap_err().to_string(), "unmatched loop on line 1");
+    }
+}
@@ -0,0 +1,41 @@
+//! An optimizing Brainfuck interpreter.
+
+pub mod cell;
+pub mod checkpoint;
+pub mod codegen;
+pub mod debugger;
+pub mod dialect;
+pub mod dump;
+pub mod error;
+pub mod interpreter;
+pub mod io_handler;
+pub mod ir;
+pub mod loop_histogram;
+pub mod max_pointer;
+pub mod optimizer;
+pub mod parser;
+pub mod profiler;
+pub mod program_output;
+pub mod stats;
+pub mod tape;
+pub mod tracer;
+pub mod visualizer;
+
+pub use cell::{CellOverflow, CellWidth};
+pub use checkpoint::Checkpoint;
+pub use debugger::Debugger;
+pub use dialect::{Dialect, DialectError};
+pub use dump::MemoryDump;
+pub use error::BfError;
+pub use interpreter::{EofBehavior, ExecutionResult, Hook, Machine, MachineState, NumericIo, RunOptions, RunOutcome, TIMEOUT_CHECK_INTERVAL, Utf8Output, run_program, run_program_io, run_program_outcome, run_program_with_hook, run_program_with_options};
+pub use io_handler::{IoHandler, StdIo, run_program_with_handler};
+pub use ir::{IrError, IrErrorKind, emit_ir, parse_ir};
+pub use loop_histogram::LoopHistogram;
+pub use max_pointer::MaxPointer;
+pub use parser::{BFInstruction, EmptyLoops, ParseError, ParseErrorKind, ParseOptions, Position, count_commands, lint, parse_data, parse_data_with_input, parse_data_with_options, parse_reader, parse_reader_with_options, program_to_source};
+pub use profiler::Profiler;
+pub use program_output::ProgramOutput;
+pub use stats::{GolfStats, ProgramStats};
+pub use tape::TapeMode;
+pub use tracer::Tracer;
+pub use visualizer::Visualizer;
@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Write;
+use std::ops::ControlFlow;
+
+use crate::interpreter::{ExecutionResult, Hook, MachineState};
+use crate::parser::BFInstruction;
+
+/// Records, for every loop, how many iterations each entry into it ran, to show which loops run a constant number of
+/// times and which vary.
+///
+/// Loops are identified by the index of their `LoopStart`; a loop the program reaches with a zero cell is an entry of
+/// zero iterations, and entries still running when the program stops aren't counted. Loops the optimizer turned into
+/// other instructions aren't loops any more, and the program mustn't have had
+/// [`thread_jumps`](crate::optimizer::thread_jumps) applied, since threaded jumps pass loop boundaries by unseen.
+#[derive(Default)]
+pub struct LoopHistogram {
+    /// The loops being run, innermost last, with the iterations each has run so far.
+    frames: Vec<(usize, u64)>,
+    /// How many entries ran each number of iterations, by loop.
+    histograms: BTreeMap<usize, BTreeMap<u64, u64>>,
+}
+
+impl LoopHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// How many entries into the loop starting at `idx` ran each number of iterations, fewest iterations first.
+    pub fn histogram(&self, idx: usize) -> Vec<(u64, u64)> {
+        self.histograms.get(&idx).map_or_else(Vec::new, |histogram| histogram.iter().map(|(&iterations, &entries)| (iterations, entries)).collect())
+    }
+    
+    fn record(&mut self, idx: usize, iterations: u64) {
+        *self.histograms.entry(idx).or_default().entry(iterations).or_default() += 1;
+    }
+    
+    /// Writes a line for every loop that was entered, in program order, with each number of iterations and how many
+    /// entries ran it.
+    pub fn report(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "loop iteration histograms, as iterations x entries:")?;
+        for (idx, histogram) in &self.histograms {
+            let buckets: Vec<_> = histogram.iter().map(|(iterations, entries)| format!("{iterations} x{entries}")).collect();
+            writeln!(out, "{idx:>6}: {}", buckets.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Hook for LoopHistogram {
+    fn before_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        let idx = state.program_counter;
+        let nonzero = state.cell(state.data_pointer).is_some_and(|value| value != 0);
+        match state.program[idx] {
+            BFInstruction::LoopStart(_) if nonzero => self.frames.push((idx, 1)),
+            BFInstruction::LoopStart(_) => self.record(idx, 0),
+            // A run resumed partway through a loop has no frame for it.
+            BFInstruction::LoopEnd(start) => match self.frames.last_mut() {
+                Some((frame, iterations)) if *frame == start && nonzero => *iterations += 1,
+                Some(&mut (frame, iterations)) if frame == start => {
+                    self.frames.pop();
+                    self.record(start, iterations);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{RunOptions, run_program_with_hook};
+    use crate::parser::parse_data;
+    
+    #[test]
+    fn iterations_per_entry() {
+        // The inner loop runs 1, 2 and then 3 times, and the one after it is skipped.
+        let program = parse_data(b"+++[>>+[-<+>>+<]>[-<+>]<<[-.]<-]>[.]").unwrap();
+        let mut histogram = LoopHistogram::new();
+        assert_eq!(run_program_with_hook(&program, 4, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut histogram), ExecutionResult::Ok);
+        assert_eq!(histogram.histogram(1), [(3, 1)]);
+        assert_eq!(histogram.histogram(8), [(1, 1), (2, 1), (3, 1)]);
+        assert_eq!(histogram.histogram(16), [(0, 1)]);
+        
+        let mut out = Vec::new();
+        histogram.report(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+loop iteration histograms, as iterations x entries:
+     1: 3 x1
+     8: 1 x1, 2 x1, 3 x1
+    16: 0 x1
+");
+    }
+}
@@ -1,208 +1,1155 @@
 use std::{env, fs, io};
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-#[cfg_attr(test, derive(Debug, PartialEq))]
-enum BFInstruction {
-    Add(u8),
-    IncrementPointer(usize),
-    Output,
-    Input,
-    LoopStart(usize),
-    LoopEnd(usize),
-}
-
-fn parse_data(data: &[u8]) -> Option<Vec<BFInstruction>> {
-    let mut instructions = Vec::new();
-    let mut loop_stack = Vec::new();
-    let mut last_instruction = None;
-    for &byte in data {
-        match byte {
-            b'+' => match last_instruction.take() {
-                Some(BFInstruction::Add(u8::MAX)) => last_instruction = None,
-                Some(BFInstruction::Add(val)) => last_instruction = Some(BFInstruction::Add(val.wrapping_add(1))),
-                Some(other_instruction) => {
-                    instructions.push(Some(other_instruction));
-                    last_instruction = Some(BFInstruction::Add(1));
-                }
-                None => last_instruction = Some(BFInstruction::Add(1))
-            }
-            b'-' => match last_instruction.take() {
-                Some(BFInstruction::Add(1)) => last_instruction = None,
-                Some(BFInstruction::Add(val)) => last_instruction = Some(BFInstruction::Add(val.wrapping_sub(1))),
-                Some(other_instruction) => {
-                    instructions.push(Some(other_instruction));
-                    last_instruction = Some(BFInstruction::Add(u8::MAX));
-                }
-                None => last_instruction = Some(BFInstruction::Add(u8::MAX))
-            }
-            b'>' => match last_instruction.take() {
-                Some(BFInstruction::IncrementPointer(usize::MAX)) => last_instruction = None,
-                Some(BFInstruction::IncrementPointer(by)) => last_instruction = Some(BFInstruction::IncrementPointer(by.wrapping_add(1))),
-                Some(other_instruction) => {
-                    instructions.push(Some(other_instruction));
-                    last_instruction = Some(BFInstruction::IncrementPointer(1));
-                }
-                None => last_instruction = Some(BFInstruction::IncrementPointer(1))
-            }
-            b'<' => match last_instruction.take() {
-                Some(BFInstruction::IncrementPointer(1)) => last_instruction = None,
-                Some(BFInstruction::IncrementPointer(by)) => last_instruction = Some(BFInstruction::IncrementPointer(by.wrapping_sub(1))),
-                Some(other_instruction) => {
-                    instructions.push(Some(other_instruction));
-                    last_instruction = Some(BFInstruction::IncrementPointer(usize::MAX));
-                }
-                None => last_instruction = Some(BFInstruction::IncrementPointer(usize::MAX))
-            }
-            b'.' => {
-                if let Some(last) = last_instruction.take() {
-                    instructions.push(Some(last));
-                }
-                instructions.push(Some(BFInstruction::Output));
-            }
-            b',' => {
-                if let Some(last) = last_instruction.take() {
-                    instructions.push(Some(last));
-                }
-                instructions.push(Some(BFInstruction::Input));
+use bfint::codegen::c::emit_c;
+use bfint::checkpoint::program_hash;
+use bfint::codegen::json::emit_json;
+use bfint::codegen::wat::emit_wat;
+use bfint::ir::{emit_ir, parse_ir};
+use bfint::optimizer::{fuse_offset_adds, strip_io, thread_jumps};
+use bfint::{BFInstruction, BfError, CellOverflow, CellWidth, Checkpoint, Debugger, Dialect, EmptyLoops, EofBehavior, ExecutionResult, GolfStats, LoopHistogram, Machine, MaxPointer, MemoryDump, NumericIo, ParseError, ParseErrorKind, ParseOptions, Profiler, ProgramStats, RunOptions, RunOutcome, TapeMode, Tracer, Utf8Output, Visualizer, count_commands, lint, parse_data_with_input, parse_data_with_options, parse_reader_with_options};
+
+const USAGE: &str = "\
+usage: [path]... [mem_size] [options]          without mem_size or --mem, the tape size is $BFINT_MEM,
+                                               or 30000 if that's unset or not a number
+       --repl [mem_size] [options]
+       run [path]... [options]           same as the first form, with the tape size from --mem
+       dump [path]... [options]          same as --dump-ir
+       transpile [path]... [--to c|wat|json|ir] [options]
+                                         same as --emit-c (the default), --emit-wat, --emit-json or --emit-ir
+       lint [path]...                    list every unmatched bracket instead of running;
+                                         --dialect and --bang-input apply
+  the subcommands take every option except the ones that pick a mode, like --dump-ir and --repl
+  several paths are read one after another as a single program, so a loop may open in one file and close in a later one
+  a path of - reads the program from stdin, which leaves nothing for ',' to read
+  -m, --mem N                     tape size in cells, instead of mem_size
+  --eof zero|unchanged|-1         value stored by ',' at end of input
+  --cell-width 8|16|32            bits per cell
+  --tape fixed|auto|bidirectional|circular
+                                  how the tape grows, or whether it wraps around
+  --wrap-pointer                  same as --tape circular
+  --cap-index N                   treat cells from index N on as off the tape, however large it is
+  --cell-overflow wrap|trap       whether arithmetic wraps or stops the program
+  --init-memory FILE              load the bytes of FILE into the start of the tape; the rest stays zero
+  --fill BYTE                     start every cell not set by --init-memory at BYTE instead of zero;
+                                  a leading '[...]' comment loop then runs, since the first cell isn't zero,
+                                  and cells added by --tape auto or bidirectional still start at zero
+  --numeric-io                    read and write cells as decimal lines
+  --malformed-input error|zero    how --numeric-io treats input that isn't a number
+  --signed-cells                  make --numeric-io print cells as signed numbers
+  --output raw|utf8               write cells as bytes or as UTF-8 encoded code points
+  --invalid-codepoint error|replace
+                                  how --output utf8 treats cells that aren't code points
+  --crlf                          write newlines from '.' as \r\n
+  --ascii-mask                    clear the high bit of bytes written by '.'; can't be combined with --crlf or --output utf8
+  --ignore-sigpipe                stop quietly when the output is closed early, as when piped into head
+  --max-steps N                   stop after executing N instructions
+  --no-io                         fail at the first '.' or ',' instead of doing I/O
+  --max-output-bytes N            stop instead of writing more than N bytes of output
+  --timeout-ms N                  stop after about N milliseconds
+  --interruptible                 on Ctrl-C, stop and print the tape around the data pointer (Unix only)
+  --repeat N                      run the program N times on fresh tapes, replaying the same input,
+                                  and print the fastest, median and slowest run times to stderr
+  --time                          print how long the run took and how many instructions it ran per second,
+                                  counting instructions after optimization, to stderr
+  --quiet                         discard the program's output, so writing it doesn't skew --repeat timings
+  --flush-every N                 flush the output after every N bytes, not just before ',' and at the end
+  --fold-io[=N]                   remove '.' and make ',' set the cell to N (default 0), which must fit in a cell,
+                                  leaving only the computation
+  --count-only                    run without I/O ('.' does nothing, ',' reads 0) and print how many instructions ran
+  --check-halts N                 run for at most N steps without I/O and print whether the program
+                                  halts (exit 0) or is still running (exit 2)
+  --dump-ir                       print the parsed instructions instead of running
+  --emit-c                        print an equivalent C program instead of running
+  --emit-wat                      print an equivalent WebAssembly text module instead of running; the host
+                                  imports env.output (param i32), env.input (result i32, -1 at end of input)
+                                  and, for '#', env.debug (param i32 i32)
+  --emit-json                     print the parsed instructions as JSON instead of running
+  --emit-ir                       print the parsed instructions as text IR, one per line, instead of running
+  --stats                         print a one-line summary of the parsed program instead of running
+  --golf-stats                    print the source's length, the count of each command, how many bytes
+                                  aren't commands and how many instructions are left, one per line,
+                                  instead of running
+  --validate-only                 print 'ok' if the program parses, or why it doesn't, instead of running;
+                                  --strict, --max-nesting and --werror-infinite apply, unlike with lint;
+                                  with --from-ir, checks the file as IR instead
+  --repl                          run each line from stdin as it's entered, keeping the tape between lines
+  --save-state FILE               if the program stops early (step limit, timeout or interrupt), save its state to FILE
+  --load-state FILE               continue a run saved with --save-state instead of starting on a fresh tape
+  --debug                         step through the program one instruction at a time
+  --break N                       pause the debugger before instruction N
+  --profile N                     report the N most executed instructions and loop counts
+  --loop-histogram                report how many iterations each entry into each loop ran
+  --trace[=N]                     log every executed instruction (or the first N) to stderr
+  --visualize[=MS]                redraw the tape around the data pointer on stderr before every instruction,
+                                  waiting MS milliseconds (default 100) after each; stderr must be a terminal
+                                  that understands ANSI escapes, and the program's output is best redirected
+                                  so it doesn't scroll the view, with --no-optimize for a step per command
+  --bang-input                    treat everything after the first '!' in the program as input, read before stdin
+  --self-input                    make ',' read the program's own source instead of stdin; not with --input,
+                                  --replay-input or --bang-input, since the source already holds any bundled input
+  --line-input                    wait for a whole line of input before ',' gets its first byte, then hand out
+                                  that line a byte at a time before reading the next
+  --input STRING                  make ',' read STRING instead of stdin, with \\n, \\r, \\t, \\0, \\\\ and \\xNN escapes
+  --echo-input                    print each byte ',' reads to stderr, in hex and as a character
+  --record-input FILE             save every byte ',' reads to FILE
+  --replay-input FILE             make ',' read FILE, as saved by --record-input, instead of stdin
+  --from-ir                       read the program as text IR written by --emit-ir instead of as Brainfuck
+  --dialect FILE                  read commands spelled as FILE maps them, one 'command alias' pair per line
+  --strict                        reject bytes that are neither commands nor whitespace
+  --allow-silent                  don't warn about programs without '.' or ','; the warning never fails a run
+  --werror-infinite               reject loops that never end once entered, like '[]' or '[>+<]', instead of warning about them
+  --max-nesting N                 reject programs with loops nested more than N deep
+  --no-optimize                   run one instruction per command, so instruction numbers match command positions
+  --enable-debug-char             treat '#' as an instruction that prints the tape around the data pointer
+  --exit-from-cell                exit with the low byte of the current cell when the program finishes
+  --report-max-pointer            print the highest cell index the program used, even if it fails
+  --memory-dump-on-error          print the tape around the data pointer if the program fails
+  --dump-memory N                 print the first N cells of the tape as hex once the program finishes
+  --checksum                      print a hash of the tape up to its last nonzero cell once the program
+                                  finishes: 64-bit FNV-1a over each cell as four little-endian bytes";
+
+#[derive(Clone, Copy, PartialEq)]
+#[cfg_attr(test, derive(Debug))]
+enum Mode {
+    Run,
+    DumpIr,
+    EmitC,
+    EmitWat,
+    EmitJson,
+    EmitIr,
+    Stats,
+    GolfStats,
+    Repl,
+    Lint,
+    Validate,
+}
+
+struct Args {
+    /// Files whose concatenation is the program; empty in the REPL, which reads its program from stdin.
+    paths: Vec<PathBuf>,
+    memory_size: usize,
+    options: RunOptions,
+    mode: Mode,
+    debug: bool,
+    breakpoints: Vec<usize>,
+    profile: Option<usize>,
+    memory_dump: bool,
+    /// How many cells of the final tape to print after a successful run.
+    dump_memory: Option<usize>,
+    init_memory: Option<PathBuf>,
+    /// The value cells start at instead of zero.
+    fill: Option<u8>,
+    /// `Some(limit)` if tracing, with an optional line limit.
+    trace: Option<Option<u64>>,
+    debug_char: bool,
+    strict: bool,
+    exit_from_cell: bool,
+    no_optimize: bool,
+    max_nesting: Option<usize>,
+    count_only: bool,
+    interruptible: bool,
+    bang_input: bool,
+    check_halts: Option<u64>,
+    dialect: Option<PathBuf>,
+    save_state: Option<PathBuf>,
+    load_state: Option<PathBuf>,
+    report_max_pointer: bool,
+    self_input: bool,
+    werror_infinite: bool,
+    from_ir: bool,
+    echo_input: bool,
+    allow_silent: bool,
+    /// How many times to run the program; at least 1.
+    repeat: u64,
+    quiet: bool,
+    time: bool,
+    /// Input given with `--input`, escapes decoded.
+    inline_input: Option<Vec<u8>>,
+    line_input: bool,
+    /// Flush the output after this many bytes; at least 1.
+    flush_every: Option<u64>,
+    record_input: Option<PathBuf>,
+    replay_input: Option<PathBuf>,
+    loop_histogram: bool,
+    /// `Some(value)` if I/O is folded away, with the value every read sets the cell to.
+    fold_io: Option<u32>,
+    /// `Some(delay)` if visualizing, with the pause after each frame.
+    visualize: Option<Duration>,
+    checksum: bool,
+}
+
+/// The tape size without `--mem`, `mem_size` or `BFINT_MEM`, the size of the original implementation's tape.
+const DEFAULT_MEMORY_SIZE: usize = 30000;
+
+/// How long `--visualize` waits after each frame without `=MS`.
+const DEFAULT_VISUALIZE_DELAY: Duration = Duration::from_millis(100);
+
+/// Tapes taking more bytes than this get a warning, since allocating them may fail.
+const LARGE_TAPE_BYTES: usize = 1 << 32;
+
+/// Parses the command line, with `env_memory_size` the value of `BFINT_MEM`, if it's set.
+///
+/// The tape size comes from the arguments if they give one, then `BFINT_MEM` if it's a number, then
+/// [`DEFAULT_MEMORY_SIZE`].
+fn parse_args(args: impl Iterator<Item=String>, env_memory_size: Option<String>) -> Option<Args> {
+    let mut args = args.skip(1).peekable();
+    let subcommand = match args.peek().map(String::as_str) {
+        Some("run") => Some(Mode::Run),
+        Some("dump") => Some(Mode::DumpIr),
+        Some("transpile") => Some(Mode::EmitC),
+        Some("lint") => Some(Mode::Lint),
+        _ => None,
+    };
+    if subcommand.is_some() {
+        args.next();
+    }
+    let mut positional = Vec::new();
+    let mut memory_size = None;
+    let mut options = RunOptions::default();
+    let mut mode = subcommand.unwrap_or(Mode::Run);
+    let mut debug = false;
+    let mut breakpoints = Vec::new();
+    let mut profile = None;
+    let mut memory_dump = false;
+    let mut dump_memory = None;
+    let mut init_memory = None;
+    let mut fill = None;
+    let mut trace = None;
+    let mut debug_char = false;
+    let mut strict = false;
+    let mut exit_from_cell = false;
+    let mut no_optimize = false;
+    let mut max_nesting = None;
+    let mut count_only = false;
+    let mut interruptible = false;
+    let mut bang_input = false;
+    let mut check_halts = None;
+    let mut dialect = None;
+    let mut save_state = None;
+    let mut load_state = None;
+    let mut report_max_pointer = false;
+    let mut self_input = false;
+    let mut werror_infinite = false;
+    let mut from_ir = false;
+    let mut echo_input = false;
+    let mut allow_silent = false;
+    let mut repeat = 1;
+    let mut quiet = false;
+    let mut time = false;
+    let mut inline_input = None;
+    let mut record_input = None;
+    let mut replay_input = None;
+    let mut loop_histogram = false;
+    let mut fold_io = None;
+    let mut visualize = None;
+    let mut checksum = false;
+    let mut line_input = false;
+    let mut flush_every = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dump-ir" | "--emit-c" | "--emit-wat" | "--emit-json" | "--emit-ir" | "--stats" | "--golf-stats" | "--validate-only" | "--repl" if subcommand.is_some() => return None,
+            "--to" if subcommand == Some(Mode::EmitC) => mode = match args.next()?.as_str() {
+                "c" => Mode::EmitC,
+                "wat" => Mode::EmitWat,
+                "json" => Mode::EmitJson,
+                "ir" => Mode::EmitIr,
+                _ => return None
+            },
+            "--to" => return None,
+            "-m" | "--mem" => memory_size = Some(args.next()?.parse().ok()?),
+            "--eof" => options.eof_behavior = match args.next()?.as_str() {
+                "zero" => EofBehavior::Zero,
+                "unchanged" => EofBehavior::Unchanged,
+                "-1" => EofBehavior::NegativeOne,
+                _ => return None
+            },
+            "--cell-width" => options.cell_width = match args.next()?.as_str() {
+                "8" => CellWidth::U8,
+                "16" => CellWidth::U16,
+                "32" => CellWidth::U32,
+                _ => return None
+            },
+            "--tape" => options.tape_mode = match args.next()?.as_str() {
+                "fixed" => TapeMode::Fixed,
+                "auto" => TapeMode::Growing,
+                "bidirectional" => TapeMode::Bidirectional,
+                "circular" => TapeMode::Circular,
+                _ => return None
+            },
+            "--wrap-pointer" => options.tape_mode = TapeMode::Circular,
+            "--cap-index" => options.cap_index = Some(args.next()?.parse().ok()?),
+            "--cell-overflow" => options.cell_overflow = match args.next()?.as_str() {
+                "wrap" => CellOverflow::Wrap,
+                "trap" => CellOverflow::Trap,
+                _ => return None
+            },
+            "--init-memory" => init_memory = Some(PathBuf::from(args.next()?)),
+            "--fill" => fill = Some(args.next()?.parse().ok()?),
+            "--numeric-io" => options.numeric_io = Some(options.numeric_io.unwrap_or_default()),
+            "--malformed-input" => {
+                let numeric_io = options.numeric_io.get_or_insert_with(NumericIo::default);
+                numeric_io.malformed_as_zero = match args.next()?.as_str() {
+                    "error" => false,
+                    "zero" => true,
+                    _ => return None
+                };
             }
-            b'[' => {
-                if let Some(last) = last_instruction.take() {
-                    instructions.push(Some(last));
-                }
-                loop_stack.push(instructions.len());
-                instructions.push(None);
+            "--signed-cells" => options.numeric_io.get_or_insert_with(NumericIo::default).signed = true,
+            "--output" => options.utf8_output = match args.next()?.as_str() {
+                "raw" => None,
+                "utf8" => Some(options.utf8_output.unwrap_or_default()),
+                _ => return None
+            },
+            "--invalid-codepoint" => {
+                let utf8_output = options.utf8_output.get_or_insert_with(Utf8Output::default);
+                utf8_output.replace_invalid = match args.next()?.as_str() {
+                    "error" => false,
+                    "replace" => true,
+                    _ => return None
+                };
             }
-            b']' => {
-                if let Some(last) = last_instruction.take() {
-                    instructions.push(Some(last));
-                }
-                let loop_start_idx = loop_stack.pop()?;
-                instructions[loop_start_idx] = Some(BFInstruction::LoopStart(instructions.len()));
-                instructions.push(Some(BFInstruction::LoopEnd(loop_start_idx)));
+            "--crlf" => options.crlf = true,
+            "--ascii-mask" => options.ascii_mask = true,
+            "--ignore-sigpipe" => options.ignore_broken_pipe = true,
+            "--no-io" => options.no_io = true,
+            "--max-steps" => options.max_steps = Some(args.next()?.parse().ok()?),
+            "--max-output-bytes" => options.max_output = Some(args.next()?.parse().ok()?),
+            "--timeout-ms" => options.timeout = Some(Duration::from_millis(args.next()?.parse().ok()?)),
+            "--dump-ir" => mode = Mode::DumpIr,
+            "--emit-c" => mode = Mode::EmitC,
+            "--emit-wat" => mode = Mode::EmitWat,
+            "--emit-json" => mode = Mode::EmitJson,
+            "--emit-ir" => mode = Mode::EmitIr,
+            "--stats" => mode = Mode::Stats,
+            "--golf-stats" => mode = Mode::GolfStats,
+            "--validate-only" => mode = Mode::Validate,
+            "--repl" => mode = Mode::Repl,
+            "--debug" => debug = true,
+            "--break" => breakpoints.push(args.next()?.parse().ok()?),
+            "--profile" => profile = Some(args.next()?.parse().ok()?),
+            "--memory-dump-on-error" => memory_dump = true,
+            "--dump-memory" => dump_memory = Some(args.next()?.parse().ok()?),
+            "--trace" => trace = Some(None),
+            "--enable-debug-char" => debug_char = true,
+            "--strict" => strict = true,
+            "--exit-from-cell" => exit_from_cell = true,
+            "--no-optimize" => no_optimize = true,
+            "--max-nesting" => max_nesting = Some(args.next()?.parse().ok()?),
+            "--count-only" => count_only = true,
+            "--interruptible" => interruptible = true,
+            "--bang-input" => bang_input = true,
+            "--check-halts" => check_halts = Some(args.next()?.parse().ok()?),
+            "--dialect" => dialect = Some(PathBuf::from(args.next()?)),
+            "--save-state" => save_state = Some(PathBuf::from(args.next()?)),
+            "--load-state" => load_state = Some(PathBuf::from(args.next()?)),
+            "--report-max-pointer" => report_max_pointer = true,
+            "--self-input" => self_input = true,
+            "--werror-infinite" => werror_infinite = true,
+            "--from-ir" => from_ir = true,
+            "--echo-input" => echo_input = true,
+            "--allow-silent" => allow_silent = true,
+            "--repeat" => repeat = args.next()?.parse().ok().filter(|&repeat| repeat > 0)?,
+            "--quiet" => quiet = true,
+            "--time" => time = true,
+            "--line-input" => line_input = true,
+            "--flush-every" => flush_every = Some(args.next()?.parse().ok().filter(|&every| every > 0)?),
+            "--input" => inline_input = Some(unescape(&args.next()?)?),
+            "--record-input" => record_input = Some(PathBuf::from(args.next()?)),
+            "--replay-input" => replay_input = Some(PathBuf::from(args.next()?)),
+            "--loop-histogram" => loop_histogram = true,
+            "--fold-io" => fold_io = Some(0),
+            "--visualize" => visualize = Some(DEFAULT_VISUALIZE_DELAY),
+            "--checksum" => checksum = true,
+            _ => match (arg.strip_prefix("--trace="), arg.strip_prefix("--visualize="), arg.strip_prefix("--fold-io=")) {
+                (Some(limit), _, _) => trace = Some(Some(limit.parse().ok()?)),
+                (None, Some(delay), _) => visualize = Some(Duration::from_millis(delay.parse().ok()?)),
+                (None, None, Some(value)) => fold_io = Some(value.parse().ok()?),
+                (None, None, None) => positional.push(arg),
             }
-            _ => {}
         }
     }
-    
-    if let Some(last_instruction) = last_instruction {
-        instructions.push(Some(last_instruction));
+    if fold_io.is_some_and(|value| value > options.cell_width.mask()) {
+        return None;
     }
-    
-    let mut instructions_return = Vec::with_capacity(instructions.len());
-    for instruction in instructions {
-        instructions_return.push(instruction?);
+    // Masking is byte for byte, which newline expansion and UTF-8 encoding aren't.
+    if options.ascii_mask && (options.crlf || options.utf8_output.is_some()) {
+        return None;
+    }
+    // Golf stats are about the Brainfuck source.
+    if mode == Mode::GolfStats && from_ir {
+        return None;
+    }
+    // The program's own source and a replay are the whole of the input, so they can't be combined with another source.
+    if [self_input, inline_input.is_some(), replay_input.is_some()].into_iter().filter(|&source| source).count() > 1 {
+        return None;
+    }
+    // Input bundled after a `!` is already part of the source `--self-input` reads.
+    if self_input && bang_input {
+        return None;
     }
     
-    Some(instructions_return)
+    // Without a subcommand, a trailing number is the tape size rather than a path.
+    let (memory_size, paths) = match (memory_size, subcommand, positional.split_last()) {
+        (Some(memory_size), _, _) => (memory_size, &positional[..]),
+        (None, None, Some((last, paths))) if last.parse::<usize>().is_ok() => (last.parse().ok()?, paths),
+        (None, _, _) => (env_memory_size.and_then(|size| size.parse().ok()).unwrap_or(DEFAULT_MEMORY_SIZE), &positional[..]),
+    };
+    if paths.is_empty() != (mode == Mode::Repl) {
+        return None;
+    }
+    Some(Args {
+        paths: paths.iter().map(PathBuf::from).collect(),
+        memory_size,
+        options,
+        mode,
+        debug,
+        breakpoints,
+        profile,
+        memory_dump,
+        dump_memory,
+        init_memory,
+        fill,
+        trace,
+        debug_char,
+        strict,
+        exit_from_cell,
+        no_optimize,
+        max_nesting,
+        count_only,
+        interruptible,
+        bang_input,
+        check_halts,
+        dialect,
+        save_state,
+        load_state,
+        report_max_pointer,
+        self_input,
+        werror_infinite,
+        from_ir,
+        echo_input,
+        allow_silent,
+        repeat,
+        quiet,
+        time,
+        inline_input,
+        line_input,
+        flush_every,
+        record_input,
+        replay_input,
+        loop_histogram,
+        fold_io,
+        visualize,
+        checksum,
+    })
 }
 
-struct Args {
-    path: PathBuf,
-    memory_size: usize,
+/// Whether the program is run with [`thread_jumps`] applied.
+///
+/// Threaded brackets no longer match up and the ones jumped over never run, so the pass is skipped for every hook that
+/// looks at the instructions: breakpoints would never fire on them, and profiles, traces, the visualizer, memory dumps
+/// and the loop histogram would all report the threaded program instead of the source.
+fn threads_jumps(args: &Args) -> bool {
+    let watched = args.debug || !args.breakpoints.is_empty() || args.profile.is_some() || args.trace.is_some() || args.visualize.is_some()
+        || args.debug_char || args.memory_dump || args.dump_memory.is_some() || args.loop_histogram;
+    !args.no_optimize && !watched
 }
 
-fn parse_args(args: impl Iterator<Item=String>) -> Option<Args> {
-    let mut args = args.skip(1);
-    Some(Args {
-        path: PathBuf::from(args.next()?),
-        memory_size: args.next()?.parse().ok()?,
-    })
+/// What `--validate-only` prints about `source`, and whether it parsed, as IR if `from_ir` is set.
+fn validate(mut source: impl Read, parse_options: &ParseOptions, from_ir: bool) -> (String, bool) {
+    let parsed = match from_ir {
+        true => {
+            let mut data = Vec::new();
+            match source.read_to_end(&mut data) {
+                Ok(_) => match std::str::from_utf8(&data) {
+                    Ok(text) => parse_ir(text).map(drop).map_err(|err| BfError::from(err).to_string()),
+                    Err(_) => Err("couldn't parse IR: not valid UTF-8".to_string()),
+                },
+                Err(_) => Err("couldn't read file".to_string()),
+            }
+        }
+        false => parse_reader_with_options(source, parse_options).map(drop).map_err(|err| err.to_string()),
+    };
+    match parsed {
+        Ok(()) => ("ok".to_string(), true),
+        Err(message) => (message, false),
+    }
+}
+
+/// The exit status `--exit-from-cell` gives a run that ended on `current_cell`: its low byte.
+fn cell_exit_status(current_cell: Option<u32>) -> u8 {
+    current_cell.unwrap_or(0) as u8
 }
 
-#[cfg_attr(test, derive(Debug, PartialEq))]
-enum ExecutionResult {
-    Ok,
-    MemoryAccessError,
-    IOError,
+/// Whether a run with `args` needs no I/O, so a program without any isn't worth a warning.
+///
+/// Runs that throw I/O away or report through the exit status or a checksum are silent on purpose, and so is
+/// anything other than running the program.
+fn silent_on_purpose(args: &Args) -> bool {
+    args.mode != Mode::Run || args.allow_silent || args.options.no_io || args.count_only || args.check_halts.is_some() || args.exit_from_cell || args.checksum
 }
 
-fn run_program(program: &[BFInstruction], memory_size: usize) -> ExecutionResult {
-    let mut program_counter = 0;
-    let mut data_pointer = 0;
-    let mut memory = vec![0u8; memory_size];
+/// Whether `program` has no instructions that read input or write output.
+fn is_silent(program: &[BFInstruction]) -> bool {
+    !program.iter().any(|instruction| matches!(instruction, BFInstruction::Output | BFInstruction::OutputN(_) | BFInstruction::Input | BFInstruction::EchoInput))
+}
+
+/// What parsing does about loops that never end once entered: reject them under `--werror-infinite`, or warn.
+fn empty_loops(werror_infinite: bool) -> EmptyLoops {
+    match werror_infinite {
+        true => EmptyLoops::Reject,
+        false => EmptyLoops::Warn(warn_empty_loop),
+    }
+}
+
+fn warn_empty_loop(err: ParseError) {
+    let position = err.position;
+    let reason = match err.kind {
+        ParseErrorKind::EndlessLoop => "never changes the cell it tests",
+        _ => "is empty",
+    };
+    eprintln!("warning: the loop at byte {} (line {}, column {}) {reason}, so it never ends once entered", position.offset, position.line, position.column);
+}
+
+fn open_source<'a>(path: &Path, stdin: impl Read + 'a) -> io::Result<Box<dyn Read + 'a>> {
+    if path == Path::new("-") {
+        Ok(Box::new(stdin))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+/// Chains the sources at `paths` in order, opening every file up front so a missing one is caught before anything runs.
+fn open_sources(paths: &[PathBuf]) -> io::Result<Box<dyn Read>> {
+    let mut source: Box<dyn Read> = Box::new(io::empty());
+    for path in paths {
+        source = Box::new(source.chain(open_source(path, io::stdin())?));
+    }
+    Ok(source)
+}
+
+fn dump_ir(program: &[BFInstruction]) -> io::Result<()> {
     let mut stdout = io::stdout().lock();
-    let mut stdin = io::stdin().lock();
-    while program_counter < program.len() {
-        match program[program_counter] {
-            BFInstruction::Add(val) => {
-                let Some(current_byte) = memory.get_mut(data_pointer) else {
-                    return ExecutionResult::MemoryAccessError;
-                };
-                
-                *current_byte = current_byte.wrapping_add(val);
-            }
-            BFInstruction::IncrementPointer(by) => data_pointer = data_pointer.wrapping_add(by),
-            BFInstruction::Output => {
-                let Some(&current_byte) = memory.get(data_pointer) else {
-                    return ExecutionResult::MemoryAccessError;
-                };
-                
-                if stdout.write(&[current_byte]).is_err() || stdout.flush().is_err() {
-                    return ExecutionResult::IOError;
-                }
-            }
-            BFInstruction::Input => {
-                let Some(current_byte) = memory.get_mut(data_pointer) else {
-                    return ExecutionResult::MemoryAccessError;
-                };
-                
-                let mut read_byte = [0; 1];
-                match stdin.read(&mut read_byte) {
-                    Ok(0) => *current_byte = 0,
-                    Ok(_) => *current_byte = read_byte[0],
-                    Err(_) => return ExecutionResult::IOError
-                }
-            }
-            BFInstruction::LoopStart(idx) => {
-                let Some(&current_byte) = memory.get(data_pointer) else {
-                    return ExecutionResult::MemoryAccessError;
-                };
-                
-                if current_byte == 0 {
-                    program_counter = idx;
+    for (idx, instruction) in program.iter().enumerate() {
+        writeln!(stdout, "{idx}: {instruction:?}")?;
+    }
+    stdout.flush()
+}
+
+/// Remembers whether the last byte written ended a line, so the REPL can put its prompt on a fresh one.
+struct LineTracker<W> {
+    inner: W,
+    at_line_start: bool,
+}
+
+impl<W: Write> Write for LineTracker<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(&last) = buf[..written].last() {
+            self.at_line_start = last == b'\n';
+        }
+        Ok(written)
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decodes the escapes `--input` accepts: `\n`, `\r`, `\t`, `\0`, `\\` and `\xNN`. Returns `None` for any other escape.
+fn unescape(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut rest = text.bytes();
+    while let Some(byte) = rest.next() {
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        bytes.push(match rest.next()? {
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'0' => 0,
+            b'\\' => b'\\',
+            b'x' => {
+                let digits = [rest.next()?, rest.next()?];
+                if !digits.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
                 }
+                u8::from_str_radix(std::str::from_utf8(&digits).ok()?, 16).ok()?
             }
-            BFInstruction::LoopEnd(idx) => {
-                let Some(&current_byte) = memory.get(data_pointer) else {
-                    return ExecutionResult::MemoryAccessError;
-                };
-                
-                if current_byte != 0 {
-                    program_counter = idx;
-                }
+            _ => return None,
+        });
+    }
+    Some(bytes)
+}
+
+/// What `--checksum` prints about a finished run.
+fn checksum_line(outcome: &RunOutcome) -> String {
+    format!("tape checksum: {:016x}", outcome.checksum())
+}
+
+/// Describes how fast a run that took `elapsed` got through `steps` instructions.
+fn speed(steps: u64, elapsed: Duration) -> String {
+    // A run too short for the clock to notice still gets a finite rate.
+    let rate = steps as f64 / elapsed.as_secs_f64().max(1e-9) / 1e6;
+    format!("executed {steps} instructions in {:.3} ms ({rate:.1} Minsn/s)", elapsed.as_secs_f64() * 1e3)
+}
+
+/// Summarizes how long each run of `--repeat` took, sorting `durations` along the way.
+fn timing_summary(durations: &mut [Duration]) -> String {
+    durations.sort();
+    let runs = match durations.len() {
+        1 => "1 run".to_string(),
+        runs => format!("{runs} runs"),
+    };
+    format!("{runs}: min {:?}, median {:?}, max {:?}", durations[0], durations[durations.len() / 2], durations[durations.len() - 1])
+}
+
+/// What `,` reads, before `--line-input` and `--record-input` wrap it.
+///
+/// That's `source` for `--self-input`, a `replayed` recording, or the input bundled after a `!` followed by
+/// `inline_input` or else `stdin`. [`parse_args`] lets through at most one of the first three.
+fn input_source(source: Vec<u8>, bundled_input: Vec<u8>, inline_input: Option<Vec<u8>>, replayed: Option<Vec<u8>>, self_input: bool, stdin: impl Read + 'static) -> Box<dyn Read> {
+    match (replayed, self_input, inline_input) {
+        // The recording already starts with whatever input was bundled with the program.
+        (Some(replayed), _, _) => Box::new(io::Cursor::new(replayed)),
+        (None, true, _) => Box::new(io::Cursor::new(source)),
+        (None, false, Some(inline_input)) => Box::new(io::Cursor::new(bundled_input).chain(io::Cursor::new(inline_input))),
+        (None, false, None) => Box::new(io::Cursor::new(bundled_input).chain(stdin)),
+    }
+}
+
+/// Reports every byte read from `inner` on `echo`, one line each, to show what `,` consumed.
+struct InputEcho<R, W> {
+    inner: R,
+    echo: W,
+}
+
+impl<R: Read, W: Write> Read for InputEcho<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        for &byte in &buf[..read] {
+            writeln!(self.echo, "input: 0x{byte:02x} '{}'", byte.escape_ascii())?;
+        }
+        Ok(read)
+    }
+}
+
+/// Saves every byte read from `inner` to `record`, so a later run can read the same input back.
+struct InputRecorder<R, W> {
+    inner: R,
+    record: W,
+}
+
+impl<R: Read, W: Write> Read for InputRecorder<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.record.write_all(&buf[..read])?;
+        Ok(read)
+    }
+}
+
+/// Reads `inner` a whole line at a time, handing out the bytes of the current line before reading the next.
+struct LineInput<R> {
+    inner: BufReader<R>,
+    line: Vec<u8>,
+    /// How much of `line` has been read.
+    consumed: usize,
+}
+
+impl<R: Read> LineInput<R> {
+    fn new(inner: R) -> Self {
+        Self { inner: BufReader::new(inner), line: Vec::new(), consumed: 0 }
+    }
+}
+
+impl<R: Read> Read for LineInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.consumed == self.line.len() {
+            self.line.clear();
+            self.consumed = 0;
+            self.inner.read_until(b'\n', &mut self.line)?;
+        }
+        let len = buf.len().min(self.line.len() - self.consumed);
+        buf[..len].copy_from_slice(&self.line[self.consumed..self.consumed + len]);
+        self.consumed += len;
+        Ok(len)
+    }
+}
+
+/// Flushes `inner` each time another `every` bytes have been written to it since it was last flushed.
+///
+/// Writes are cut short where a flush is due, so a long run of output is flushed partway through.
+struct FlushEvery<W> {
+    inner: W,
+    every: u64,
+    unflushed: u64,
+}
+
+impl<W: Write> Write for FlushEvery<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let due = usize::try_from(self.every - self.unflushed).unwrap_or(usize::MAX);
+        let written = self.inner.write(&buf[..buf.len().min(due)])?;
+        self.unflushed += written as u64;
+        if self.unflushed == self.every {
+            self.flush()?;
+        }
+        Ok(written)
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        self.unflushed = 0;
+        self.inner.flush()
+    }
+}
+
+/// Runs each line of `input` as a program on the same machine, so the tape and data pointer carry over from one line to the next.
+///
+/// `,` reads from `input` as well, taking the lines after the one being run.
+/// Lines that don't parse or programs that fail are reported without ending the session.
+fn repl(machine: &mut Machine, options: &RunOptions, parse_options: &ParseOptions, input: &mut impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut output = LineTracker { inner: output, at_line_start: true };
+    let mut line = Vec::new();
+    loop {
+        if !output.at_line_start {
+            writeln!(output)?;
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+        output.at_line_start = false;
+        
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            return writeln!(output);
+        }
+        output.at_line_start = true;
+        
+        let mut program = match parse_data_with_options(&line, parse_options) {
+            Ok(program) => program,
+            Err(err) => {
+                eprintln!("{}", BfError::from(err));
+                continue;
             }
+        };
+        if !parse_options.unoptimized {
+            fuse_offset_adds(&mut program);
         }
-        program_counter += 1;
+        if let Some(message) = error_message(machine.run(&program, options, input, &mut output, &mut ())) {
+            output.flush()?;
+            eprintln!("{message}");
+        }
+    }
+}
+
+/// What to tell the user about a run that didn't finish normally.
+fn error_message(result: ExecutionResult) -> Option<String> {
+    result.into_result().err().map(|err| err.to_string())
+}
+
+/// Set by the SIGINT handler.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes SIGINT set [`INTERRUPTED`] instead of killing the process, returning whether that worked.
+///
+/// The handler is installed with `signal(2)`, which on Linux and the BSDs restarts interrupted reads,
+/// so a program blocked on `,` only notices the interrupt after its input arrives.
+/// Once installed, Ctrl-C can't kill the process until the program reaches the next check.
+#[cfg(unix)]
+fn handle_interrupts() -> bool {
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+    }
+    const SIGINT: i32 = 2;
+    const SIG_ERR: usize = usize::MAX;
+    
+    extern "C" fn on_interrupt(_: i32) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
     }
-    ExecutionResult::Ok
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+    unsafe { signal(SIGINT, on_interrupt) != SIG_ERR }
+}
+
+#[cfg(not(unix))]
+fn handle_interrupts() -> bool {
+    false
 }
 
 fn main() -> ExitCode {
-    let Some(Args { path, memory_size }) = parse_args(env::args()) else {
-        eprintln!("usage: [path] [mem_size]");
+    let Some(args) = parse_args(env::args(), env::var("BFINT_MEM").ok()) else {
+        eprintln!("{USAGE}");
         return ExitCode::FAILURE;
     };
+    let threaded = threads_jumps(&args);
+    let silent_on_purpose = silent_on_purpose(&args);
+    let Args { paths, memory_size, mut options, mode, debug, breakpoints, profile, memory_dump, dump_memory, init_memory, fill, trace, debug_char, strict, exit_from_cell, no_optimize, max_nesting, count_only, interruptible, bang_input, check_halts, dialect, save_state, load_state, report_max_pointer, self_input, werror_infinite, from_ir, echo_input, allow_silent: _, repeat, quiet, time, inline_input, line_input, flush_every, record_input, replay_input, loop_histogram, fold_io, visualize, checksum } = args;
     
-    let Ok(file_contents) = fs::read(path) else {
-        eprintln!("couldn't read file");
+    if memory_size == 0 {
+        eprintln!("memory size must be at least 1");
         return ExitCode::FAILURE;
+    }
+    let cell_bytes = options.cell_width.mask().count_ones() as usize / 8;
+    if memory_size.saturating_mul(cell_bytes) > LARGE_TAPE_BYTES && matches!(mode, Mode::Run | Mode::Repl) {
+        eprintln!("warning: a tape of {memory_size} cells may not fit in memory");
+    }
+    
+    if let Some(init_memory) = init_memory {
+        let Ok(initial_memory) = fs::read(init_memory) else {
+            eprintln!("couldn't read initial memory");
+            return ExitCode::FAILURE;
+        };
+        if initial_memory.len() > memory_size {
+            eprintln!("initial memory is larger than the tape");
+            return ExitCode::FAILURE;
+        }
+        options.initial_memory = initial_memory;
+    }
+    if let Some(fill) = fill {
+        // The rest of the tape is loaded as initial memory too, which also keeps a leading comment loop.
+        options.initial_memory.resize(memory_size, fill);
+    }
+    
+    let dialect = match dialect.map(fs::read) {
+        None => None,
+        Some(Ok(spec)) => match Dialect::parse(&spec) {
+            Ok(dialect) => Some(dialect),
+            Err(err) => {
+                eprintln!("{}", BfError::from(err));
+                return ExitCode::FAILURE;
+            }
+        },
+        Some(Err(_)) => {
+            eprintln!("couldn't read dialect");
+            return ExitCode::FAILURE;
+        }
+    };
+    let parse_options = ParseOptions {
+        cell_width: options.cell_width,
+        cell_overflow: options.cell_overflow,
+        drop_comment_loop: false,
+        debug_char,
+        strict,
+        unoptimized: no_optimize,
+        max_nesting,
+        bang_input,
+        dialect,
+        empty_loops: empty_loops(werror_infinite),
     };
+    if mode == Mode::Repl {
+        let mut machine = Machine::new(memory_size, &options);
+        return match repl(&mut machine, &options, &parse_options, &mut io::stdin().lock(), io::stdout().lock()) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(_) => {
+                eprintln!("I/O error");
+                ExitCode::FAILURE
+            }
+        };
+    }
     
-    let Some(program) = parse_data(&file_contents) else {
-        eprintln!("couldn't parse program");
+    let Ok(mut source) = open_sources(&paths) else {
+        eprintln!("couldn't read file");
         return ExitCode::FAILURE;
     };
+    if mode == Mode::Lint {
+        let mut data = Vec::new();
+        if source.read_to_end(&mut data).is_err() {
+            eprintln!("couldn't read file");
+            return ExitCode::FAILURE;
+        }
+        let errors = lint(&data, &parse_options);
+        for err in &errors {
+            println!("{err}");
+        }
+        return match errors.is_empty() {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        };
+    }
+    if mode == Mode::Validate {
+        let (message, valid) = validate(source, &parse_options, from_ir);
+        println!("{message}");
+        return match valid {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        };
+    }
+    let parse_options = ParseOptions {
+        // A leading loop only never runs if the tape starts out zeroed.
+        drop_comment_loop: !no_optimize && options.initial_memory.first().is_none_or(|&byte| byte == 0),
+        ..parse_options
+    };
+    let mut commands = 0;
+    let mut data = Vec::new();
+    let parsed = if from_ir {
+        if source.read_to_end(&mut data).is_err() {
+            eprintln!("couldn't read file");
+            return ExitCode::FAILURE;
+        }
+        match std::str::from_utf8(&data).map(parse_ir) {
+            Ok(Ok(program)) => Ok((program, Vec::new())),
+            Ok(Err(err)) => {
+                eprintln!("{}", BfError::from(err));
+                return ExitCode::FAILURE;
+            }
+            Err(_) => {
+                eprintln!("couldn't parse IR: not valid UTF-8");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else if bang_input || self_input || matches!(mode, Mode::Stats | Mode::GolfStats) {
+        // Keeping the input after the `!`, feeding the source to `,` and counting commands all need the whole source, so it's read up front.
+        if source.read_to_end(&mut data).is_err() {
+            eprintln!("couldn't read file");
+            return ExitCode::FAILURE;
+        }
+        commands = count_commands(&data, &parse_options);
+        parse_data_with_input(&data, &parse_options).map(|(program, input)| (program, input.to_vec()))
+    } else {
+        parse_reader_with_options(source, &parse_options).map(|program| (program, Vec::new()))
+    };
+    let (mut program, bundled_input) = match parsed {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("{}", BfError::from(err));
+            return ExitCode::FAILURE;
+        }
+    };
+    if !silent_on_purpose && is_silent(&program) {
+        eprintln!("warning: the program has no '.' or ',', so it reads and writes nothing");
+    }
+    if let Some(value) = fold_io {
+        strip_io(&mut program, value);
+    }
+    // IR is run as written.
+    if !no_optimize && !from_ir {
+        fuse_offset_adds(&mut program);
+    }
     
-    match run_program(&program, memory_size) {
-        ExecutionResult::Ok => ExitCode::SUCCESS,
-        ExecutionResult::MemoryAccessError => {
-            eprintln!("memory access error");
-            ExitCode::FAILURE
+    let emitted = match mode {
+        Mode::Run | Mode::Repl | Mode::Lint | Mode::Validate => None,
+        Mode::DumpIr => Some(dump_ir(&program)),
+        Mode::EmitC => Some(emit_c(&program, memory_size, &options, &mut io::stdout().lock())),
+        Mode::EmitWat => Some(emit_wat(&program, memory_size, &options, &mut io::stdout().lock())),
+        Mode::EmitJson => Some(emit_json(&program, &mut io::stdout().lock())),
+        Mode::EmitIr => Some(emit_ir(&program, &mut io::stdout().lock())),
+        Mode::Stats => Some(writeln!(io::stdout().lock(), "{}", ProgramStats::new(&program, commands))),
+        Mode::GolfStats => Some(writeln!(io::stdout().lock(), "{}", GolfStats::new(&data, &parse_options, &program))),
+    };
+    if let Some(emitted) = emitted {
+        return match emitted {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(_) => {
+                eprintln!("I/O error");
+                ExitCode::FAILURE
+            }
+        };
+    }
+    // Saved states match the program as parsed, so they resume whether or not the jumps are threaded this time.
+    let hash = program_hash(&program);
+    if threaded {
+        thread_jumps(&mut program);
+    }
+    
+    let (checkpoint, start) = match load_state {
+        None => (None, 0),
+        Some(path) => {
+            let Ok(checkpoint) = File::open(path).and_then(|file| Checkpoint::read_from(&mut BufReader::new(file))) else {
+                eprintln!("couldn't read saved state");
+                return ExitCode::FAILURE;
+            };
+            if checkpoint.program_hash != hash {
+                eprintln!("saved state is for a different program or different parse options");
+                return ExitCode::FAILURE;
+            }
+            if checkpoint.cell_width != options.cell_width {
+                eprintln!("saved state is for {}-bit cells", checkpoint.cell_width.mask().count_ones());
+                return ExitCode::FAILURE;
+            }
+            let start = checkpoint.program_counter;
+            (Some(checkpoint), start)
         }
-        ExecutionResult::IOError => {
-            eprintln!("I/O error");
+    };
+    
+    if interruptible {
+        if handle_interrupts() {
+            options.interrupt = Some(&INTERRUPTED);
+        } else {
+            eprintln!("warning: can't handle interrupts on this platform");
+        }
+    }
+    
+    if check_halts.is_some() {
+        options.max_steps = check_halts;
+    }
+    let (mut input, mut output): (Box<dyn Read>, Box<dyn Write>) = if count_only || check_halts.is_some() {
+        // `,` always reads a zero byte and `.` writes nowhere, so runs are deterministic and free of terminal I/O.
+        options.numeric_io = None;
+        options.utf8_output = None;
+        (Box::new(io::repeat(0)), Box::new(io::sink()))
+    } else {
+        let replayed = match replay_input {
+            None => None,
+            Some(path) => match fs::read(path) {
+                Ok(replayed) => Some(replayed),
+                Err(_) => {
+                    eprintln!("couldn't read replayed input");
+                    return ExitCode::FAILURE;
+                }
+            },
+        };
+        let mut input = input_source(data, bundled_input, inline_input, replayed, self_input, io::stdin().lock());
+        if line_input {
+            input = Box::new(LineInput::new(input));
+        }
+        if let Some(path) = record_input {
+            // Unbuffered, so everything read so far is saved however the run ends.
+            let Ok(record) = File::create(path) else {
+                eprintln!("couldn't create input recording");
+                return ExitCode::FAILURE;
+            };
+            input = Box::new(InputRecorder { inner: input, record });
+        }
+        let output: Box<dyn Write> = match (quiet, flush_every) {
+            (true, _) => Box::new(io::sink()),
+            (false, None) => Box::new(BufWriter::new(io::stdout().lock())),
+            (false, Some(every)) => Box::new(FlushEvery { inner: BufWriter::new(io::stdout().lock()), every, unflushed: 0 }),
+        };
+        (input, output)
+    };
+    // Every run of --repeat reads the same input, so it's read up front and replayed.
+    let mut replay = None;
+    if repeat > 1 && !count_only && check_halts.is_none() {
+        let mut recorded = Vec::new();
+        if input.read_to_end(&mut recorded).is_err() {
+            eprintln!("couldn't read input");
+            return ExitCode::FAILURE;
+        }
+        replay = Some(recorded);
+    }
+    let debugger = if debug || !breakpoints.is_empty() {
+        // stdin belongs to the program, so debugger commands come from the terminal directly.
+        let Ok(tty) = File::open("/dev/tty") else {
+            eprintln!("the debugger needs a terminal at /dev/tty");
+            return ExitCode::FAILURE;
+        };
+        
+        Some(Debugger::new(BufReader::new(tty), io::stderr(), breakpoints, debug))
+    } else {
+        None
+    };
+    let profiler = profile.map(|_| Profiler::new(&program));
+    let dump = (memory_dump || debug_char).then(|| MemoryDump::new(io::stderr(), options.cell_width, memory_dump));
+    let tracer = trace.map(|limit| Tracer::new(BufWriter::new(io::stderr()), limit));
+    let max_pointer = report_max_pointer.then(MaxPointer::new);
+    let histogram = loop_histogram.then(LoopHistogram::new);
+    let visualizer = visualize.map(|delay| Visualizer::new(BufWriter::new(io::stderr()), delay, options.cell_width));
+    
+    let mut hooks = (debugger, profiler, dump, tracer, max_pointer, histogram, visualizer);
+    let mut durations = Vec::new();
+    // Each repetition starts over on a fresh tape, stopping early if a run fails.
+    let (machine, outcome) = loop {
+        let mut machine = match &checkpoint {
+            None => Machine::new(memory_size, &options),
+            Some(checkpoint) => Machine::restore(checkpoint, &options),
+        };
+        if let Some(recorded) = &replay {
+            input = Box::new(io::Cursor::new(recorded.clone()));
+        }
+        let mut input: Box<dyn Read + '_> = match echo_input {
+            true => Box::new(InputEcho { inner: &mut input, echo: io::stderr() }),
+            false => Box::new(&mut input),
+        };
+        let started = Instant::now();
+        let outcome = match hooks {
+            (None, None, None, None, None, None, None) => machine.resume(&program, start, &options, &mut input, &mut output, &mut ()),
+            _ => machine.resume(&program, start, &options, &mut input, &mut output, &mut hooks),
+        };
+        durations.push(started.elapsed());
+        if durations.len() as u64 == repeat || !matches!(outcome.result, ExecutionResult::Ok) {
+            break (machine, outcome);
+        }
+    };
+    if time {
+        eprintln!("{}", speed(outcome.steps, durations[durations.len() - 1]));
+    }
+    if repeat > 1 {
+        eprintln!("{}", timing_summary(&mut durations));
+    }
+    
+    if let (Some(path), ExecutionResult::StepLimitExceeded | ExecutionResult::Timeout | ExecutionResult::Interrupted) = (&save_state, &outcome.result) {
+        let saved = File::create(path).and_then(|file| {
+            let mut file = BufWriter::new(file);
+            Checkpoint { program_hash: hash, ..machine.checkpoint(&program, outcome.program_counter) }.write_to(&mut file)?;
+            file.flush()
+        });
+        match saved {
+            Ok(()) => eprintln!("saved state to {}", path.display()),
+            Err(_) => eprintln!("couldn't save state"),
+        }
+    }
+    
+    if let (Some(profiler), Some(top)) = (&hooks.1, profile) {
+        if profiler.report(&program, top, &mut io::stderr().lock()).is_err() {
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(histogram) = &hooks.5 {
+        if histogram.report(&mut io::stderr().lock()).is_err() {
+            return ExitCode::FAILURE;
+        }
+    }
+    if let Some(max_pointer) = &hooks.4 {
+        match max_pointer.max() {
+            Some(max) => eprintln!("max cell index used: {max}"),
+            None => eprintln!("no cells used"),
+        }
+    }
+    if count_only {
+        println!("{} instructions executed", outcome.steps);
+    }
+    
+    if check_halts.is_some() {
+        match outcome.result {
+            ExecutionResult::Ok => {
+                println!("halts");
+                return ExitCode::SUCCESS;
+            }
+            ExecutionResult::StepLimitExceeded => {
+                println!("running");
+                return ExitCode::from(2);
+            }
+            _ => {}
+        }
+    }
+    
+    if let (Some(cells), ExecutionResult::Ok) = (dump_memory, &outcome.result) {
+        if MemoryDump::new(io::stderr(), options.cell_width, false).dump_cells(&outcome, cells).is_err() {
+            return ExitCode::FAILURE;
+        }
+    }
+    if let (true, ExecutionResult::Ok) = (checksum, &outcome.result) {
+        eprintln!("{}", checksum_line(&outcome));
+    }
+    
+    if let ExecutionResult::Interrupted = outcome.result {
+        eprintln!("interrupted");
+        return match MemoryDump::new(io::stderr(), options.cell_width, false).dump_outcome(&program, &outcome) {
+            // The exit status shells use for processes killed by SIGINT.
+            Ok(()) => ExitCode::from(130),
+            Err(_) => ExitCode::FAILURE,
+        };
+    }
+    
+    match error_message(outcome.result) {
+        None if exit_from_cell => ExitCode::from(cell_exit_status(outcome.current_cell)),
+        None => ExitCode::SUCCESS,
+        Some(message) => {
+            eprintln!("{message}");
             ExitCode::FAILURE
         }
     }
@@ -211,29 +1158,706 @@ fn main() -> ExitCode {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bfint::{parse_data, run_program_outcome};
+    
+    fn args(args: &[&str]) -> Option<Args> {
+        args_with_env(args, None)
+    }
+    
+    fn args_with_env(args: &[&str], env_memory_size: Option<&str>) -> Option<Args> {
+        parse_args(std::iter::once("bfint").chain(args.iter().copied()).map(String::from), env_memory_size.map(String::from))
+    }
+    
+    #[test]
+    fn positional_args() {
+        let args = args(&["prog.bf", "30000"]).unwrap();
+        assert_eq!(args.paths, [PathBuf::from("prog.bf")]);
+        assert_eq!(args.memory_size, 30000);
+        assert_eq!(args.options.eof_behavior, EofBehavior::Zero);
+    }
+    
+    #[test]
+    fn eof_flag() {
+        assert_eq!(args(&["--eof", "unchanged", "prog.bf", "10"]).unwrap().options.eof_behavior, EofBehavior::Unchanged);
+        assert_eq!(args(&["prog.bf", "10", "--eof", "-1"]).unwrap().options.eof_behavior, EofBehavior::NegativeOne);
+        assert!(args(&["prog.bf", "10", "--eof", "maybe"]).is_none());
+        assert!(args(&["prog.bf", "10", "--eof"]).is_none());
+    }
+    
+    #[test]
+    fn cell_width_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().options.cell_width, CellWidth::U8);
+        assert_eq!(args(&["prog.bf", "10", "--cell-width", "16"]).unwrap().options.cell_width, CellWidth::U16);
+        assert!(args(&["prog.bf", "10", "--cell-width", "64"]).is_none());
+    }
+    
+    #[test]
+    fn tape_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().options.tape_mode, TapeMode::Fixed);
+        assert_eq!(args(&["prog.bf", "10", "--tape", "auto"]).unwrap().options.tape_mode, TapeMode::Growing);
+        assert_eq!(args(&["prog.bf", "10", "--tape", "bidirectional"]).unwrap().options.tape_mode, TapeMode::Bidirectional);
+        assert_eq!(args(&["prog.bf", "10", "--tape", "circular"]).unwrap().options.tape_mode, TapeMode::Circular);
+        assert_eq!(args(&["prog.bf", "10", "--wrap-pointer"]).unwrap().options.tape_mode, TapeMode::Circular);
+    }
+    
+    #[test]
+    fn cap_index_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().options.cap_index, None);
+        assert_eq!(args(&["prog.bf", "10", "--cap-index", "4"]).unwrap().options.cap_index, Some(4));
+        assert!(args(&["prog.bf", "10", "--cap-index"]).is_none());
+        assert!(args(&["prog.bf", "10", "--tape", "infinite"]).is_none());
+    }
+    
+    #[test]
+    fn cell_overflow_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().options.cell_overflow, CellOverflow::Wrap);
+        assert_eq!(args(&["prog.bf", "10", "--cell-overflow", "trap"]).unwrap().options.cell_overflow, CellOverflow::Trap);
+        assert!(args(&["prog.bf", "10", "--cell-overflow", "saturate"]).is_none());
+    }
+    
+    #[test]
+    fn init_memory_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().init_memory, None);
+        assert_eq!(args(&["prog.bf", "10", "--init-memory", "tape.bin"]).unwrap().init_memory, Some(PathBuf::from("tape.bin")));
+        assert!(args(&["prog.bf", "10", "--init-memory"]).is_none());
+    }
+    
+    #[test]
+    fn fill_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().fill, None);
+        assert_eq!(args(&["prog.bf", "10", "--fill", "7"]).unwrap().fill, Some(7));
+        assert!(args(&["prog.bf", "10", "--fill", "256"]).is_none());
+        assert!(args(&["prog.bf", "10", "--fill"]).is_none());
+    }
+    
+    #[test]
+    fn fill() {
+        let mut options = RunOptions { initial_memory: b"ab".to_vec(), ..RunOptions::default() };
+        options.initial_memory.resize(5, 7);
+        let outcome = run_program_outcome(&[], 5, &options, &mut io::empty(), &mut Vec::new(), &mut ());
+        assert_eq!(outcome.memory, [b'a'.into(), b'b'.into(), 7, 7, 7]);
+    }
+    
+    #[test]
+    fn numeric_io_flags() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().options.numeric_io, None);
+        assert_eq!(args(&["prog.bf", "10", "--numeric-io"]).unwrap().options.numeric_io, Some(NumericIo { malformed_as_zero: false, signed: false }));
+        assert_eq!(args(&["--malformed-input", "zero", "prog.bf", "10"]).unwrap().options.numeric_io, Some(NumericIo { malformed_as_zero: true, signed: false }));
+        assert_eq!(args(&["prog.bf", "10", "--signed-cells"]).unwrap().options.numeric_io, Some(NumericIo { malformed_as_zero: false, signed: true }));
+    }
+    
+    #[test]
+    fn output_flags() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().options.utf8_output, None);
+        assert_eq!(args(&["prog.bf", "10", "--output", "utf8"]).unwrap().options.utf8_output, Some(Utf8Output { replace_invalid: false }));
+        assert_eq!(args(&["prog.bf", "10", "--invalid-codepoint", "replace"]).unwrap().options.utf8_output, Some(Utf8Output { replace_invalid: true }));
+        assert_eq!(args(&["prog.bf", "10", "--output", "utf8", "--output", "raw"]).unwrap().options.utf8_output, None);
+        assert!(args(&["prog.bf", "10", "--output", "latin1"]).is_none());
+    }
+    
+    #[test]
+    fn ignore_sigpipe_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().options.ignore_broken_pipe);
+        assert!(args(&["prog.bf", "10", "--ignore-sigpipe"]).unwrap().options.ignore_broken_pipe);
+        assert!(args(&["prog.bf", "10", "--no-io"]).unwrap().options.no_io);
+    }
+    
+    #[test]
+    fn crlf_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().options.crlf);
+        assert!(args(&["prog.bf", "10", "--crlf"]).unwrap().options.crlf);
+        assert!(args(&["prog.bf", "10", "--ascii-mask"]).unwrap().options.ascii_mask);
+        assert!(args(&["prog.bf", "10", "--ascii-mask", "--crlf"]).is_none());
+        assert!(args(&["prog.bf", "10", "--output", "utf8", "--ascii-mask"]).is_none());
+    }
+    
+    #[test]
+    fn max_output_bytes_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().options.max_output, None);
+        assert_eq!(args(&["prog.bf", "10", "--max-output-bytes", "4096"]).unwrap().options.max_output, Some(4096));
+        assert!(args(&["prog.bf", "10", "--max-output-bytes", "-1"]).is_none());
+    }
+    
+    #[test]
+    fn max_steps_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().options.max_steps, None);
+        assert_eq!(args(&["prog.bf", "10", "--max-steps", "500"]).unwrap().options.max_steps, Some(500));
+        assert!(args(&["prog.bf", "10", "--max-steps", "lots"]).is_none());
+    }
+    
+    #[test]
+    fn threading_skipped_for_hooks() {
+        assert!(threads_jumps(&args(&["prog.bf"]).unwrap()));
+        assert!(!threads_jumps(&args(&["prog.bf", "--no-optimize"]).unwrap()));
+        let watching: [&[&str]; 9] = [
+            &["--debug"],
+            &["--break", "3"],
+            &["--profile", "5"],
+            &["--trace"],
+            &["--visualize=0"],
+            &["--enable-debug-char"],
+            &["--memory-dump-on-error"],
+            &["--dump-memory", "8"],
+            &["--loop-histogram"],
+        ];
+        for flags in watching {
+            assert!(!threads_jumps(&args(&[&["prog.bf"], flags].concat()).unwrap()), "{flags:?}");
+        }
+        
+        // A profile of nested loops sees every bracket of the source, which it wouldn't if the jumps were threaded.
+        let source = b">++<+[[-.]>[-<+>]<]";
+        let profile = |threaded| {
+            let mut program = parse_data(source).unwrap();
+            if threaded {
+                thread_jumps(&mut program);
+            }
+            let mut profiler = Profiler::new(&program);
+            let outcome = run_program_outcome(&program, 2, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut profiler);
+            assert!(matches!(outcome.result, ExecutionResult::Ok));
+            (0..program.len()).map(|idx| (profiler.executions(idx), profiler.back_jumps(idx))).collect::<Vec<_>>()
+        };
+        let profiled = args(&["prog.bf", "--profile", "5"]).unwrap();
+        assert_eq!(profile(threads_jumps(&profiled)), profile(false));
+        assert_ne!(profile(true), profile(false));
+    }
+    
+    #[test]
+    fn checksum() {
+        assert!(args(&["prog.bf", "10", "--checksum"]).unwrap().checksum);
+        // FNV-1a over the cells up to the last nonzero one, 3, 0 and 2, each as four little-endian bytes.
+        let program = parse_data(b"+++>>++>+-").unwrap();
+        for memory_size in [3, 10, 30000] {
+            let outcome = run_program_outcome(&program, memory_size, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut ());
+            assert_eq!(checksum_line(&outcome), "tape checksum: 09b5dc69cb732424");
+        }
+    }
+    
+    #[test]
+    fn report_max_pointer_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().report_max_pointer);
+        assert!(args(&["prog.bf", "10", "--report-max-pointer"]).unwrap().report_max_pointer);
+    }
+    
+    #[test]
+    fn memory_dump_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().memory_dump);
+        assert!(args(&["prog.bf", "10", "--memory-dump-on-error"]).unwrap().memory_dump);
+        assert_eq!(args(&["prog.bf", "10", "--dump-memory", "32"]).unwrap().dump_memory, Some(32));
+        assert!(args(&["prog.bf", "10", "--dump-memory"]).is_none());
+    }
+    
+    #[test]
+    fn trace_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().trace, None);
+        assert_eq!(args(&["prog.bf", "10", "--trace"]).unwrap().trace, Some(None));
+        assert_eq!(args(&["prog.bf", "--trace=50", "10"]).unwrap().trace, Some(Some(50)));
+        assert!(args(&["prog.bf", "10", "--trace=many"]).is_none());
+    }
+    
+    #[test]
+    fn visualize_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().visualize, None);
+        assert_eq!(args(&["prog.bf", "10", "--visualize"]).unwrap().visualize, Some(DEFAULT_VISUALIZE_DELAY));
+        assert_eq!(args(&["prog.bf", "--visualize=0", "10"]).unwrap().visualize, Some(Duration::ZERO));
+        assert!(args(&["prog.bf", "10", "--visualize=slow"]).is_none());
+    }
+    
+    #[test]
+    fn debug_char_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().debug_char);
+        assert!(args(&["prog.bf", "10", "--enable-debug-char"]).unwrap().debug_char);
+    }
+    
+    #[test]
+    fn strict_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().strict);
+        assert!(args(&["prog.bf", "10", "--strict"]).unwrap().strict);
+    }
+    
+    #[test]
+    fn exit_from_cell_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().exit_from_cell);
+        assert!(args(&["prog.bf", "10", "--exit-from-cell"]).unwrap().exit_from_cell);
+    }
+    
+    #[test]
+    fn cell_exit_statuses() {
+        let status = |source: &[u8], cell_width| {
+            let options = RunOptions { cell_width, ..RunOptions::default() };
+            let program = parse_data_with_options(source, &ParseOptions { cell_width, ..ParseOptions::default() }).unwrap();
+            cell_exit_status(run_program_outcome(&program, 10, &options, &mut io::empty(), &mut Vec::new(), &mut ()).current_cell)
+        };
+        // The cell under the pointer when the program ends, not the first or the largest.
+        assert_eq!(status(b"+++>++++++<", CellWidth::U8), 3);
+        assert_eq!(status(b"-", CellWidth::U8), 255);
+        // Wider cells only keep their low byte, since that's all an exit status holds.
+        assert_eq!(status(&[b'+'; 300], CellWidth::U16), 44);
+    }
+    
+    #[test]
+    fn no_optimize_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().no_optimize);
+        assert!(args(&["prog.bf", "10", "--no-optimize"]).unwrap().no_optimize);
+    }
+    
+    #[test]
+    fn max_nesting_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().max_nesting, None);
+        assert_eq!(args(&["prog.bf", "10", "--max-nesting", "64"]).unwrap().max_nesting, Some(64));
+        assert!(args(&["prog.bf", "10", "--max-nesting", "deep"]).is_none());
+    }
+    
+    #[test]
+    fn count_only_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().count_only);
+        assert!(args(&["prog.bf", "10", "--count-only"]).unwrap().count_only);
+    }
+    
+    #[test]
+    fn interruptible_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().interruptible);
+        assert!(args(&["prog.bf", "10", "--interruptible"]).unwrap().interruptible);
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn interrupts() {
+        extern "C" {
+            fn raise(signum: i32) -> i32;
+        }
+        assert!(handle_interrupts());
+        // SAFETY: the handler installed above only stores to an atomic.
+        assert_eq!(unsafe { raise(2) }, 0);
+        assert!(INTERRUPTED.load(Ordering::Relaxed));
+        
+        // The program never ends on its own, so only the interrupt stops it.
+        let program = parse_data(b"+[>+<]").unwrap();
+        let options = RunOptions { interrupt: Some(&INTERRUPTED), ..RunOptions::default() };
+        let outcome = run_program_outcome(&program, 10, &options, &mut io::empty(), &mut Vec::new(), &mut ());
+        assert!(matches!(outcome.result, ExecutionResult::Interrupted));
+    }
+    
+    #[test]
+    fn self_input_conflicts() {
+        assert!(args(&["prog.bf", "10", "--self-input"]).unwrap().self_input);
+        assert!(args(&["prog.bf", "10", "--self-input", "--input", "x"]).is_none());
+        assert!(args(&["prog.bf", "10", "--self-input", "--bang-input"]).is_none());
+    }
+    
+    #[test]
+    fn input_sources() {
+        let read = |source: &[u8], bundled: &[u8], inline: Option<&[u8]>, replayed: Option<&[u8]>, self_input| {
+            let mut read = Vec::new();
+            input_source(source.to_vec(), bundled.to_vec(), inline.map(<[u8]>::to_vec), replayed.map(<[u8]>::to_vec), self_input, &b"stdin"[..]).read_to_end(&mut read).unwrap();
+            read
+        };
+        assert_eq!(read(b",[.,]", b"", None, None, true), b",[.,]");
+        assert_eq!(read(b",[.,]!ab", b"ab", Some(b"cd"), None, false), b"abcd");
+        assert_eq!(read(b",[.,]!ab", b"ab", None, None, false), b"abstdin");
+        assert_eq!(read(b",[.,]", b"", None, None, false), b"stdin");
+        assert_eq!(read(b",[.,]!ab", b"ab", None, Some(b"abc"), false), b"abc");
+    }
+    
+    #[test]
+    fn werror_infinite_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().werror_infinite);
+        assert!(args(&["prog.bf", "10", "--werror-infinite"]).unwrap().werror_infinite);
+    }
+    
+    #[test]
+    fn werror_infinite() {
+        let parse = |source: &[u8], werror_infinite| {
+            parse_data_with_options(source, &ParseOptions { empty_loops: empty_loops(werror_infinite), ..ParseOptions::default() }).map_err(|err| err.kind)
+        };
+        // Without the flag, these only warn and still run.
+        assert!(parse(b"+[]", false).is_ok());
+        assert!(parse(b"+[>+<]", false).is_ok());
+        assert_eq!(parse(b"+[]", true).unwrap_err(), ParseErrorKind::EmptyLoop);
+        assert_eq!(parse(b"+[>+<]", true).unwrap_err(), ParseErrorKind::EndlessLoop);
+        assert!(parse(b"+[-]", true).is_ok());
+    }
+    
+    #[test]
+    fn echo_input_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().echo_input);
+        assert!(args(&["prog.bf", "10", "--echo-input"]).unwrap().echo_input);
+    }
+    
+    #[test]
+    fn echo_input() {
+        let mut echo = Vec::new();
+        let mut input = InputEcho { inner: &b"hi\n!"[..], echo: &mut echo };
+        let outcome = run_program_outcome(&parse_data(b",,,").unwrap(), 1, &RunOptions::default(), &mut input, &mut Vec::new(), &mut ());
+        assert!(matches!(outcome.result, ExecutionResult::Ok));
+        assert_eq!(String::from_utf8(echo).unwrap(), "input: 0x68 'h'\ninput: 0x69 'i'\ninput: 0x0a '\\n'\n");
+    }
+    
+    #[test]
+    fn record_input_flags() {
+        let parsed = args(&["prog.bf", "10", "--record-input", "in.rec"]).unwrap();
+        assert_eq!((parsed.record_input, parsed.replay_input), (Some(PathBuf::from("in.rec")), None));
+        assert_eq!(args(&["prog.bf", "10", "--replay-input", "in.rec"]).unwrap().replay_input, Some(PathBuf::from("in.rec")));
+        assert!(args(&["prog.bf", "10", "--replay-input", "in.rec", "--input", "x"]).is_none());
+        assert!(args(&["prog.bf", "10", "--replay-input", "in.rec", "--self-input"]).is_none());
+    }
+    
+    #[test]
+    fn record_then_replay() {
+        // Prints each byte of the first line shifted up by one, stopping at the newline.
+        let program = parse_data(b",----------[+++++++++++.,----------]").unwrap();
+        let run = |mut input: &mut dyn Read| {
+            let mut output = Vec::new();
+            let outcome = run_program_outcome(&program, 1, &RunOptions::default(), &mut input, &mut output, &mut ());
+            assert!(matches!(outcome.result, ExecutionResult::Ok));
+            output
+        };
+        let mut record = Vec::new();
+        let recorded_output = run(&mut InputRecorder { inner: &b"hey\nignored"[..], record: &mut record });
+        assert_eq!((&record[..], &recorded_output[..]), (&b"hey\n"[..], &b"ifz"[..]));
+        assert_eq!(run(&mut &record[..]), recorded_output);
+    }
+    
+    #[test]
+    fn allow_silent_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().allow_silent);
+        assert!(args(&["prog.bf", "10", "--allow-silent"]).unwrap().allow_silent);
+    }
+    
+    #[test]
+    fn silent_runs() {
+        assert!(!silent_on_purpose(&args(&["prog.bf", "10"]).unwrap()));
+        assert!(!silent_on_purpose(&args(&["prog.bf", "10", "--time"]).unwrap()));
+        for flags in [&["--allow-silent"][..], &["--no-io"], &["--count-only"], &["--check-halts", "100"], &["--exit-from-cell"], &["--checksum"], &["--dump-ir"]] {
+            let args = args(&[&["prog.bf", "10"][..], flags].concat()).unwrap();
+            assert!(silent_on_purpose(&args), "{flags:?}");
+        }
+    }
+    
+    #[test]
+    fn validate_only() {
+        assert_eq!(validate(&b"+[->+<]."[..], &ParseOptions::default(), false), ("ok".to_string(), true));
+        assert_eq!(validate(&b"+[->+<."[..], &ParseOptions::default(), false), ("unclosed '[' at line 1, column 2".to_string(), false));
+        let strict = ParseOptions { strict: true, ..ParseOptions::default() };
+        assert!(!validate(&b"+ comment"[..], &strict, false).1);
+        assert!(validate(&b"#!/usr/bin/env bfint\n+"[..], &strict, false).1);
+        // IR is checked as IR, not as Brainfuck with every line a comment.
+        assert_eq!(validate(&b"add 1\nloop_start\nout"[..], &ParseOptions::default(), true), ("couldn't parse IR: unmatched loop on line 2".to_string(), false));
+        assert_eq!(validate(&b"add 1\nout"[..], &ParseOptions::default(), true), ("ok".to_string(), true));
+        assert!(!validate(&b"+[->+<]."[..], &ParseOptions::default(), true).1);
+        assert!(!validate(&b"\xff"[..], &ParseOptions::default(), true).1);
+    }
+    
+    #[test]
+    fn silent_programs() {
+        assert!(is_silent(&parse_data(b"++[->+<]").unwrap()));
+        assert!(is_silent(&parse_data(b"").unwrap()));
+        assert!(!is_silent(&parse_data(b"++[->+<]>.").unwrap()));
+        assert!(!is_silent(&parse_data(b"+[,.]").unwrap()));
+        assert!(!is_silent(&parse_data(b",[-]").unwrap()));
+    }
+    
+    #[test]
+    fn repeat_flags() {
+        let default = args(&["prog.bf", "10"]).unwrap();
+        assert_eq!((default.repeat, default.quiet), (1, false));
+        let repeat = args(&["prog.bf", "10", "--repeat", "20", "--quiet"]).unwrap();
+        assert_eq!((repeat.repeat, repeat.quiet), (20, true));
+        assert!(args(&["prog.bf", "10", "--repeat", "0"]).is_none());
+    }
+    
+    #[test]
+    fn timings() {
+        let mut durations = [5, 1, 4, 2, 3].map(Duration::from_millis);
+        assert_eq!(timing_summary(&mut durations), "5 runs: min 1ms, median 3ms, max 5ms");
+        assert_eq!(timing_summary(&mut [Duration::from_micros(1500)]), "1 run: min 1.5ms, median 1.5ms, max 1.5ms");
+    }
+    
+    #[test]
+    fn time_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().time);
+        assert!(args(&["prog.bf", "10", "--time"]).unwrap().time);
+    }
+    
+    #[test]
+    fn speeds() {
+        assert_eq!(speed(3_000_000, Duration::from_millis(20)), "executed 3000000 instructions in 20.000 ms (150.0 Minsn/s)");
+        assert_eq!(speed(0, Duration::ZERO), "executed 0 instructions in 0.000 ms (0.0 Minsn/s)");
+    }
+    
+    #[test]
+    fn line_input_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().line_input);
+        assert!(args(&["prog.bf", "10", "--line-input"]).unwrap().line_input);
+    }
+    
+    #[test]
+    fn line_input() {
+        let mut input = LineInput::new(&b"ab\ncd"[..]);
+        let mut byte = [0];
+        assert_eq!((input.read(&mut byte).unwrap(), byte), (1, *b"a"));
+        // The rest of the line is already buffered, and nothing past it.
+        assert_eq!((&input.line[..], input.consumed), (&b"ab\n"[..], 1));
+        
+        let mut output = Vec::new();
+        run_program_outcome(&parse_data(b",.,.,.,.,.").unwrap(), 1, &RunOptions::default(), &mut input, &mut output, &mut ());
+        assert_eq!(output, b"b\ncd\0");
+    }
+    
+    #[test]
+    fn flush_every() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().flush_every, None);
+        assert_eq!(args(&["prog.bf", "10", "--flush-every", "64"]).unwrap().flush_every, Some(64));
+        assert!(args(&["prog.bf", "10", "--flush-every", "0"]).is_none());
+        
+        /// Records how much had been written at each flush.
+        #[derive(Default)]
+        struct FlushLog {
+            written: usize,
+            flushes: Vec<usize>,
+        }
+        
+        impl Write for FlushLog {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.written += buf.len();
+                Ok(buf.len())
+            }
+            
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushes.push(self.written);
+                Ok(())
+            }
+        }
+        
+        let mut output = FlushEvery { inner: FlushLog::default(), every: 3, unflushed: 0 };
+        run_program_outcome(&parse_data(b"+.......,..").unwrap(), 1, &RunOptions::default(), &mut io::empty(), &mut output, &mut ());
+        // Reading input flushes too, starting the count over, and so does finishing.
+        assert_eq!(output.inner.flushes, [3, 6, 7, 9]);
+    }
+    
+    #[test]
+    fn input_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().inline_input, None);
+        assert_eq!(args(&["prog.bf", "10", "--input", ""]).unwrap().inline_input, Some(vec![]));
+        assert_eq!(args(&["prog.bf", "10", "--input", "a\\nb\\x00\\xfF\\\\"]).unwrap().inline_input, Some(b"a\nb\0\xff\\".to_vec()));
+        assert!(args(&["prog.bf", "10", "--input", "\\q"]).is_none());
+        assert!(args(&["prog.bf", "10", "--input", "\\x4"]).is_none());
+        assert!(args(&["prog.bf", "10", "--input", "\\x+f"]).is_none());
+        assert!(args(&["prog.bf", "10", "--input", "trailing\\"]).is_none());
+        
+        let input = args(&["prog.bf", "10", "--input", "AB"]).unwrap().inline_input.unwrap();
+        let mut output = Vec::new();
+        run_program_outcome(&parse_data(b",.,.,.").unwrap(), 1, &RunOptions::default(), &mut &input[..], &mut output, &mut ());
+        assert_eq!(output, b"AB\0");
+    }
+    
+    #[test]
+    fn from_ir_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().from_ir);
+        assert!(args(&["prog.ir", "10", "--from-ir"]).unwrap().from_ir);
+    }
+    
+    #[test]
+    fn bang_input_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().bang_input);
+        assert!(args(&["prog.bf", "10", "--bang-input"]).unwrap().bang_input);
+    }
+    
+    #[test]
+    fn check_halts_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().check_halts, None);
+        assert_eq!(args(&["prog.bf", "10", "--check-halts", "1000"]).unwrap().check_halts, Some(1000));
+        assert!(args(&["prog.bf", "10", "--check-halts"]).is_none());
+    }
+    
+    #[test]
+    fn dialect_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().dialect, None);
+        assert_eq!(args(&["prog.bf", "10", "--dialect", "ook.txt"]).unwrap().dialect, Some(PathBuf::from("ook.txt")));
+        assert!(args(&["prog.bf", "10", "--dialect"]).is_none());
+    }
+    
+    #[test]
+    fn state_flags() {
+        let none = args(&["prog.bf", "10"]).unwrap();
+        assert_eq!((none.save_state, none.load_state), (None, None));
+        let both = args(&["prog.bf", "10", "--save-state", "out.state", "--load-state", "in.state"]).unwrap();
+        assert_eq!(both.save_state, Some(PathBuf::from("out.state")));
+        assert_eq!(both.load_state, Some(PathBuf::from("in.state")));
+    }
+    
+    #[test]
+    fn debugger_flags() {
+        let breakpoints = args(&["prog.bf", "10", "--break", "3", "--break", "7"]).unwrap();
+        assert!(!breakpoints.debug);
+        assert_eq!(breakpoints.breakpoints, [3, 7]);
+        assert!(args(&["prog.bf", "10", "--debug"]).unwrap().debug);
+    }
+    
+    #[test]
+    fn timeout_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().options.timeout, None);
+        assert_eq!(args(&["prog.bf", "10", "--timeout-ms", "1500"]).unwrap().options.timeout, Some(Duration::from_millis(1500)));
+        assert!(args(&["prog.bf", "10", "--timeout-ms", "soon"]).is_none());
+    }
+    
+    #[test]
+    fn fold_io_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().fold_io, None);
+        assert_eq!(args(&["prog.bf", "10", "--fold-io"]).unwrap().fold_io, Some(0));
+        assert!(args(&["prog.bf", "10", "--fold-io=256"]).is_none());
+        assert_eq!(args(&["prog.bf", "10", "--fold-io=256", "--cell-width", "16"]).unwrap().fold_io, Some(256));
+        assert!(args(&["prog.bf", "10", "--fold-io=lots"]).is_none());
+        
+        // Every read gives the value, and nothing is written.
+        let value = args(&["prog.bf", "10", "--fold-io=65"]).unwrap().fold_io.unwrap();
+        let mut program = parse_data(b",.>,+.").unwrap();
+        strip_io(&mut program, value);
+        let mut output = Vec::new();
+        let outcome = run_program_outcome(&program, 2, &RunOptions::default(), &mut &b"xy"[..], &mut output, &mut ());
+        assert!(matches!(outcome.result, ExecutionResult::Ok));
+        assert_eq!((&outcome.memory[..], &output[..]), (&[65, 66][..], &b""[..]));
+    }
+    
+    #[test]
+    fn loop_histogram_flag() {
+        assert!(!args(&["prog.bf", "10"]).unwrap().loop_histogram);
+        assert!(args(&["prog.bf", "10", "--loop-histogram"]).unwrap().loop_histogram);
+    }
+    
+    #[test]
+    fn profile_flag() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().profile, None);
+        assert_eq!(args(&["prog.bf", "10", "--profile", "5"]).unwrap().profile, Some(5));
+    }
+    
+    #[test]
+    fn source_from_stdin() {
+        let mut source = Vec::new();
+        open_source(Path::new("-"), &b"+[,.]"[..]).unwrap().read_to_end(&mut source).unwrap();
+        assert_eq!(source, b"+[,.]");
+        assert!(open_source(Path::new("/nonexistent/prog.bf"), &b"+[,.]"[..]).is_err());
+    }
+    
+    #[test]
+    fn several_paths() {
+        let several = args(&["lib.bf", "main.bf", "30000"]).unwrap();
+        assert_eq!(several.paths, [PathBuf::from("lib.bf"), PathBuf::from("main.bf")]);
+        assert_eq!(several.memory_size, 30000);
+        assert!(args(&["30000"]).is_none());
+    }
+    
+    #[test]
+    fn loop_across_files() {
+        let dir = env::temp_dir().join(format!("bfint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths = [dir.join("open.bf"), dir.join("close.bf")];
+        fs::write(&paths[0], "++++++++[>++++++++").unwrap();
+        fs::write(&paths[1], "<-]>+.").unwrap();
+        let program = parse_reader_with_options(open_sources(&paths).unwrap(), &ParseOptions::default());
+        fs::remove_dir_all(&dir).unwrap();
+        
+        let mut output = Vec::new();
+        let outcome = run_program_outcome(&program.unwrap(), 2, &RunOptions::default(), &mut io::empty(), &mut output, &mut ());
+        assert!(matches!(outcome.result, ExecutionResult::Ok));
+        assert_eq!(output, b"A");
+    }
+    
+    #[test]
+    fn mode_flags() {
+        assert_eq!(args(&["prog.bf", "10"]).unwrap().mode, Mode::Run);
+        assert_eq!(args(&["prog.bf", "10", "--dump-ir"]).unwrap().mode, Mode::DumpIr);
+        assert_eq!(args(&["prog.bf", "10", "--emit-c"]).unwrap().mode, Mode::EmitC);
+        assert_eq!(args(&["prog.bf", "10", "--emit-wat"]).unwrap().mode, Mode::EmitWat);
+        assert_eq!(args(&["prog.bf", "10", "--emit-json"]).unwrap().mode, Mode::EmitJson);
+        assert_eq!(args(&["prog.bf", "10", "--emit-ir"]).unwrap().mode, Mode::EmitIr);
+        assert_eq!(args(&["prog.bf", "10", "--stats"]).unwrap().mode, Mode::Stats);
+        assert_eq!(args(&["prog.bf", "10", "--golf-stats"]).unwrap().mode, Mode::GolfStats);
+        assert!(args(&["prog.bf", "10", "--golf-stats", "--from-ir"]).is_none());
+        assert_eq!(args(&["prog.bf", "10", "--validate-only"]).unwrap().mode, Mode::Validate);
+        assert!(args(&["lint", "prog.bf", "--validate-only"]).is_none());
+    }
+    
+    #[test]
+    fn subcommands() {
+        let run = args(&["run", "a.bf", "b.bf", "--mem", "100", "--eof", "-1"]).unwrap();
+        assert_eq!(run.paths, [PathBuf::from("a.bf"), PathBuf::from("b.bf")]);
+        assert_eq!(run.memory_size, 100);
+        assert_eq!(run.mode, Mode::Run);
+        assert_eq!(run.options.eof_behavior, EofBehavior::NegativeOne);
+        assert_eq!(args(&["run", "prog.bf"]).unwrap().memory_size, DEFAULT_MEMORY_SIZE);
+        assert_eq!(args(&["dump", "prog.bf"]).unwrap().mode, Mode::DumpIr);
+        assert_eq!(args(&["transpile", "prog.bf"]).unwrap().mode, Mode::EmitC);
+        assert_eq!(args(&["transpile", "prog.bf", "--to", "wat"]).unwrap().mode, Mode::EmitWat);
+        assert_eq!(args(&["transpile", "prog.bf", "--to", "json"]).unwrap().mode, Mode::EmitJson);
+        assert_eq!(args(&["transpile", "prog.bf", "--to", "ir"]).unwrap().mode, Mode::EmitIr);
+        assert!(args(&["transpile", "prog.bf", "--to", "wasm"]).is_none());
+        assert_eq!(args(&["lint", "prog.bf"]).unwrap().mode, Mode::Lint);
+        assert!(args(&["run"]).is_none());
+        assert!(args(&["run", "prog.bf", "--dump-ir"]).is_none());
+        assert!(args(&["run", "prog.bf", "--golf-stats"]).is_none());
+        assert!(args(&["dump", "prog.bf", "--repl"]).is_none());
+        assert!(args(&["prog.bf", "10", "--to", "c"]).is_none());
+        
+        // `--mem` works without a subcommand too, which leaves every positional argument a path.
+        assert_eq!(args(&["-m", "20", "prog.bf"]).unwrap().memory_size, 20);
+        let legacy = args(&["prog.bf", "10", "--mem", "20"]).unwrap();
+        assert_eq!(legacy.paths, [PathBuf::from("prog.bf"), PathBuf::from("10")]);
+        assert_eq!(legacy.memory_size, 20);
+    }
+    
+    #[test]
+    fn default_memory_size() {
+        let default = args(&["prog.bf"]).unwrap();
+        assert_eq!(default.paths, [PathBuf::from("prog.bf")]);
+        assert_eq!(default.memory_size, DEFAULT_MEMORY_SIZE);
+        assert_eq!(args(&["--eof", "zero", "prog.bf", "--crlf"]).unwrap().memory_size, DEFAULT_MEMORY_SIZE);
+        assert_eq!(args(&["prog.bf", "--mem", "64"]).unwrap().memory_size, 64);
+        assert_eq!(args(&["--mem", "64", "prog.bf", "--crlf"]).unwrap().memory_size, 64);
+        assert_eq!(args(&["prog.bf", "64"]).unwrap().memory_size, 64);
+        assert_eq!(args(&["--repl"]).unwrap().memory_size, DEFAULT_MEMORY_SIZE);
+        assert!(args(&["prog.bf", "--mem", "lots"]).is_none());
+    }
     
     #[test]
-    fn invalid_memory_access() {
-        assert_eq!(run_program(&parse_data(b">+").unwrap(), 1), ExecutionResult::MemoryAccessError);
-        assert_eq!(run_program(&parse_data(b"<+").unwrap(), 1), ExecutionResult::MemoryAccessError);
-        assert_eq!(run_program(&parse_data(b"<>+").unwrap(), 1), ExecutionResult::Ok);
-        assert_eq!(run_program(&parse_data(b">[]").unwrap(), 1), ExecutionResult::MemoryAccessError);
+    fn env_memory_size() {
+        // An explicit size wins, however it's given.
+        assert_eq!(args_with_env(&["prog.bf", "64"], Some("128")).unwrap().memory_size, 64);
+        assert_eq!(args_with_env(&["prog.bf", "--mem", "64"], Some("128")).unwrap().memory_size, 64);
+        assert_eq!(args_with_env(&["run", "prog.bf", "-m", "64"], Some("128")).unwrap().memory_size, 64);
+        // Then BFINT_MEM.
+        assert_eq!(args_with_env(&["prog.bf"], Some("128")).unwrap().memory_size, 128);
+        assert_eq!(args_with_env(&["--repl"], Some("128")).unwrap().memory_size, 128);
+        // Then the default, if it isn't a number.
+        assert_eq!(args_with_env(&["prog.bf"], Some("lots")).unwrap().memory_size, DEFAULT_MEMORY_SIZE);
+        assert_eq!(args_with_env(&["prog.bf"], Some("")).unwrap().memory_size, DEFAULT_MEMORY_SIZE);
+        assert_eq!(args_with_env(&["prog.bf"], None).unwrap().memory_size, DEFAULT_MEMORY_SIZE);
     }
     
     #[test]
-    fn optimizations() {
-        assert_eq!(parse_data(b"++++++.---,").unwrap(), [BFInstruction::Add(6), BFInstruction::Output, BFInstruction::Add(253), BFInstruction::Input]);
+    fn repl_flag() {
+        let repl = args(&["--repl", "100"]).unwrap();
+        assert_eq!(repl.mode, Mode::Repl);
+        assert!(repl.paths.is_empty());
+        assert_eq!(repl.memory_size, 100);
+        assert!(args(&["--repl", "prog.bf", "100"]).is_none());
     }
     
     #[test]
-    fn dead_code_elimination() {
-        assert_eq!(parse_data(b"++--+-+--+<>>><<").unwrap(), []);
+    fn overrun_messages() {
+        let run = |source: &[u8]| error_message(run_program_outcome(&parse_data(source).unwrap(), 2, &RunOptions::default(), &mut io::empty(), &mut io::sink(), &mut ()).result);
+        assert_eq!(run(b">>+").as_deref(), Some("memory access error at instruction 1: past the end of the tape"));
+        assert_eq!(run(b"<+").as_deref(), Some("memory access error at instruction 1: left of the start of the tape"));
+        assert_eq!(run(b"<>+"), None);
     }
     
     #[test]
-    fn invalid_loops() {
-        assert!(parse_data(b"][").is_none());
-        assert!(parse_data(b"[[]").is_none());
-        assert!(parse_data(b"[]]").is_none());
+    fn repl_keeps_tape() {
+        let mut machine = Machine::new(10, &RunOptions::default());
+        let mut output = Vec::new();
+        let input = b"+++++++[>++++++++++<-]>\n[\n.+.\n,.\nA\n";
+        repl(&mut machine, &RunOptions::default(), &ParseOptions::default(), &mut &input[..], &mut output).unwrap();
+        assert_eq!(output, b"> > > FG\n> A\n> > \n");
+        assert_eq!(machine.data_pointer(), 1);
+        assert_eq!(machine.cell(1), Some(65));
     }
 }
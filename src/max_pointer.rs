@@ -0,0 +1,97 @@
+use std::ops::ControlFlow;
+
+use crate::interpreter::{ExecutionResult, Hook, MachineState};
+use crate::parser::BFInstruction;
+
+/// Records the highest cell index a program reads or writes, to help choose a tape size.
+///
+/// A failed access past the end of the tape counts too, showing how far the program tried to go.
+///
+/// Indices are signed, so cells left of the starting one on a bidirectional tape count as negative.
+/// Cells the data pointer only passes over with `>` aren't counted, while the cells `MulAdd`
+/// and the `...AtOffset` instructions change at an offset are.
+#[derive(Default)]
+pub struct MaxPointer {
+    max: Option<isize>,
+    /// Set while a scan runs, since only its final position is known afterwards.
+    scanning: bool,
+}
+
+impl MaxPointer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// The highest cell index used so far, or `None` if the program hasn't touched a cell.
+    pub fn max(&self) -> Option<isize> {
+        self.max
+    }
+    
+    fn touch(&mut self, pointer: isize) {
+        self.max = Some(self.max.map_or(pointer, |max| max.max(pointer)));
+    }
+}
+
+impl Hook for MaxPointer {
+    fn before_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        let data_pointer = state.data_pointer as isize;
+        match state.program[state.program_counter] {
+            BFInstruction::IncrementPointer(_) => {}
+            BFInstruction::AddAtOffset { offset, .. } | BFInstruction::ClearCellAtOffset(offset) | BFInstruction::SetCellAtOffset { offset, .. } => {
+                self.touch(data_pointer.wrapping_add(offset))
+            }
+            BFInstruction::MulAdd(ref targets) => {
+                self.touch(data_pointer);
+                // The targets are only changed if the current cell isn't zero.
+                if state.cell(state.data_pointer).is_some_and(|value| value != 0) {
+                    for &(offset, _) in targets.iter() {
+                        self.touch(data_pointer.wrapping_add(offset));
+                    }
+                }
+            }
+            BFInstruction::ScanRight(_) | BFInstruction::ScanLeft(_) => {
+                self.touch(data_pointer);
+                self.scanning = true;
+            }
+            _ => self.touch(data_pointer),
+        }
+        ControlFlow::Continue(())
+    }
+    
+    fn after_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        if self.scanning {
+            self.scanning = false;
+            self.touch(state.data_pointer as isize);
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{RunOptions, run_program_with_hook};
+    use crate::parser::parse_data;
+    use crate::tape::TapeMode;
+    use std::io;
+    
+    fn max(source: &[u8], tape_mode: TapeMode) -> (ExecutionResult, Option<isize>) {
+        let mut max_pointer = MaxPointer::new();
+        let options = RunOptions { tape_mode, ..RunOptions::default() };
+        let result = run_program_with_hook(&parse_data(source).unwrap(), 8, &options, &mut io::empty(), &mut Vec::new(), &mut max_pointer);
+        (result, max_pointer.max())
+    }
+    
+    #[test]
+    fn highest_cell() {
+        assert_eq!(max(b"", TapeMode::Fixed), (ExecutionResult::Ok, None));
+        assert_eq!(max(b">>+>>", TapeMode::Fixed), (ExecutionResult::Ok, Some(2)));
+        assert_eq!(max(b">>>+<<<", TapeMode::Fixed), (ExecutionResult::Ok, Some(3)));
+        assert_eq!(max(b"+[->>>+<<<]", TapeMode::Fixed), (ExecutionResult::Ok, Some(3)));
+        assert_eq!(max(b"[->>>+<<<]", TapeMode::Fixed), (ExecutionResult::Ok, Some(0)));
+        assert_eq!(max(b"+>+>+>+<<<[>]", TapeMode::Fixed), (ExecutionResult::Ok, Some(4)));
+        assert_eq!(max(b"+[>+]", TapeMode::Fixed), (ExecutionResult::MemoryAccessError(3), Some(8)));
+        assert_eq!(max(b"<<+>+", TapeMode::Bidirectional), (ExecutionResult::Ok, Some(-1)));
+        assert_eq!(max(b">>>>>>>>>>+", TapeMode::Growing), (ExecutionResult::Ok, Some(10)));
+    }
+}
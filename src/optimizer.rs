@@ -0,0 +1,250 @@
+//! Passes that rewrite already parsed programs.
+//!
+//! Passes are free to insert and remove instructions without fixing up jump targets as they go;
+//! they finish by calling [`relink_loops`], which recomputes every target from the bracket structure.
+
+use crate::parser::BFInstruction;
+
+/// Recomputes the targets of every `LoopStart` and `LoopEnd` by matching them up again.
+///
+/// # Panics
+///
+/// Panics if the loops in `program` are unbalanced.
+pub fn relink_loops(program: &mut [BFInstruction]) {
+    let mut loop_stack = Vec::new();
+    for idx in 0..program.len() {
+        match program[idx] {
+            BFInstruction::LoopStart(_) => loop_stack.push(idx),
+            BFInstruction::LoopEnd(_) => {
+                let start = loop_stack.pop().expect("unbalanced loops");
+                program[start] = BFInstruction::LoopStart(idx);
+                program[idx] = BFInstruction::LoopEnd(start);
+            }
+            _ => {}
+        }
+    }
+    assert!(loop_stack.is_empty(), "unbalanced loops");
+}
+
+/// Fuses an `Add`, `ClearCell` or `SetCell` between two pointer moves that cancel out, like `>+<` or `>[-]<`,
+/// into an `AddAtOffset`, `ClearCellAtOffset` or `SetCellAtOffset`.
+pub fn fuse_offset_adds(program: &mut Vec<BFInstruction>) {
+    for instruction in std::mem::take(program) {
+        program.push(instruction);
+        if let [.., BFInstruction::IncrementPointer(there), ref middle, BFInstruction::IncrementPointer(back)] = program[..] {
+            let offset = there as isize;
+            let fused = match *middle {
+                BFInstruction::Add(value) => BFInstruction::AddAtOffset { offset, value },
+                BFInstruction::ClearCell => BFInstruction::ClearCellAtOffset(offset),
+                BFInstruction::SetCell(value) => BFInstruction::SetCellAtOffset { offset, value },
+                _ => continue,
+            };
+            if there.wrapping_add(back) == 0 {
+                program.truncate(program.len() - 3);
+                program.push(fused);
+            }
+        }
+    }
+    relink_loops(program);
+}
+
+/// Removes the program's I/O, leaving the computation: output is dropped and every read sets the cell to `input`,
+/// which should fit the cell width.
+///
+/// `EchoInput` copies until it reads a zero, so it becomes a `ClearCell` for a zero `input` and a loop that never ends
+/// once entered for any other.
+pub fn strip_io(program: &mut Vec<BFInstruction>, input: u32) {
+    let read = || match input {
+        0 => BFInstruction::ClearCell,
+        input => BFInstruction::SetCell(input),
+    };
+    for instruction in std::mem::take(program) {
+        match instruction {
+            BFInstruction::Output | BFInstruction::OutputN(_) => {}
+            BFInstruction::Input => program.push(read()),
+            BFInstruction::EchoInput if input == 0 => program.push(read()),
+            BFInstruction::EchoInput => program.extend([BFInstruction::LoopStart(0), read(), BFInstruction::LoopEnd(0)]),
+            instruction => program.push(instruction),
+        }
+    }
+    relink_loops(program);
+}
+
+/// Points loop jumps past brackets that would only test the same cell again.
+///
+/// A `[` that skips its loop lands on the `]` after its own, and a `]` that jumps back lands on the `[` after its own;
+/// each would find the cell just as the first bracket did. So a `LoopStart` is retargeted to the last of the `LoopEnd`s
+/// right after its match, and a `LoopEnd` to the last of the `LoopStart`s right after its match. What a program does is
+/// unchanged, but it takes fewer steps, and its loops no longer match up, so this must be the last pass and the
+/// result is only fit for the interpreter: [`relink_loops`] undoes it, and the code generators expect matched loops.
+pub fn thread_jumps(program: &mut [BFInstruction]) {
+    for idx in 0..program.len() {
+        match program[idx] {
+            BFInstruction::LoopStart(mut end) => {
+                while let Some(BFInstruction::LoopEnd(_)) = program.get(end + 1) {
+                    end += 1;
+                }
+                program[idx] = BFInstruction::LoopStart(end);
+            }
+            BFInstruction::LoopEnd(mut start) => {
+                while let Some(BFInstruction::LoopStart(_)) = program.get(start + 1) {
+                    start += 1;
+                }
+                program[idx] = BFInstruction::LoopEnd(start);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn assert_loops_linked(program: &[BFInstruction]) {
+    for (idx, instruction) in program.iter().enumerate() {
+        match *instruction {
+            BFInstruction::LoopStart(end) => assert!(matches!(program[end], BFInstruction::LoopEnd(start) if start == idx)),
+            BFInstruction::LoopEnd(start) => assert!(matches!(program[start], BFInstruction::LoopStart(end) if end == idx)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_data;
+    
+    #[test]
+    fn relink_after_removal() {
+        let mut program = parse_data(b".[.>[.>,]<.[.-<]]..[,.>]").unwrap();
+        program.retain(|instruction| !matches!(instruction, BFInstruction::Output | BFInstruction::OutputN(_)));
+        relink_loops(&mut program);
+        assert_loops_linked(&program);
+        assert_eq!(program, parse_data(b"[>[>,]<[-<]][,>]").unwrap());
+    }
+    
+    #[test]
+    fn stripped_io() {
+        let mut program = parse_data(b"+[>,[.-]<.-]>.....[,.]").unwrap();
+        strip_io(&mut program, 0);
+        assert_loops_linked(&program);
+        assert_eq!(program, [
+            BFInstruction::Add(1),
+            BFInstruction::LoopStart(9),
+            BFInstruction::IncrementPointer(1),
+            BFInstruction::ClearCell,
+            BFInstruction::LoopStart(6),
+            BFInstruction::Add(255),
+            BFInstruction::LoopEnd(4),
+            BFInstruction::IncrementPointer(usize::MAX),
+            BFInstruction::Add(255),
+            BFInstruction::LoopEnd(1),
+            BFInstruction::IncrementPointer(1),
+            BFInstruction::ClearCell,
+        ]);
+        
+        // Reading anything but a zero, the copy never ends.
+        let mut program = parse_data(b",[,.]+[,.]").unwrap();
+        strip_io(&mut program, 7);
+        assert_loops_linked(&program);
+        assert_eq!(program, [
+            BFInstruction::SetCell(7),
+            BFInstruction::LoopStart(3),
+            BFInstruction::SetCell(7),
+            BFInstruction::LoopEnd(1),
+            BFInstruction::Add(1),
+            BFInstruction::LoopStart(7),
+            BFInstruction::SetCell(7),
+            BFInstruction::LoopEnd(5),
+        ]);
+    }
+    
+    #[test]
+    fn offset_adds() {
+        let mut program = parse_data(b">+<.>>>++<<<[>-<,]>+>").unwrap();
+        fuse_offset_adds(&mut program);
+        assert_loops_linked(&program);
+        assert_eq!(program, [
+            BFInstruction::AddAtOffset { offset: 1, value: 1 },
+            BFInstruction::Output,
+            BFInstruction::AddAtOffset { offset: 3, value: 2 },
+            BFInstruction::LoopStart(6),
+            BFInstruction::AddAtOffset { offset: 1, value: 255 },
+            BFInstruction::Input,
+            BFInstruction::LoopEnd(3),
+            BFInstruction::IncrementPointer(1),
+            BFInstruction::Add(1),
+            BFInstruction::IncrementPointer(1),
+        ]);
+    }
+    
+    #[test]
+    fn threaded_jumps() {
+        let mut program = parse_data(b"+[[[.-]>]<],[.[,]]").unwrap();
+        thread_jumps(&mut program);
+        assert_eq!(program, [
+            BFInstruction::Add(1),
+            BFInstruction::LoopStart(10),
+            BFInstruction::LoopStart(8),
+            BFInstruction::LoopStart(6),
+            BFInstruction::Output,
+            BFInstruction::Add(255),
+            BFInstruction::LoopEnd(3),
+            BFInstruction::IncrementPointer(1),
+            BFInstruction::LoopEnd(3),
+            BFInstruction::IncrementPointer(usize::MAX),
+            BFInstruction::LoopEnd(3),
+            BFInstruction::Input,
+            BFInstruction::LoopStart(17),
+            BFInstruction::Output,
+            BFInstruction::LoopStart(17),
+            BFInstruction::Input,
+            BFInstruction::LoopEnd(14),
+            BFInstruction::LoopEnd(12),
+        ]);
+        
+        // Nested loops that share a cell behave the same with fewer steps.
+        let sources: [&[u8]; 3] = [b"+++[>[.]]>.", b"++[>+++[[.-]<]>+[>]<<]", b",[[.,]>,]"];
+        for source in sources {
+            let run = |program: &[BFInstruction]| {
+                let mut output = Vec::new();
+                let outcome = crate::interpreter::run_program_outcome(program, 8, &crate::interpreter::RunOptions::default(), &mut &b"abc\0de"[..], &mut output, &mut ());
+                (outcome, output)
+            };
+            let program = parse_data(source).unwrap();
+            let mut threaded = parse_data(source).unwrap();
+            thread_jumps(&mut threaded);
+            let (expected, expected_output) = run(&program);
+            let (outcome, output) = run(&threaded);
+            assert_eq!((outcome.result, outcome.memory, outcome.data_pointer, output), (expected.result, expected.memory, expected.data_pointer, expected_output));
+            assert!(outcome.steps < expected.steps);
+        }
+    }
+    
+    #[test]
+    fn offset_clears() {
+        let mut program = parse_data(b">[-]<.>>[-]<<.<<[-]++>>[>[-]-<]").unwrap();
+        fuse_offset_adds(&mut program);
+        assert_loops_linked(&program);
+        assert_eq!(program, [
+            BFInstruction::ClearCellAtOffset(1),
+            BFInstruction::Output,
+            BFInstruction::ClearCellAtOffset(2),
+            BFInstruction::Output,
+            BFInstruction::SetCellAtOffset { offset: -2, value: 2 },
+            BFInstruction::LoopStart(7),
+            BFInstruction::SetCellAtOffset { offset: 1, value: 255 },
+            BFInstruction::LoopEnd(5),
+        ]);
+        
+        // Moves that don't cancel out are left alone.
+        let mut program = parse_data(b">[-]>").unwrap();
+        fuse_offset_adds(&mut program);
+        assert_eq!(program, parse_data(b">[-]>").unwrap());
+    }
+    
+    #[test]
+    #[should_panic(expected = "unbalanced loops")]
+    fn unbalanced() {
+        relink_loops(&mut [BFInstruction::LoopStart(0)]);
+    }
+}
@@ -0,0 +1,1102 @@
+use std::fmt;
+use std::io;
+use std::io::{BufReader, Read};
+
+use crate::cell::{CellOverflow, CellWidth};
+use crate::dialect::Dialect;
+
+/// A single optimized Brainfuck instruction.
+#[derive(Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+pub enum BFInstruction {
+    /// Adds the value (wrapping) to the current cell; `-` is folded in as the maximum cell value.
+    Add(u32),
+    /// Moves the data pointer by the given (wrapping) amount; `<` is folded in as `usize::MAX`.
+    IncrementPointer(usize),
+    /// Writes the current cell to the output.
+    Output,
+    /// Reads one byte from the input into the current cell.
+    Input,
+    /// Jumps to the matching `LoopEnd` at the given index if the current cell is zero.
+    LoopStart(usize),
+    /// Jumps back to the matching `LoopStart` at the given index if the current cell is nonzero.
+    LoopEnd(usize),
+    /// Sets the current cell to zero; produced from `[-]` and `[+]`.
+    ClearCell,
+    /// Sets the current cell to the value; produced from a clear like `[-]` followed by `+`/`-`, as long as it doesn't wrap.
+    SetCell(u32),
+    /// If the current cell is nonzero, adds its value times each multiplier to the cell at each offset, then clears it.
+    ///
+    /// Produced from loops like `[->+<]` that only add and move, return to the starting cell and change it by an odd amount.
+    /// The targets sit behind a second box so this variant fits in a thin pointer, keeping every instruction at two words.
+    MulAdd(Box<Box<[(isize, u32)]>>),
+    /// Adds the value (wrapping) to the cell at the given offset from the data pointer, which stays put.
+    ///
+    /// Produced by [`fuse_offset_adds`](crate::optimizer::fuse_offset_adds) from moves around an `Add` like `>+<`.
+    AddAtOffset { offset: isize, value: u32 },
+    /// Sets the cell at the given offset from the data pointer to zero, leaving the data pointer where it is.
+    ///
+    /// Produced by [`fuse_offset_adds`](crate::optimizer::fuse_offset_adds) from moves around a clear like `>[-]<`.
+    ClearCellAtOffset(isize),
+    /// Sets the cell at the given offset from the data pointer to the value, leaving the data pointer where it is.
+    ///
+    /// Produced by [`fuse_offset_adds`](crate::optimizer::fuse_offset_adds) from moves around a set like `>[-]+<`.
+    SetCellAtOffset { offset: isize, value: u32 },
+    /// Does nothing by itself; a [`MemoryDump`](crate::MemoryDump) hook prints the machine state when it runs.
+    ///
+    /// Produced from `#` when [`ParseOptions::debug_char`] is set.
+    Debug,
+    /// Moves the data pointer right by the given step until it reaches a zero cell; produced from loops like `[>]`.
+    ScanRight(usize),
+    /// Moves the data pointer left by the given step until it reaches a zero cell; produced from loops like `[<]`.
+    ScanLeft(usize),
+    /// Writes the current cell to the output the given number of times; produced from runs of `.` with nothing between them.
+    OutputN(usize),
+    /// While the current cell is nonzero, reads a byte into it and writes it back out; produced from `[,.]`, which copies input up to a zero byte.
+    EchoInput,
+}
+
+impl BFInstruction {
+    /// The name of the variant, without its operands.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BFInstruction::Add(_) => "Add",
+            BFInstruction::IncrementPointer(_) => "IncrementPointer",
+            BFInstruction::Output => "Output",
+            BFInstruction::Input => "Input",
+            BFInstruction::LoopStart(_) => "LoopStart",
+            BFInstruction::LoopEnd(_) => "LoopEnd",
+            BFInstruction::ClearCell => "ClearCell",
+            BFInstruction::SetCell(_) => "SetCell",
+            BFInstruction::MulAdd(_) => "MulAdd",
+            BFInstruction::AddAtOffset { .. } => "AddAtOffset",
+            BFInstruction::ClearCellAtOffset(_) => "ClearCellAtOffset",
+            BFInstruction::SetCellAtOffset { .. } => "SetCellAtOffset",
+            BFInstruction::Debug => "Debug",
+            BFInstruction::ScanRight(_) => "ScanRight",
+            BFInstruction::ScanLeft(_) => "ScanLeft",
+            BFInstruction::OutputN(_) => "OutputN",
+            BFInstruction::EchoInput => "EchoInput",
+        }
+    }
+}
+
+/// Writes `value` as a run of `+`, or of `-` when that's shorter, for 8-bit cells.
+fn write_add(f: &mut fmt::Formatter<'_>, value: u32) -> fmt::Result {
+    match value as u8 {
+        value @ 0..=128 => write!(f, "{}", "+".repeat(value as usize)),
+        value => write!(f, "{}", "-".repeat(256 - value as usize)),
+    }
+}
+
+fn write_move(f: &mut fmt::Formatter<'_>, by: isize) -> fmt::Result {
+    match by {
+        0.. => write!(f, "{}", ">".repeat(by as usize)),
+        _ => write!(f, "{}", "<".repeat(by.unsigned_abs())),
+    }
+}
+
+/// Renders the instruction as Brainfuck commands that do the same thing, like `+++` for `Add(3)` and `[->++<]` for
+/// a `MulAdd`.
+///
+/// Values are written for 8-bit cells, the default, so `Add(255)` is `-`; a program parsed for wider cells may not
+/// mean the same once rendered. `LoopStart` and `LoopEnd` are `[` and `]` regardless of their targets, and `Debug` is `#`.
+impl fmt::Display for BFInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            BFInstruction::Add(value) => write_add(f, value),
+            BFInstruction::IncrementPointer(by) => write_move(f, by as isize),
+            BFInstruction::Output => write!(f, "."),
+            BFInstruction::Input => write!(f, ","),
+            BFInstruction::LoopStart(_) => write!(f, "["),
+            BFInstruction::LoopEnd(_) => write!(f, "]"),
+            BFInstruction::ClearCell => write!(f, "[-]"),
+            BFInstruction::SetCell(value) => {
+                write!(f, "[-]")?;
+                write_add(f, value)
+            }
+            BFInstruction::MulAdd(ref targets) => {
+                write!(f, "[-")?;
+                let mut at = 0;
+                for &(offset, multiplier) in targets.iter() {
+                    write_move(f, offset - at)?;
+                    write_add(f, multiplier)?;
+                    at = offset;
+                }
+                write_move(f, -at)?;
+                write!(f, "]")
+            }
+            BFInstruction::AddAtOffset { offset, value } => {
+                write_move(f, offset)?;
+                write_add(f, value)?;
+                write_move(f, -offset)
+            }
+            BFInstruction::ClearCellAtOffset(offset) => {
+                write_move(f, offset)?;
+                write!(f, "[-]")?;
+                write_move(f, -offset)
+            }
+            BFInstruction::SetCellAtOffset { offset, value } => {
+                write_move(f, offset)?;
+                write!(f, "[-]")?;
+                write_add(f, value)?;
+                write_move(f, -offset)
+            }
+            BFInstruction::Debug => write!(f, "#"),
+            BFInstruction::ScanRight(step) => write!(f, "[{}]", ">".repeat(step)),
+            BFInstruction::ScanLeft(step) => write!(f, "[{}]", "<".repeat(step)),
+            BFInstruction::OutputN(count) => write!(f, "{}", ".".repeat(count)),
+            BFInstruction::EchoInput => write!(f, "[,.]"),
+        }
+    }
+}
+
+/// Turns a parsed program back into Brainfuck source, with every instruction rendered by its [`Display`](fmt::Display) impl.
+///
+/// The result has no comments or whitespace, and parses back into an equivalent program. That's usually the same
+/// instructions, though the moves back and forth between two neighbouring offset instructions fold together.
+pub fn program_to_source(program: &[BFInstruction]) -> String {
+    program.iter().map(BFInstruction::to_string).collect()
+}
+
+/// A location in the program source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// Byte offset from the start of the source.
+    pub offset: usize,
+    /// One-based line number.
+    pub line: usize,
+    /// One-based column, counted in bytes.
+    pub column: usize,
+}
+
+impl Position {
+    const START: Self = Self { offset: 0, line: 1, column: 1 };
+    
+    /// Moves past `byte`.
+    fn advance(&mut self, byte: u8) {
+        self.offset += 1;
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+/// Why a program failed to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// A `]` appeared with no open loop to close.
+    UnexpectedCloseBracket,
+    /// A `[` was never closed.
+    UnclosedBracket,
+    /// In strict mode, a byte that is neither a command nor whitespace.
+    UnexpectedByte(u8),
+    /// Reading the source failed; the position is where reading stopped.
+    Read(io::ErrorKind),
+    /// A `[` opened a loop nested deeper than [`ParseOptions::max_nesting`] allows.
+    NestingTooDeep(usize),
+    /// With [`EmptyLoops::Reject`], a `[` opened a loop whose body is empty.
+    EmptyLoop,
+    /// With [`EmptyLoops::Reject`], a `[` opened a loop whose body only adds and moves, and leaves the cell it tests
+    /// unchanged, like `[>+<]`.
+    EndlessLoop,
+}
+
+/// An error produced by [`parse_data`], pointing at the offending source byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub position: Position,
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::UnexpectedCloseBracket => write!(f, "unexpected ']'")?,
+            ParseErrorKind::UnclosedBracket => write!(f, "unclosed '['")?,
+            ParseErrorKind::UnexpectedByte(byte) => write!(f, "unexpected byte '{}'", byte.escape_ascii())?,
+            ParseErrorKind::Read(kind) => write!(f, "couldn't read source ({kind})")?,
+            ParseErrorKind::NestingTooDeep(limit) => write!(f, "loops nested more than {limit} deep")?,
+            ParseErrorKind::EmptyLoop => write!(f, "empty loop")?,
+            ParseErrorKind::EndlessLoop => write!(f, "endless loop")?,
+        }
+        write!(f, " at line {}, column {}", self.position.line, self.position.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn optimize_loop(body: &[Option<BFInstruction>], options: &ParseOptions) -> Option<BFInstruction> {
+    if options.unoptimized {
+        return None;
+    }
+    
+    match *body {
+        [Some(BFInstruction::IncrementPointer(by))] => match by as isize {
+            step @ 1.. => Some(BFInstruction::ScanRight(step as usize)),
+            step => Some(BFInstruction::ScanLeft(step.unsigned_abs())),
+        },
+        [Some(BFInstruction::Input), Some(BFInstruction::Output)] => Some(BFInstruction::EchoInput),
+        _ => multiply_loop(body, options),
+    }
+}
+
+/// The inverse of odd `value` modulo 2^32, by Newton's iteration; every step doubles the number of correct low bits.
+fn inverse(value: u32) -> u32 {
+    // Odd numbers are their own inverse modulo 8.
+    let mut inverse = value;
+    for _ in 0..4 {
+        inverse = inverse.wrapping_mul(2u32.wrapping_sub(value.wrapping_mul(inverse)));
+    }
+    inverse
+}
+
+/// Turns a loop body into a `MulAdd` (or a `ClearCell` if it touches no other cell)
+/// if running it step by step is guaranteed to do the same thing.
+///
+/// That holds only for bodies made of nothing but `Add`s and pointer moves (so no I/O, nested loops or idioms)
+/// which end where they started and change the starting cell by an odd amount `step` in total.
+/// Each iteration adds the same amounts to the same cells, and the loop runs as many times as it takes
+/// `value + n * step` to wrap to zero, which is `value * -step⁻¹` and so a fixed multiple of the starting value.
+/// Even steps, including zero, only reach zero from some values and loop forever from the rest, so they're left alone.
+///
+/// When overflow traps, the starting cell must go down by exactly 1, since any other step would overflow
+/// before reaching zero. A cell touched more than once could also overflow partway through an iteration
+/// even if its net change doesn't, so every cell must be touched by a single `Add`.
+fn multiply_loop(body: &[Option<BFInstruction>], options: &ParseOptions) -> Option<BFInstruction> {
+    let mask = options.cell_width.mask();
+    let mut offset = 0usize;
+    let mut deltas: Vec<(isize, u32)> = Vec::new();
+    for instruction in body {
+        match *instruction {
+            Some(BFInstruction::Add(val)) => match deltas.iter_mut().find(|(target, _)| *target == offset as isize) {
+                Some(_) if options.cell_overflow == CellOverflow::Trap => return None,
+                Some((_, delta)) => *delta = delta.wrapping_add(val) & mask,
+                None => deltas.push((offset as isize, val)),
+            },
+            Some(BFInstruction::IncrementPointer(by)) => offset = offset.wrapping_add(by),
+            _ => return None
+        }
+    }
+    
+    if offset != 0 {
+        return None;
+    }
+    
+    let control = deltas.iter().position(|&(target, _)| target == 0)?;
+    let step = deltas.remove(control).1;
+    let iterations = match options.cell_overflow {
+        CellOverflow::Wrap if step % 2 == 1 => inverse(step).wrapping_neg() & mask,
+        CellOverflow::Trap if step == mask => 1,
+        _ => return None,
+    };
+    
+    if deltas.is_empty() {
+        return Some(BFInstruction::ClearCell);
+    }
+    Some(BFInstruction::MulAdd(Box::new(deltas.into_iter().map(|(target, delta)| (target, delta.wrapping_mul(iterations) & mask)).collect())))
+}
+
+/// Whether a loop body made of nothing but `Add`s and pointer moves ends where it started with the starting cell unchanged,
+/// so it tests the same value every time around.
+fn never_changes_cell(body: &[Option<BFInstruction>], options: &ParseOptions) -> bool {
+    let mask = options.cell_width.mask();
+    let mut offset = 0usize;
+    let mut delta = 0u32;
+    let mut adds_elsewhere = false;
+    for instruction in body {
+        match *instruction {
+            Some(BFInstruction::Add(val)) if offset == 0 => delta = delta.wrapping_add(val) & mask,
+            Some(BFInstruction::Add(_)) => adds_elsewhere = true,
+            Some(BFInstruction::IncrementPointer(by)) => offset = offset.wrapping_add(by),
+            _ => return false,
+        }
+    }
+    offset == 0 && delta == 0 && !(adds_elsewhere && options.cell_overflow == CellOverflow::Trap)
+}
+
+/// What parsing does about a loop that never ends once entered: one with an empty body, like `[]`, or one that
+/// never changes the cell it tests, like `[>+<]`.
+///
+/// A body also counts as empty if everything in it cancels out, like `[+-]`. A leading comment loop that gets dropped never counts.
+///
+/// Only bodies made of nothing but `+`, `-`, `<` and `>` that end on the cell they started on are checked for the
+/// tested cell changing. Loops with nested loops or I/O, such as `[,]` or `[>[-]<]`, can loop forever too, depending on
+/// the data, and aren't caught. When overflow traps, a body that adds to another cell overflows it eventually, so it
+/// isn't caught either.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum EmptyLoops {
+    #[default]
+    Allow,
+    /// Call the function with the error that `Reject` would fail with, and carry on.
+    Warn(fn(ParseError)),
+    /// Fail with [`ParseErrorKind::EmptyLoop`] or [`ParseErrorKind::EndlessLoop`], at the loop's `[`.
+    Reject,
+}
+
+/// Settings controlling how source is parsed.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// The cell width the program will run with, which determines where `+`/`-` runs wrap.
+    pub cell_width: CellWidth,
+    /// With `Trap`, runs of `+` and `-` aren't fused across each other or beyond half the cell range,
+    /// so every `Add` is an unambiguous signed change.
+    pub cell_overflow: CellOverflow,
+    /// Drop a loop at the very start of the program, which never runs on a zeroed tape and is conventionally a comment.
+    pub drop_comment_loop: bool,
+    /// Parse `#` as a `Debug` instruction instead of ignoring it.
+    pub debug_char: bool,
+    /// Reject bytes that are neither commands nor whitespace instead of ignoring them as comments.
+    pub strict: bool,
+    /// Emit one instruction per command without fusing runs or recognizing loop idioms,
+    /// so that instruction indices line up with the commands in the source.
+    pub unoptimized: bool,
+    /// Reject programs with loops nested more levels deep than this.
+    pub max_nesting: Option<usize>,
+    /// Stop at the first `!`, which by a common convention separates the program from input bundled with it.
+    ///
+    /// [`parse_data_with_input`] returns what follows the `!`; the reader functions leave it unread.
+    pub bang_input: bool,
+    /// Read commands spelled the way this dialect spells them instead of as standard Brainfuck.
+    pub dialect: Option<Dialect>,
+    pub empty_loops: EmptyLoops,
+}
+
+impl ParseOptions {
+    /// The standard command `byte` stands for, if any.
+    fn command(&self, byte: u8) -> Option<u8> {
+        match &self.dialect {
+            Some(dialect) => dialect.command(byte),
+            None => Some(byte),
+        }
+    }
+}
+
+/// Parses Brainfuck source into instructions for 8-bit cells, fusing runs of `+`/`-` and `>`/`<`.
+///
+/// A UTF-8 byte order mark at the start is skipped, and so is a first line starting with `#!`, so that a program
+/// file can be run as a script.
+pub fn parse_data(data: &[u8]) -> Result<Vec<BFInstruction>, ParseError> {
+    parse_data_with_options(data, &ParseOptions::default())
+}
+
+/// Parses Brainfuck source into instructions with the given options.
+pub fn parse_data_with_options(data: &[u8], options: &ParseOptions) -> Result<Vec<BFInstruction>, ParseError> {
+    parse_bytes(data.iter().copied().map(Ok), options)
+}
+
+/// Like [`parse_data_with_options`], also returning the input that follows the program when [`ParseOptions::bang_input`] is set.
+///
+/// The input is empty if there is no `!` or `bang_input` isn't set.
+pub fn parse_data_with_input<'a>(data: &'a [u8], options: &ParseOptions) -> Result<(Vec<BFInstruction>, &'a [u8]), ParseError> {
+    let preamble = preamble_len(data);
+    let (code, input) = match data[preamble..].iter().position(|&byte| options.command(byte) == Some(b'!')) {
+        Some(bang) if options.bang_input => (&data[..preamble + bang], &data[preamble + bang + 1..]),
+        _ => (data, &[][..]),
+    };
+    Ok((parse_data_with_options(code, options)?, input))
+}
+
+/// Like [`parse_data`], reading the source incrementally instead of from a slice.
+pub fn parse_reader(reader: impl Read) -> Result<Vec<BFInstruction>, ParseError> {
+    parse_reader_with_options(reader, &ParseOptions::default())
+}
+
+/// Like [`parse_data_with_options`], reading the source incrementally instead of from a slice.
+pub fn parse_reader_with_options(reader: impl Read, options: &ParseOptions) -> Result<Vec<BFInstruction>, ParseError> {
+    parse_bytes(BufReader::new(reader).bytes(), options)
+}
+
+/// How many bytes of `data` parsing with `options` would treat as commands, before any fusion.
+pub fn count_commands(data: &[u8], options: &ParseOptions) -> usize {
+    commands(data, options).count()
+}
+
+/// The commands parsing `data` with `options` would see, as the standard command characters, before any fusion.
+pub(crate) fn commands<'a>(data: &'a [u8], options: &'a ParseOptions) -> impl Iterator<Item=u8> + 'a {
+    data[preamble_len(data)..].iter()
+        .map(|&byte| options.command(byte))
+        .take_while(|&command| !(options.bang_input && command == Some(b'!')))
+        .flatten()
+        .filter(|&command| matches!(command, b'+' | b'-' | b'<' | b'>' | b'.' | b',' | b'[' | b']') || options.debug_char && command == b'#')
+}
+
+/// Finds every unmatched bracket in `data`, instead of stopping at the first the way parsing does.
+///
+/// The errors come in source order: every `[` left open follows the last `]` that closes nothing, since that `]` would have closed it.
+/// Of `options`, only [`ParseOptions::dialect`] and [`ParseOptions::bang_input`] matter here.
+pub fn lint(data: &[u8], options: &ParseOptions) -> Vec<ParseError> {
+    let mut errors = Vec::new();
+    let mut loop_stack = Vec::new();
+    let mut next_position = Position::START;
+    let preamble = preamble_len(data);
+    for &byte in data {
+        let position = next_position;
+        next_position.advance(byte);
+        if position.offset < preamble {
+            continue;
+        }
+        match options.command(byte) {
+            Some(b'[') => loop_stack.push(position),
+            Some(b']') if loop_stack.pop().is_none() => errors.push(ParseError { position, kind: ParseErrorKind::UnexpectedCloseBracket }),
+            Some(b'!') if options.bang_input => break,
+            _ => {}
+        }
+    }
+    errors.extend(loop_stack.into_iter().map(|position| ParseError { position, kind: ParseErrorKind::UnclosedBracket }));
+    errors
+}
+
+const BYTE_ORDER_MARK: [u8; 3] = [0xEF, 0xBB, 0xBF];
+
+/// How many bytes at the start of `data` are a byte order mark or a `#!` line, which parsing skips.
+fn preamble_len(data: &[u8]) -> usize {
+    let bom = match data.starts_with(&BYTE_ORDER_MARK) {
+        true => BYTE_ORDER_MARK.len(),
+        false => 0,
+    };
+    match data[bom..].starts_with(b"#!") {
+        true => data[bom..].iter().position(|&byte| byte == b'\n').map_or(data.len(), |newline| bom + newline + 1),
+        false => bom,
+    }
+}
+
+/// Takes back the last instruction when a run that cancelled out leaves later commands free to fold into it again.
+fn reopen(instructions: &mut Vec<Option<BFInstruction>>) -> Option<BFInstruction> {
+    match instructions.last() {
+        Some(Some(
+            BFInstruction::Add(_) | BFInstruction::IncrementPointer(_) | BFInstruction::ClearCell | BFInstruction::SetCell(_) | BFInstruction::Output | BFInstruction::OutputN(_)
+        )) => instructions.pop().flatten(),
+        _ => None,
+    }
+}
+
+fn parse_bytes(bytes: impl Iterator<Item=io::Result<u8>>, options: &ParseOptions) -> Result<Vec<BFInstruction>, ParseError> {
+    let mask = options.cell_width.mask();
+    let checked = options.cell_overflow == CellOverflow::Trap;
+    let error = |position, kind| ParseError { position, kind };
+    
+    let mut instructions = Vec::new();
+    let mut loop_stack = Vec::new();
+    let mut last_instruction = None;
+    let mut next_position = Position::START;
+    // The same preamble `preamble_len` finds, read a byte at a time.
+    let mut bom = 0;
+    let mut in_shebang = false;
+    let mut bytes = bytes.peekable();
+    while let Some(byte) = bytes.next() {
+        let position = next_position;
+        let byte = byte.map_err(|err| error(position, ParseErrorKind::Read(err.kind())))?;
+        next_position.advance(byte);
+        if in_shebang {
+            in_shebang = byte != b'\n';
+            continue;
+        }
+        if position.offset == bom {
+            if bom < BYTE_ORDER_MARK.len() && byte == BYTE_ORDER_MARK[bom] {
+                bom += 1;
+                continue;
+            }
+            if byte == b'#' && matches!(bytes.peek(), Some(Ok(b'!'))) {
+                in_shebang = true;
+                continue;
+            }
+        }
+        
+        if options.unoptimized {
+            if let Some(last) = last_instruction.take() {
+                instructions.push(Some(last));
+            }
+        }
+        match options.command(byte) {
+            Some(b'+') => match last_instruction.take() {
+                Some(BFInstruction::Add(val)) if checked && val >= mask / 2 => {
+                    instructions.push(Some(BFInstruction::Add(val)));
+                    last_instruction = Some(BFInstruction::Add(1));
+                }
+                Some(BFInstruction::Add(val)) if val == mask => last_instruction = reopen(&mut instructions),
+                Some(BFInstruction::Add(val)) => last_instruction = Some(BFInstruction::Add(val + 1)),
+                Some(BFInstruction::ClearCell) => last_instruction = Some(BFInstruction::SetCell(1)),
+                Some(BFInstruction::SetCell(val)) if val < mask => last_instruction = Some(BFInstruction::SetCell(val + 1)),
+                Some(other_instruction) => {
+                    instructions.push(Some(other_instruction));
+                    last_instruction = Some(BFInstruction::Add(1));
+                }
+                None => last_instruction = Some(BFInstruction::Add(1))
+            }
+            Some(b'-') => match last_instruction.take() {
+                Some(BFInstruction::Add(val)) if checked && val <= mask / 2 + 1 => {
+                    instructions.push(Some(BFInstruction::Add(val)));
+                    last_instruction = Some(BFInstruction::Add(mask));
+                }
+                Some(BFInstruction::Add(1)) => last_instruction = reopen(&mut instructions),
+                Some(BFInstruction::Add(val)) => last_instruction = Some(BFInstruction::Add(val - 1)),
+                // Going below zero traps when overflow is checked, so keep that as an `Add`.
+                Some(BFInstruction::ClearCell) if !checked => last_instruction = Some(BFInstruction::SetCell(mask)),
+                Some(BFInstruction::SetCell(val)) if val > 0 => last_instruction = Some(BFInstruction::SetCell(val - 1)),
+                Some(other_instruction) => {
+                    instructions.push(Some(other_instruction));
+                    last_instruction = Some(BFInstruction::Add(mask));
+                }
+                None => last_instruction = Some(BFInstruction::Add(mask))
+            }
+            Some(b'>') => match last_instruction.take() {
+                Some(BFInstruction::IncrementPointer(usize::MAX)) => last_instruction = reopen(&mut instructions),
+                Some(BFInstruction::IncrementPointer(by)) => last_instruction = Some(BFInstruction::IncrementPointer(by.wrapping_add(1))),
+                Some(other_instruction) => {
+                    instructions.push(Some(other_instruction));
+                    last_instruction = Some(BFInstruction::IncrementPointer(1));
+                }
+                None => last_instruction = Some(BFInstruction::IncrementPointer(1))
+            }
+            Some(b'<') => match last_instruction.take() {
+                Some(BFInstruction::IncrementPointer(1)) => last_instruction = reopen(&mut instructions),
+                Some(BFInstruction::IncrementPointer(by)) => last_instruction = Some(BFInstruction::IncrementPointer(by.wrapping_sub(1))),
+                Some(other_instruction) => {
+                    instructions.push(Some(other_instruction));
+                    last_instruction = Some(BFInstruction::IncrementPointer(usize::MAX));
+                }
+                None => last_instruction = Some(BFInstruction::IncrementPointer(usize::MAX))
+            }
+            Some(b'.') => {
+                last_instruction = match last_instruction.take() {
+                    Some(BFInstruction::Output) => Some(BFInstruction::OutputN(2)),
+                    Some(BFInstruction::OutputN(count)) => Some(BFInstruction::OutputN(count + 1)),
+                    last => {
+                        if let Some(last) = last {
+                            instructions.push(Some(last));
+                        }
+                        Some(BFInstruction::Output)
+                    }
+                };
+            }
+            Some(b',') => {
+                if let Some(last) = last_instruction.take() {
+                    instructions.push(Some(last));
+                }
+                instructions.push(Some(BFInstruction::Input));
+            }
+            Some(b'!') if options.bang_input => break,
+            Some(b'#') if options.debug_char => {
+                if let Some(last) = last_instruction.take() {
+                    instructions.push(Some(last));
+                }
+                instructions.push(Some(BFInstruction::Debug));
+            }
+            Some(b'[') => {
+                if let Some(last) = last_instruction.take() {
+                    instructions.push(Some(last));
+                }
+                if let Some(limit) = options.max_nesting.filter(|&limit| loop_stack.len() >= limit) {
+                    return Err(error(position, ParseErrorKind::NestingTooDeep(limit)));
+                }
+                loop_stack.push((instructions.len(), position));
+                instructions.push(None);
+            }
+            Some(b']') => {
+                if let Some(last) = last_instruction.take() {
+                    instructions.push(Some(last));
+                }
+                let Some((loop_start_idx, loop_start)) = loop_stack.pop() else {
+                    return Err(error(position, ParseErrorKind::UnexpectedCloseBracket));
+                };
+                
+                if loop_start_idx == 0 && options.drop_comment_loop {
+                    instructions.clear();
+                    continue;
+                }
+                let body = &instructions[loop_start_idx + 1..];
+                let endless = match body.is_empty() {
+                    true => Some(ParseErrorKind::EmptyLoop),
+                    false => never_changes_cell(body, options).then_some(ParseErrorKind::EndlessLoop),
+                };
+                if let Some(kind) = endless {
+                    match options.empty_loops {
+                        EmptyLoops::Allow => {}
+                        EmptyLoops::Warn(warn) => warn(error(loop_start, kind)),
+                        EmptyLoops::Reject => return Err(error(loop_start, kind)),
+                    }
+                }
+                
+                match optimize_loop(&instructions[loop_start_idx + 1..], options) {
+                    // Left pending so that `+`/`-` can turn it into a `SetCell`.
+                    Some(BFInstruction::ClearCell) => {
+                        instructions.truncate(loop_start_idx);
+                        // The clear overwrites whatever was just stored in the cell, though an `Add` has to stay if it could trap.
+                        while let Some(Some(instruction)) = instructions.last() {
+                            match instruction {
+                                BFInstruction::ClearCell | BFInstruction::SetCell(_) => {}
+                                BFInstruction::Add(_) if !checked => {}
+                                _ => break,
+                            }
+                            instructions.pop();
+                        }
+                        last_instruction = Some(BFInstruction::ClearCell);
+                    }
+                    Some(optimized) => {
+                        instructions.truncate(loop_start_idx);
+                        instructions.push(Some(optimized));
+                    }
+                    None => {
+                        instructions[loop_start_idx] = Some(BFInstruction::LoopStart(instructions.len()));
+                        instructions.push(Some(BFInstruction::LoopEnd(loop_start_idx)));
+                    }
+                }
+            }
+            _ if options.strict && !byte.is_ascii_whitespace() => return Err(error(position, ParseErrorKind::UnexpectedByte(byte))),
+            _ => {}
+        }
+    }
+    
+    if let Some(&(_, position)) = loop_stack.last() {
+        return Err(error(position, ParseErrorKind::UnclosedBracket));
+    }
+    
+    if let Some(last_instruction) = last_instruction {
+        instructions.push(Some(last_instruction));
+    }
+    
+    Ok(instructions.into_iter().map(|instruction| instruction.expect("every loop is closed")).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn instruction_size() {
+        assert_eq!(std::mem::size_of::<BFInstruction>(), 16);
+    }
+    
+    #[test]
+    fn optimizations() {
+        assert_eq!(parse_data(b"++++++.---,").unwrap(), [BFInstruction::Add(6), BFInstruction::Output, BFInstruction::Add(253), BFInstruction::Input]);
+    }
+    
+    #[test]
+    fn subtraction_folds_into_add() {
+        assert_eq!(parse_data(b"+++--").unwrap(), [BFInstruction::Add(1)]);
+        assert_eq!(parse_data(b"--+").unwrap(), [BFInstruction::Add(255)]);
+        assert_eq!(parse_data(b"+++---.").unwrap(), [BFInstruction::Output]);
+        assert_eq!(parse_data(b"-+-+").unwrap(), []);
+    }
+    
+    #[test]
+    fn unoptimized() {
+        let options = ParseOptions { unoptimized: true, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"++", &options).unwrap(), [BFInstruction::Add(1), BFInstruction::Add(1)]);
+        assert_eq!(parse_data_with_options(b"+-<[-]", &options).unwrap(), [
+            BFInstruction::Add(1),
+            BFInstruction::Add(255),
+            BFInstruction::IncrementPointer(usize::MAX),
+            BFInstruction::LoopStart(5),
+            BFInstruction::Add(255),
+            BFInstruction::LoopEnd(3),
+        ]);
+    }
+    
+    #[test]
+    fn pointer_moves_fold() {
+        assert_eq!(parse_data(b">>><<").unwrap(), [BFInstruction::IncrementPointer(1)]);
+        assert_eq!(parse_data(b"><<<").unwrap(), [BFInstruction::IncrementPointer(usize::MAX - 1)]);
+        assert_eq!(parse_data(b">>><<.").unwrap(), [BFInstruction::IncrementPointer(1), BFInstruction::Output]);
+    }
+    
+    #[test]
+    fn cancelled_runs() {
+        assert_eq!(parse_data(b"+++---").unwrap(), []);
+        assert_eq!(parse_data(b">><<").unwrap(), []);
+        assert_eq!(parse_data(b"+><+").unwrap(), [BFInstruction::Add(2)]);
+        assert_eq!(parse_data(b">+->").unwrap(), [BFInstruction::IncrementPointer(2)]);
+        assert_eq!(parse_data(b".[-]<>++").unwrap(), [BFInstruction::Output, BFInstruction::SetCell(2)]);
+        assert_eq!(parse_data(b"[.]+-+").unwrap(), [BFInstruction::LoopStart(2), BFInstruction::Output, BFInstruction::LoopEnd(0), BFInstruction::Add(1)]);
+    }
+    
+    #[test]
+    fn output_runs() {
+        assert_eq!(parse_data(b"..").unwrap(), [BFInstruction::OutputN(2)]);
+        assert_eq!(parse_data(b"[-]++++ .. . comment .").unwrap(), [BFInstruction::SetCell(4), BFInstruction::OutputN(4)]);
+        assert_eq!(parse_data(b".+.").unwrap(), [BFInstruction::Output, BFInstruction::Add(1), BFInstruction::Output]);
+        assert_eq!(parse_data(b".>.,.").unwrap(), [
+            BFInstruction::Output,
+            BFInstruction::IncrementPointer(1),
+            BFInstruction::Output,
+            BFInstruction::Input,
+            BFInstruction::Output,
+        ]);
+        assert_eq!(parse_data(b".+-.<>.").unwrap(), [BFInstruction::OutputN(3)]);
+        assert_eq!(parse_data(b"[..]").unwrap(), [BFInstruction::LoopStart(2), BFInstruction::OutputN(2), BFInstruction::LoopEnd(0)]);
+        
+        let options = ParseOptions { unoptimized: true, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"..", &options).unwrap(), [BFInstruction::Output, BFInstruction::Output]);
+    }
+    
+    #[test]
+    fn dead_code_elimination() {
+        assert_eq!(parse_data(b"++--+-+--+<>>><<").unwrap(), []);
+    }
+    
+    #[test]
+    fn clear_cell() {
+        assert_eq!(parse_data(b"[-]").unwrap(), [BFInstruction::ClearCell]);
+        assert_eq!(parse_data(b"[+]").unwrap(), [BFInstruction::ClearCell]);
+        assert_eq!(parse_data(b"[[-]]").unwrap(), [BFInstruction::LoopStart(2), BFInstruction::ClearCell, BFInstruction::LoopEnd(0)]);
+        assert_eq!(parse_data(b"[--]").unwrap(), [BFInstruction::LoopStart(2), BFInstruction::Add(254), BFInstruction::LoopEnd(0)]);
+    }
+    
+    #[test]
+    fn dead_stores() {
+        assert_eq!(parse_data(b"[-]+++[-]").unwrap(), [BFInstruction::ClearCell]);
+        assert_eq!(parse_data(b"+++[-]").unwrap(), [BFInstruction::ClearCell]);
+        assert_eq!(parse_data(b"[-]++[-]+++").unwrap(), [BFInstruction::SetCell(3)]);
+        assert_eq!(parse_data(b"+[-]--[-]-[+]+").unwrap(), [BFInstruction::SetCell(1)]);
+        // Anything that moves, reads or writes in between keeps the earlier store.
+        assert_eq!(parse_data(b"++.[-]").unwrap(), [BFInstruction::Add(2), BFInstruction::Output, BFInstruction::ClearCell]);
+        assert_eq!(parse_data(b"++>[-]").unwrap(), [BFInstruction::Add(2), BFInstruction::IncrementPointer(1), BFInstruction::ClearCell]);
+        assert_eq!(parse_data(b"+[>+++[-]]").unwrap(), [BFInstruction::Add(1), BFInstruction::LoopStart(4), BFInstruction::IncrementPointer(1), BFInstruction::ClearCell, BFInstruction::LoopEnd(1)]);
+        // An `Add` that might trap still runs.
+        let options = ParseOptions { cell_overflow: CellOverflow::Trap, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"-[-]", &options).unwrap(), [BFInstruction::Add(255), BFInstruction::ClearCell]);
+        assert_eq!(parse_data_with_options(b"[-]+[-]", &options).unwrap(), [BFInstruction::ClearCell]);
+    }
+    
+    #[test]
+    fn set_cell() {
+        assert_eq!(parse_data(b"[-]+++++").unwrap(), [BFInstruction::SetCell(5)]);
+        assert_eq!(parse_data(b"[+]++-.").unwrap(), [BFInstruction::SetCell(1), BFInstruction::Output]);
+        assert_eq!(parse_data(b"[-]--").unwrap(), [BFInstruction::SetCell(254)]);
+        assert_eq!(parse_data(b"[-]+-").unwrap(), [BFInstruction::SetCell(0)]);
+        assert_eq!(parse_data(b"[-]+--").unwrap(), [BFInstruction::SetCell(0), BFInstruction::Add(255)]);
+        assert_eq!(parse_data(b"[-]>+").unwrap(), [BFInstruction::ClearCell, BFInstruction::IncrementPointer(1), BFInstruction::Add(1)]);
+        assert_eq!(parse_data(b"+[[-]+>]").unwrap()[2], BFInstruction::SetCell(1));
+        let options = ParseOptions { cell_overflow: CellOverflow::Trap, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"[-]-", &options).unwrap(), [BFInstruction::ClearCell, BFInstruction::Add(255)]);
+    }
+    
+    #[test]
+    fn multiply_loops() {
+        assert_eq!(parse_data(b"[->+<]").unwrap(), [BFInstruction::MulAdd(Box::new([(1, 1)].into()))]);
+        assert_eq!(parse_data(b"[<+++>-]").unwrap(), [BFInstruction::MulAdd(Box::new([(-1, 3)].into()))]);
+        assert_eq!(parse_data(b"[->++>+++<<]").unwrap(), [BFInstruction::MulAdd(Box::new([(1, 2), (2, 3)].into()))]);
+        assert_eq!(parse_data(b"[->+<<]").unwrap().len(), 6);
+        assert_eq!(parse_data(b"[-->+<]").unwrap().len(), 6);
+        assert_eq!(parse_data(b"[->+<.]").unwrap().len(), 7);
+        assert_eq!(parse_data(b"[.->+<]").unwrap().len(), 7);
+        assert_eq!(parse_data(b"[->,<]").unwrap().len(), 6);
+        assert_eq!(parse_data(b"[->[-]<]").unwrap().len(), 6);
+        // Mid-body changes to the control cell are fine as long as the net change is -1.
+        assert_eq!(parse_data(b"[++>+<---]").unwrap(), [BFInstruction::MulAdd(Box::new([(1, 1)].into()))]);
+        assert_eq!(parse_data(b"[->+<+>+<-]").unwrap(), [BFInstruction::MulAdd(Box::new([(1, 2)].into()))]);
+        assert_eq!(parse_data(b"[+>+<--]").unwrap(), [BFInstruction::MulAdd(Box::new([(1, 1)].into()))]);
+    }
+    
+    #[test]
+    fn linear_loops() {
+        // Starting from `v`, `[+++>+<]` runs `v * 85` times, since 3 * -85 = 1 modulo 256.
+        assert_eq!(parse_data(b"[+++>+<]").unwrap(), [BFInstruction::MulAdd(Box::new([(1, 85)].into()))]);
+        assert_eq!(parse_data(b"[--->+<]").unwrap(), [BFInstruction::MulAdd(Box::new([(1, 171)].into()))]);
+        assert_eq!(parse_data(b"[--->++<<->]").unwrap(), [BFInstruction::MulAdd(Box::new([(1, 86), (-1, 85)].into()))]);
+        assert_eq!(parse_data(b"[+]").unwrap(), [BFInstruction::ClearCell]);
+        assert_eq!(parse_data(b"[-----]").unwrap(), [BFInstruction::ClearCell]);
+        assert_eq!(parse_data(b"[>+<+++<>]").unwrap(), [BFInstruction::MulAdd(Box::new([(1, 85)].into()))]);
+        
+        // Even steps loop forever from odd values, and a zero step from any nonzero one.
+        assert_eq!(parse_data(b"[++>+<]").unwrap().len(), 6);
+        assert_eq!(parse_data(b"[-->+<]").unwrap().len(), 6);
+        assert_eq!(parse_data(b"[+>+<-]").unwrap().len(), 7);
+        assert_eq!(parse_data(b"[>+<]").unwrap().len(), 5);
+        assert_eq!(parse_data(b"[--]").unwrap().len(), 3);
+        
+        let options = ParseOptions { cell_width: CellWidth::U16, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"[+++>+<]", &options).unwrap(), [BFInstruction::MulAdd(Box::new([(1, 21845)].into()))]);
+        let options = ParseOptions { cell_width: CellWidth::U32, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"[+++>+<]", &options).unwrap(), [BFInstruction::MulAdd(Box::new([(1, 0x5555_5555)].into()))]);
+        let options = ParseOptions { cell_overflow: CellOverflow::Trap, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"[+++>+<]", &options).unwrap().len(), 6);
+        assert_eq!(parse_data_with_options(b"[---]", &options).unwrap().len(), 3);
+    }
+    
+    #[test]
+    fn wide_cells() {
+        let options = ParseOptions { cell_width: CellWidth::U16, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"-", &options).unwrap(), [BFInstruction::Add(u16::MAX as u32)]);
+        assert_eq!(parse_data_with_options(&[b'+'; 256], &options).unwrap(), [BFInstruction::Add(256)]);
+        assert_eq!(parse_data(&[b'+'; 256]).unwrap(), []);
+        assert_eq!(parse_data_with_options(b"[-]", &options).unwrap(), [BFInstruction::ClearCell]);
+        assert_eq!(parse_data_with_options(b"[->+<]", &options).unwrap(), [BFInstruction::MulAdd(Box::new([(1, 1)].into()))]);
+    }
+    
+    #[test]
+    fn checked_runs() {
+        let options = ParseOptions { cell_overflow: CellOverflow::Trap, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"++-", &options).unwrap(), [BFInstruction::Add(2), BFInstruction::Add(255)]);
+        assert_eq!(parse_data_with_options(b"--+", &options).unwrap(), [BFInstruction::Add(254), BFInstruction::Add(1)]);
+        assert_eq!(parse_data_with_options(&[b'+'; 200], &options).unwrap(), [BFInstruction::Add(127), BFInstruction::Add(73)]);
+        assert_eq!(parse_data_with_options(&[b'-'; 200], &options).unwrap(), [BFInstruction::Add(128), BFInstruction::Add(184)]);
+        assert_eq!(parse_data_with_options(b"[-]", &options).unwrap(), [BFInstruction::ClearCell]);
+        assert_eq!(parse_data_with_options(b"[+]", &options).unwrap().len(), 3);
+        assert_eq!(parse_data_with_options(b"[->+<]", &options).unwrap(), [BFInstruction::MulAdd(Box::new([(1, 1)].into()))]);
+        assert_eq!(parse_data_with_options(b"[+>+<--]", &options).unwrap().len(), 7);
+        assert_eq!(parse_data_with_options(b"[->+>-<+<]", &options).unwrap().len(), 10);
+    }
+    
+    #[test]
+    fn comment_loop() {
+        let options = ParseOptions { drop_comment_loop: true, ..ParseOptions::default() };
+        let header = [&b"["[..], &b"This comment mentions +, -, <, ., and [nested] loops.\n".repeat(1000), b"]"].concat();
+        assert!(parse_data(&header).unwrap().len() > 6000);
+        assert_eq!(parse_data_with_options(&header, &options).unwrap(), []);
+        assert_eq!(parse_data_with_options(&[&header[..], b"+-[-][.]+."].concat(), &options).unwrap(), [BFInstruction::Add(1), BFInstruction::Output]);
+        assert_eq!(parse_data_with_options(b".[-]", &options).unwrap(), [BFInstruction::Output, BFInstruction::ClearCell]);
+        assert_eq!(parse_data_with_options(b"[[]", &options).unwrap_err().kind, ParseErrorKind::UnclosedBracket);
+    }
+    
+    #[test]
+    fn debug_char() {
+        assert_eq!(parse_data(b"+#+").unwrap(), [BFInstruction::Add(2)]);
+        let options = ParseOptions { debug_char: true, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"+#+", &options).unwrap(), [BFInstruction::Add(1), BFInstruction::Debug, BFInstruction::Add(1)]);
+        assert_eq!(parse_data_with_options(b"[-#]", &options).unwrap().len(), 4);
+    }
+    
+    #[test]
+    fn byte_order_mark() {
+        assert_eq!(parse_data(b"\xEF\xBB\xBF+.").unwrap(), [BFInstruction::Add(1), BFInstruction::Output]);
+        let options = ParseOptions { strict: true, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"\xEF\xBB\xBF+.", &options).unwrap(), [BFInstruction::Add(1), BFInstruction::Output]);
+        // Only at the very start.
+        assert_eq!(parse_data_with_options(b"+\xEF\xBB\xBF", &options).unwrap_err().kind, ParseErrorKind::UnexpectedByte(0xEF));
+    }
+    
+    #[test]
+    fn shebang() {
+        let script = b"#!/usr/bin/env -S bfint --bang-input\n+.!in";
+        assert_eq!(parse_data(script).unwrap(), [BFInstruction::Add(1), BFInstruction::Output]);
+        assert_eq!(parse_data(&[&b"\xEF\xBB\xBF"[..], script].concat()).unwrap(), [BFInstruction::Add(1), BFInstruction::Output]);
+        assert_eq!(parse_reader(&script[..]).unwrap(), [BFInstruction::Add(1), BFInstruction::Output]);
+        assert_eq!(parse_data(b"#!-").unwrap(), []);
+        
+        let options = ParseOptions { strict: true, debug_char: true, bang_input: true, ..ParseOptions::default() };
+        let (program, input) = parse_data_with_input(script, &options).unwrap();
+        assert_eq!((&program[..], input), (&[BFInstruction::Add(1), BFInstruction::Output][..], &b"in"[..]));
+        assert_eq!(count_commands(script, &options), 2);
+        assert_eq!(parse_data_with_options(b"#!\n]", &options).unwrap_err().to_string(), "unexpected ']' at line 2, column 1");
+        assert_eq!(lint(b"#![\n]", &ParseOptions::default()).len(), 1);
+        
+        // A `#!` anywhere else is just the two bytes.
+        assert_eq!(parse_data_with_options(b"+\n#!+", &ParseOptions { bang_input: false, ..options }).unwrap_err().kind, ParseErrorKind::UnexpectedByte(b'!'));
+        assert_eq!(parse_data_with_options(b"#+!", &ParseOptions { debug_char: true, ..ParseOptions::default() }).unwrap(), [BFInstruction::Debug, BFInstruction::Add(1)]);
+    }
+    
+    #[test]
+    fn strict() {
+        assert_eq!(parse_data(b"+x+").unwrap(), [BFInstruction::Add(2)]);
+        let options = ParseOptions { strict: true, ..ParseOptions::default() };
+        assert_eq!(parse_data_with_options(b"+ +\t\r\n[>]", &options).unwrap(), [BFInstruction::Add(2), BFInstruction::ScanRight(1)]);
+        let err = parse_data_with_options(b"++\n+x+", &options).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::UnexpectedByte(b'x'));
+        assert_eq!(err.to_string(), "unexpected byte 'x' at line 2, column 2");
+        assert_eq!(parse_data_with_options(b"\x00", &options).unwrap_err().to_string(), "unexpected byte '\\x00' at line 1, column 1");
+        assert_eq!(parse_data_with_options(b"#", &options).unwrap_err().kind, ParseErrorKind::UnexpectedByte(b'#'));
+        assert_eq!(parse_data_with_options(b"#", &ParseOptions { debug_char: true, ..options }).unwrap(), [BFInstruction::Debug]);
+    }
+    
+    #[test]
+    fn bang_input() {
+        let options = ParseOptions { bang_input: true, strict: true, ..ParseOptions::default() };
+        let (program, input) = parse_data_with_input(b",.!A", &options).unwrap();
+        assert_eq!(program, [BFInstruction::Input, BFInstruction::Output]);
+        assert_eq!(input, b"A");
+        let mut output = Vec::new();
+        crate::interpreter::run_program_io(&program, 1, &mut &input[..], &mut output);
+        assert_eq!(output, b"A");
+        
+        assert_eq!(parse_data_with_input(b"+[!]", &options).unwrap_err().kind, ParseErrorKind::UnclosedBracket);
+        assert_eq!(parse_reader_with_options(&b"+!+!"[..], &options).unwrap(), [BFInstruction::Add(1)]);
+        assert_eq!(parse_data_with_input(b"+", &options).unwrap().1, b"");
+        assert_eq!(parse_data_with_input(b"+!+", &ParseOptions::default()).unwrap(), (vec![BFInstruction::Add(2)], &b""[..]));
+    }
+    
+    #[test]
+    fn dialect() {
+        let dialect = Dialect::parse(b"+ a\n- b\n> r\n< l\n. o\n[ (\n] )\n").unwrap();
+        let options = ParseOptions { dialect: Some(dialect), ..ParseOptions::default() };
+        let program = parse_data_with_options(b"aaaa(r+aaaaaaaaaaaaaaaa-lb)r+o", &options).unwrap();
+        assert_eq!(program, [BFInstruction::Add(4), BFInstruction::MulAdd(Box::new([(1, 16)].into())), BFInstruction::IncrementPointer(1), BFInstruction::Output]);
+        let mut output = Vec::new();
+        crate::interpreter::run_program_io(&program, 2, &mut io::empty(), &mut output);
+        assert_eq!(output, b"@");
+        
+        let strict = ParseOptions { strict: true, ..options };
+        assert_eq!(parse_data_with_options(b"a+", &strict).unwrap_err().kind, ParseErrorKind::UnexpectedByte(b'+'));
+    }
+    
+    #[test]
+    fn command_count() {
+        assert_eq!(count_commands(b"++ [->+<] comment.", &ParseOptions::default()), 9);
+        assert_eq!(count_commands(b"+#+!+", &ParseOptions::default()), 3);
+        assert_eq!(count_commands(b"+#+!+", &ParseOptions { debug_char: true, bang_input: true, ..ParseOptions::default() }), 3);
+    }
+    
+    #[test]
+    fn max_nesting() {
+        let options = ParseOptions { max_nesting: Some(2), ..ParseOptions::default() };
+        assert!(parse_data_with_options(b"+[>[.]<[.]-]", &options).is_ok());
+        let err = parse_data_with_options(b"+[>[.[\n-]]]", &options).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::NestingTooDeep(2));
+        assert_eq!(err.to_string(), "loops nested more than 2 deep at line 1, column 6");
+        assert_eq!(parse_data_with_options(b"[]", &ParseOptions { max_nesting: Some(0), ..options }).unwrap_err().kind, ParseErrorKind::NestingTooDeep(0));
+    }
+    
+    #[test]
+    fn empty_loops() {
+        static WARNED: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+        let options = ParseOptions { empty_loops: EmptyLoops::Warn(|err| WARNED.lock().unwrap().push(err.position.offset)), ..ParseOptions::default() };
+        assert!(parse_data_with_options(b"+[]>[[+-]<]+[ comment ]+[.]", &options).is_ok());
+        assert_eq!(*WARNED.lock().unwrap(), [1, 5, 12]);
+        
+        let options = ParseOptions { empty_loops: EmptyLoops::Reject, ..ParseOptions::default() };
+        let err = parse_data_with_options(b"+.\n+[]", &options).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::EmptyLoop);
+        assert_eq!(err.to_string(), "empty loop at line 2, column 2");
+        assert!(parse_data_with_options(b"[]+[-]", &ParseOptions { drop_comment_loop: true, ..options }).is_ok());
+    }
+    
+    #[test]
+    fn endless_loops() {
+        static WARNED: Mutex<Vec<(usize, ParseErrorKind)>> = Mutex::new(Vec::new());
+        let options = ParseOptions { empty_loops: EmptyLoops::Warn(|err| WARNED.lock().unwrap().push((err.position.offset, err.kind))), ..ParseOptions::default() };
+        let program = parse_data_with_options(b"+[>+<]>[>-<<++>+--]<[>+<-]>[>>+<+<-+]<[[-]>]", &options).unwrap();
+        assert_eq!(*WARNED.lock().unwrap(), [(1, ParseErrorKind::EndlessLoop), (27, ParseErrorKind::EndlessLoop)]);
+        // Flagged loops are still kept.
+        assert_eq!(program[1], BFInstruction::LoopStart(5));
+        
+        let options = ParseOptions { empty_loops: EmptyLoops::Reject, ..ParseOptions::default() };
+        let err = parse_data_with_options(b"+\n +[<++>]", &options).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::EndlessLoop);
+        assert_eq!(err.to_string(), "endless loop at line 2, column 3");
+        
+        // 256 steps wrap an 8-bit cell back to where it was, but not a 16-bit one.
+        let wraps = format!("+[{}]", "+".repeat(256));
+        assert!(parse_data_with_options(wraps.as_bytes(), &options).is_err());
+        assert!(parse_data_with_options(wraps.as_bytes(), &ParseOptions { cell_width: CellWidth::U16, ..options.clone() }).is_ok());
+        // An overflow ends the loop if it traps.
+        assert!(parse_data_with_options(b"+[>+<]", &ParseOptions { cell_overflow: CellOverflow::Trap, ..options.clone() }).is_ok());
+        assert!(parse_data_with_options(b"+[><]+[.]+[,]", &options).is_err());
+    }
+    
+    #[test]
+    fn echo_loops() {
+        assert_eq!(parse_data(b"+[,.]").unwrap(), [BFInstruction::Add(1), BFInstruction::EchoInput]);
+        assert_eq!(parse_data(b"+[.,]").unwrap(), [BFInstruction::Add(1), BFInstruction::LoopStart(4), BFInstruction::Output, BFInstruction::Input, BFInstruction::LoopEnd(1)]);
+        assert_eq!(parse_data(b"+[,..]").unwrap()[1], BFInstruction::LoopStart(4));
+    }
+    
+    #[test]
+    fn scan_loops() {
+        assert_eq!(parse_data(b"[>]").unwrap(), [BFInstruction::ScanRight(1)]);
+        assert_eq!(parse_data(b"[<<]").unwrap(), [BFInstruction::ScanLeft(2)]);
+        assert_eq!(parse_data(b"[>>><]").unwrap(), [BFInstruction::ScanRight(2)]);
+        assert_eq!(parse_data(b"[>+]").unwrap().len(), 4);
+    }
+    
+    #[test]
+    fn invalid_loops() {
+        assert_eq!(parse_data(b"][").unwrap_err().kind, ParseErrorKind::UnexpectedCloseBracket);
+        assert_eq!(parse_data(b"[[]").unwrap_err().kind, ParseErrorKind::UnclosedBracket);
+        assert_eq!(parse_data(b"[]]").unwrap_err().kind, ParseErrorKind::UnexpectedCloseBracket);
+    }
+    
+    #[test]
+    fn lint_brackets() {
+        assert_eq!(lint(b"+[-]>[<]", &ParseOptions::default()), []);
+        
+        let errors = lint(b"]+]\n[-]\n[[", &ParseOptions::default());
+        let found: Vec<_> = errors.iter().map(|error| (error.kind, error.position.offset, error.position.line)).collect();
+        assert_eq!(found, [
+            (ParseErrorKind::UnexpectedCloseBracket, 0, 1),
+            (ParseErrorKind::UnexpectedCloseBracket, 2, 1),
+            (ParseErrorKind::UnclosedBracket, 8, 3),
+            (ParseErrorKind::UnclosedBracket, 9, 3),
+        ]);
+        assert_eq!(errors[3].to_string(), "unclosed '[' at line 3, column 2");
+        
+        let dialect = ParseOptions { dialect: Some(Dialect::parse(b"[ (\n] )\n").unwrap()), ..ParseOptions::default() };
+        let found: Vec<_> = lint(b"((])", &dialect).iter().map(|error| (error.kind, error.position.offset)).collect();
+        assert_eq!(found, [(ParseErrorKind::UnclosedBracket, 0)]);
+        let bang = ParseOptions { bang_input: true, ..ParseOptions::default() };
+        assert_eq!(lint(b"+[-]![[", &bang), []);
+        assert_eq!(lint(b"+[-]![[", &ParseOptions::default()).len(), 2);
+    }
+    
+    #[test]
+    fn source_round_trip() {
+        // Prints the squares up to 10000, which goes through most of the optimizer.
+        let source = b"++++[>+++++<-]>[<+++++>-]+<+[>[>+>+<<-]++>>[<<+>>-]>>>[-]++>[-]+>>>+[[-]++++++>>>]<<<[[<++++++++<++>>-]+<.<[>----<-]<]<<[>>>>>[>>>[-]+++++++++<[>-<-]+++++++++>[-[<->-]+[<<<]]<[>+<-]>]<<-]<<-]";
+        let mut program = parse_data(source).unwrap();
+        crate::optimizer::fuse_offset_adds(&mut program);
+        program.extend([
+            BFInstruction::ClearCellAtOffset(-1),
+            BFInstruction::Output,
+            BFInstruction::SetCellAtOffset { offset: 2, value: 250 },
+            BFInstruction::OutputN(2),
+            BFInstruction::IncrementPointer(1),
+            BFInstruction::EchoInput,
+        ]);
+        
+        let rendered = program_to_source(&program);
+        let mut parsed = parse_data(rendered.as_bytes()).unwrap();
+        crate::optimizer::fuse_offset_adds(&mut parsed);
+        assert_eq!(parsed, program);
+        
+        let run = |program: &[BFInstruction]| {
+            let mut output = Vec::new();
+            let outcome = crate::interpreter::run_program_outcome(program, 100, &crate::interpreter::RunOptions::default(), &mut &b"echo"[..], &mut output, &mut ());
+            (outcome, output)
+        };
+        assert_eq!(run(&parsed), run(&program));
+        
+        assert_eq!(program_to_source(&parse_data(b"+++<<--[-]++.[->+++<<-->][>>]").unwrap()), "+++<<[-]++.[->+++<<-->][>>]");
+    }
+    
+    #[test]
+    fn error_position() {
+        let err = parse_data(b"+[\n++]\n  ]").unwrap_err();
+        assert_eq!(err.position, Position { offset: 9, line: 3, column: 3 });
+        assert_eq!(err.to_string(), "unexpected ']' at line 3, column 3");
+        
+        let err = parse_data(b"[[]").unwrap_err();
+        assert_eq!(err.position, Position { offset: 0, line: 1, column: 1 });
+        assert_eq!(err.to_string(), "unclosed '[' at line 1, column 1");
+    }
+    
+    /// Hands out one byte per read, then fails.
+    struct Trickle<'a>(&'a [u8]);
+    
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((&first, rest)) = self.0.split_first() else {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            };
+            
+            buf[0] = first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+    
+    #[test]
+    fn reader() {
+        let source = b"++[->+<]\n>>[-]<<.,[>+<-]";
+        assert_eq!(parse_reader(&source[..]).unwrap(), parse_data(source).unwrap());
+        let err = parse_reader(Trickle(b"+[\n>")).unwrap_err();
+        assert_eq!(err.position, Position { offset: 4, line: 2, column: 2 });
+        assert_eq!(err.to_string(), "couldn't read source (broken pipe) at line 2, column 2");
+        assert_eq!(parse_reader(&b"+[\n++]\n  ]"[..]).unwrap_err(), parse_data(b"+[\n++]\n  ]").unwrap_err());
+    }
+}
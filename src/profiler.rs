@@ -0,0 +1,90 @@
+use std::cmp::Reverse;
+use std::io;
+use std::io::Write;
+use std::ops::ControlFlow;
+
+use crate::interpreter::{ExecutionResult, Hook, MachineState};
+use crate::parser::BFInstruction;
+
+/// Counts how often each instruction runs and how often each loop jumps back.
+pub struct Profiler {
+    executions: Vec<u64>,
+    back_jumps: Vec<u64>,
+}
+
+impl Profiler {
+    pub fn new(program: &[BFInstruction]) -> Self {
+        Self {
+            executions: vec![0; program.len()],
+            back_jumps: vec![0; program.len()],
+        }
+    }
+    
+    /// How many times the instruction at `idx` ran.
+    pub fn executions(&self, idx: usize) -> u64 {
+        self.executions[idx]
+    }
+    
+    /// How many times the `LoopEnd` at `idx` jumped back to its `LoopStart`.
+    pub fn back_jumps(&self, idx: usize) -> u64 {
+        self.back_jumps[idx]
+    }
+    
+    /// Writes the `top` most executed instructions, then every loop that iterated, most iterations first.
+    pub fn report(&self, program: &[BFInstruction], top: usize, out: &mut impl Write) -> io::Result<()> {
+        let mut hottest: Vec<usize> = (0..program.len()).filter(|&idx| self.executions[idx] > 0).collect();
+        hottest.sort_by_key(|&idx| (Reverse(self.executions[idx]), idx));
+        writeln!(out, "most executed instructions:")?;
+        for &idx in hottest.iter().take(top) {
+            writeln!(out, "{:>12}  {idx:>6}: {:?}", self.executions[idx], program[idx])?;
+        }
+        
+        let mut loops: Vec<usize> = (0..program.len()).filter(|&idx| self.back_jumps[idx] > 0).collect();
+        loops.sort_by_key(|&idx| (Reverse(self.back_jumps[idx]), idx));
+        writeln!(out, "loop back jumps:")?;
+        for &idx in &loops {
+            writeln!(out, "{:>12}  {idx:>6}: {:?}", self.back_jumps[idx], program[idx])?;
+        }
+        Ok(())
+    }
+}
+
+impl Hook for Profiler {
+    fn before_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        let idx = state.program_counter;
+        self.executions[idx] += 1;
+        if let BFInstruction::LoopEnd(_) = state.program[idx] {
+            if state.cell(state.data_pointer).is_some_and(|value| value != 0) {
+                self.back_jumps[idx] += 1;
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{RunOptions, run_program_with_hook};
+    use crate::parser::parse_data;
+    
+    #[test]
+    fn counts() {
+        let program = parse_data(b"+++[>++<-.]").unwrap();
+        let mut profiler = Profiler::new(&program);
+        assert_eq!(run_program_with_hook(&program, 2, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut profiler), ExecutionResult::Ok);
+        assert_eq!(profiler.executions(0), 1);
+        assert_eq!(profiler.executions(2), 3);
+        assert_eq!(profiler.back_jumps(7), 2);
+        
+        let mut out = Vec::new();
+        profiler.report(&program, 2, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+most executed instructions:
+           3       2: IncrementPointer(1)
+           3       3: Add(2)
+loop back jumps:
+           2       7: LoopEnd(1)
+");
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::io::Read;
+use std::ops::ControlFlow;
+use std::time::Instant;
+
+use crate::interpreter::{ExecutionResult, Hook, Machine, MachineState, RunOptions};
+use crate::parser::BFInstruction;
+
+/// Runs a program lazily, yielding its output one byte at a time.
+///
+/// The program is suspended after every instruction that writes output and continues once those bytes have been
+/// consumed, so even a program that never ends can be read from. If it stops with anything but `Ok`, that result
+/// comes after the last byte it wrote.
+///
+/// `,` reads from the input given to [`ProgramOutput::new`] whenever the program gets to it, which may be in the
+/// middle of a call to `next`; an input that blocks blocks the iterator. `RunOptions::max_steps`,
+/// `RunOptions::max_output` and `RunOptions::timeout` apply to the run as a whole, time spent outside `next` included.
+pub struct ProgramOutput<'a, R> {
+    program: &'a [BFInstruction],
+    machine: Machine,
+    options: RunOptions,
+    input: R,
+    program_counter: usize,
+    steps: u64,
+    /// Bytes the program has written so far, counted against `RunOptions::max_output`.
+    written: u64,
+    deadline: Option<Instant>,
+    buffered: VecDeque<u8>,
+    /// Why the program stopped early, held back until `buffered` is drained.
+    error: Option<ExecutionResult>,
+    finished: bool,
+}
+
+impl<'a, R: Read> ProgramOutput<'a, R> {
+    /// Prepares `program` to run on a tape of `memory_size` cells, as [`run_program_outcome`](crate::run_program_outcome) would.
+    pub fn new(program: &'a [BFInstruction], memory_size: usize, options: &RunOptions, input: R) -> Self {
+        Self {
+            program,
+            machine: Machine::new(memory_size, options),
+            // The tape already holds the initial memory, so don't copy it along with the options for every run.
+            options: RunOptions { initial_memory: Vec::new(), ..options.clone() },
+            input,
+            program_counter: 0,
+            steps: 0,
+            written: 0,
+            deadline: options.timeout.and_then(|timeout| Instant::now().checked_add(timeout)),
+            buffered: VecDeque::new(),
+            error: None,
+            finished: false,
+        }
+    }
+    
+    /// The machine as the program last left it.
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+    
+    /// Runs until the next instruction that writes output, or until the program stops.
+    fn run_to_output(&mut self) {
+        let options = RunOptions {
+            max_steps: self.options.max_steps.map(|max_steps| max_steps.saturating_sub(self.steps)),
+            max_output: self.options.max_output.map(|max_output| max_output.saturating_sub(self.written)),
+            timeout: self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now())),
+            ..self.options.clone()
+        };
+        let mut output = Vec::new();
+        let (result, program_counter, steps) = self.machine.run_from(self.program, self.program_counter, &options, &mut self.input, &mut output, &mut SuspendAfterOutput::default());
+        self.program_counter = program_counter;
+        self.steps += steps;
+        self.written += output.len() as u64;
+        self.buffered.extend(output);
+        match result {
+            // Stopped by the hook, with more to run.
+            ExecutionResult::Ok if program_counter < self.program.len() => {}
+            ExecutionResult::Ok => self.finished = true,
+            result => {
+                self.error = Some(result);
+                self.finished = true;
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for ProgramOutput<'_, R> {
+    type Item = Result<u8, ExecutionResult>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(byte) = self.buffered.pop_front() {
+                return Some(Ok(byte));
+            }
+            if let Some(error) = self.error.take() {
+                return Some(Err(error));
+            }
+            if self.finished {
+                return None;
+            }
+            self.run_to_output();
+        }
+    }
+}
+
+/// Stops execution right after an instruction that can write output.
+#[derive(Default)]
+struct SuspendAfterOutput {
+    output: bool,
+}
+
+impl Hook for SuspendAfterOutput {
+    fn before_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        self.output = matches!(state.program[state.program_counter], BFInstruction::Output | BFInstruction::OutputN(_) | BFInstruction::EchoInput);
+        ControlFlow::Continue(())
+    }
+    
+    fn after_instruction(&mut self, _state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        match self.output {
+            true => ControlFlow::Break(ExecutionResult::Ok),
+            false => ControlFlow::Continue(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_data;
+    use std::io;
+    
+    #[test]
+    fn lazy_output() {
+        let program = parse_data(b",[.,]").unwrap();
+        let output: Result<Vec<u8>, _> = ProgramOutput::new(&program, 1, &RunOptions::default(), &b"streamed"[..]).collect();
+        assert_eq!(output.unwrap(), b"streamed");
+        
+        // A program that never ends still yields as it goes.
+        let program = parse_data(b"+[.+]").unwrap();
+        let output: Vec<_> = ProgramOutput::new(&program, 1, &RunOptions::default(), io::empty()).take(3).collect();
+        assert_eq!(output, [Ok(1), Ok(2), Ok(3)]);
+    }
+    
+    #[test]
+    fn errors_follow_output() {
+        let program = parse_data(b".+.>.").unwrap();
+        let output: Vec<_> = ProgramOutput::new(&program, 1, &RunOptions::default(), io::empty()).collect();
+        assert_eq!(output, [Ok(0), Ok(1), Err(ExecutionResult::MemoryAccessError(4))]);
+        
+        // The step limit counts every step, not just those since the last byte.
+        let program = parse_data(b"+[.]").unwrap();
+        let options = RunOptions { max_steps: Some(5), ..RunOptions::default() };
+        let output: Vec<_> = ProgramOutput::new(&program, 1, &options, io::empty()).collect();
+        assert_eq!(output, [Ok(1), Ok(1), Err(ExecutionResult::StepLimitExceeded)]);
+        let options = RunOptions { max_output: Some(2), ..RunOptions::default() };
+        let output: Vec<_> = ProgramOutput::new(&program, 1, &options, io::empty()).collect();
+        assert_eq!(output, [Ok(1), Ok(1), Err(ExecutionResult::OutputLimitExceeded)]);
+    }
+}
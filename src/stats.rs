@@ -0,0 +1,172 @@
+use std::fmt;
+
+use crate::parser::{BFInstruction, ParseOptions, commands};
+
+/// Every instruction kind, in the order [`ProgramStats`] lists them.
+const KINDS: [&str; 17] = [
+    "Add",
+    "IncrementPointer",
+    "Output",
+    "Input",
+    "LoopStart",
+    "LoopEnd",
+    "ClearCell",
+    "SetCell",
+    "MulAdd",
+    "AddAtOffset",
+    "ClearCellAtOffset",
+    "SetCellAtOffset",
+    "Debug",
+    "ScanRight",
+    "ScanLeft",
+    "OutputN",
+    "EchoInput",
+];
+
+/// A summary of a parsed program's size and shape, for judging how much the optimizer fused.
+///
+/// Displays as a single line of `key=value` pairs that always has the same keys in the same order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramStats {
+    /// Commands in the source, as counted by [`count_commands`](crate::parser::count_commands).
+    pub commands: usize,
+    pub instructions: usize,
+    /// Loops left after optimization.
+    pub loops: usize,
+    /// How deeply the remaining loops nest; zero if there are none.
+    pub max_depth: usize,
+    /// How many instructions there are of each kind, including kinds that don't occur.
+    pub kinds: Vec<(&'static str, usize)>,
+}
+
+impl ProgramStats {
+    pub fn new(program: &[BFInstruction], commands: usize) -> Self {
+        let mut kinds: Vec<_> = KINDS.iter().map(|&kind| (kind, 0)).collect();
+        let mut depth = 0;
+        let mut max_depth = 0;
+        for instruction in program {
+            match instruction {
+                BFInstruction::LoopStart(_) => {
+                    depth += 1;
+                    max_depth = max_depth.max(depth);
+                }
+                BFInstruction::LoopEnd(_) => depth -= 1,
+                _ => {}
+            }
+            let kind = kinds.iter_mut().find(|(kind, _)| *kind == instruction.name()).expect("every kind is listed");
+            kind.1 += 1;
+        }
+        
+        Self {
+            commands,
+            instructions: program.len(),
+            loops: kinds[4].1,
+            max_depth,
+            kinds,
+        }
+    }
+}
+
+impl fmt::Display for ProgramStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "commands={} instructions={} ", self.commands, self.instructions)?;
+        match self.instructions {
+            0 => write!(f, "ratio=-")?,
+            instructions => write!(f, "ratio={:.2}", self.commands as f64 / instructions as f64)?,
+        }
+        write!(f, " loops={} max_depth={}", self.loops, self.max_depth)?;
+        for (kind, count) in &self.kinds {
+            write!(f, " {kind}={count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The eight commands, in the order [`GolfStats`] lists them.
+const COMMANDS: [u8; 8] = *b"+-<>.,[]";
+
+/// A summary of a program's source, for comparing golfed versions of it.
+///
+/// Displays as one `key=value` line per count, always the same keys in the same order, so two summaries can be diffed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GolfStats {
+    /// The length of the source in bytes.
+    pub bytes: usize,
+    /// How many times each of the eight commands occurs, in the order `+-<>.,[]`.
+    pub commands: [(char, usize); 8],
+    /// Bytes that aren't commands and so are dropped by parsing, like comments, whitespace and a `#!` line.
+    pub comments: usize,
+    /// Instructions left once the commands are fused.
+    pub instructions: usize,
+}
+
+impl GolfStats {
+    /// Summarizes `source`, which parsed with `options` into `program`.
+    pub fn new(source: &[u8], options: &ParseOptions, program: &[BFInstruction]) -> Self {
+        let mut counts = COMMANDS.map(|command| (char::from(command), 0));
+        let mut total = 0;
+        for command in commands(source, options) {
+            total += 1;
+            if let Some(count) = counts.iter_mut().find(|(counted, _)| *counted == char::from(command)) {
+                count.1 += 1;
+            }
+        }
+        Self {
+            bytes: source.len(),
+            commands: counts,
+            comments: source.len() - total,
+            instructions: program.len(),
+        }
+    }
+}
+
+impl fmt::Display for GolfStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "bytes={}", self.bytes)?;
+        for (command, count) in &self.commands {
+            writeln!(f, "{command}={count}")?;
+        }
+        writeln!(f, "comments={}", self.comments)?;
+        write!(f, "instructions={}", self.instructions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ParseOptions, count_commands, parse_data};
+    
+    #[test]
+    fn summary() {
+        let source = b"++++[>++[>+.<-]<-] [-] comment";
+        let stats = ProgramStats::new(&parse_data(source).unwrap(), count_commands(source, &ParseOptions::default()));
+        assert_eq!(stats.commands, 21);
+        assert_eq!(stats.instructions, 15);
+        assert_eq!(stats.loops, 2);
+        assert_eq!(stats.max_depth, 2);
+        assert_eq!(stats.to_string(), "commands=21 instructions=15 ratio=1.40 loops=2 max_depth=2 \
+Add=5 IncrementPointer=4 Output=1 Input=0 LoopStart=2 LoopEnd=2 ClearCell=1 SetCell=0 MulAdd=0 AddAtOffset=0 ClearCellAtOffset=0 SetCellAtOffset=0 Debug=0 ScanRight=0 ScanLeft=0 OutputN=0 EchoInput=0");
+
+        assert_eq!(ProgramStats::new(&[], 0).to_string().split(' ').take(5).collect::<Vec<_>>(), ["commands=0", "instructions=0", "ratio=-", "loops=0", "max_depth=0"]);
+    }
+    
+    #[test]
+    fn golf() {
+        let source = b"#!/usr/bin/env bfint\n++++[>++[>+.<-]<-] [-] comment";
+        let program = parse_data(source).unwrap();
+        let stats = GolfStats::new(source, &ParseOptions::default(), &program);
+        assert_eq!(stats.comments, 30);
+        assert_eq!(stats.to_string(), "\
+bytes=51
++=7
+-=3
+<=2
+>=2
+.=1
+,=0
+[=3
+]=3
+comments=30
+instructions=15");
+    }
+}
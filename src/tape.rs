@@ -0,0 +1,196 @@
+use crate::cell::Cell;
+
+/// How the tape behaves when the data pointer leaves the allocated cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TapeMode {
+    /// Accessing a cell outside `0..memory_size` is an error.
+    #[default]
+    Fixed,
+    /// The tape starts with `memory_size` cells and doubles whenever a cell past the end is written.
+    /// Accessing a cell left of the first one is still an error.
+    Growing,
+    /// Like `Growing`, but the tape also extends to the left of the starting cell.
+    Bidirectional,
+    /// Moving off either end of the `memory_size` cells wraps around to the other, so every pointer is valid.
+    Circular,
+}
+
+pub(crate) struct Tape<C> {
+    cells: Vec<C>,
+    /// Index in `cells` of the cell the data pointer started at.
+    origin: usize,
+    mode: TapeMode,
+    /// Pointers at or past this are out of bounds, however many cells there are.
+    limit: isize,
+}
+
+impl<C: Cell> Tape<C> {
+    /// Creates a tape of `size` cells, the first of which hold `initial` (up to `size` bytes of it), the rest zero.
+    ///
+    /// With a `limit`, cells from that index on can't be accessed, whether or not they're allocated.
+    pub(crate) fn new(size: usize, mode: TapeMode, initial: &[u8], limit: Option<usize>) -> Self {
+        let mut cells = vec![C::default(); size];
+        for (cell, &byte) in cells.iter_mut().zip(initial) {
+            *cell = C::from_u32(byte.into());
+        }
+        Self::from_snapshot(cells, 0, mode, limit)
+    }
+    
+    /// Recreates a tape from the cells [`TapeView::snapshot`] returned.
+    pub(crate) fn from_snapshot(cells: Vec<C>, origin: usize, mode: TapeMode, limit: Option<usize>) -> Self {
+        // Comparing pointers as signed keeps addresses left of the start, which wrap to huge values, under the limit.
+        let limit = limit.map_or(isize::MAX, |limit| limit.min(isize::MAX as usize) as isize);
+        Self { cells, origin, mode, limit }
+    }
+    
+    /// Reduces `pointer` to `0..memory_size` on circular tapes; other tapes leave pointers alone.
+    pub(crate) fn normalize(&self, pointer: usize) -> usize {
+        match self.mode {
+            TapeMode::Circular if !self.cells.is_empty() => (pointer as isize).rem_euclid(self.cells.len() as isize) as usize,
+            _ => pointer,
+        }
+    }
+    
+    pub(crate) fn get(&self, pointer: usize) -> Option<C> {
+        let pointer = self.normalize(pointer);
+        if pointer as isize >= self.limit {
+            return None;
+        }
+        let index = self.origin.wrapping_add(pointer);
+        match self.cells.get(index) {
+            Some(&cell) => Some(cell),
+            None => match self.mode {
+                TapeMode::Fixed | TapeMode::Circular => None,
+                TapeMode::Growing => (index as isize >= 0).then(C::default),
+                TapeMode::Bidirectional => Some(C::default()),
+            }
+        }
+    }
+    
+    pub(crate) fn get_mut(&mut self, pointer: usize) -> Option<&mut C> {
+        let pointer = self.normalize(pointer);
+        if pointer as isize >= self.limit {
+            return None;
+        }
+        let index = self.origin.wrapping_add(pointer);
+        if index >= self.cells.len() {
+            match self.mode {
+                TapeMode::Fixed | TapeMode::Circular => return None,
+                TapeMode::Growing if (index as isize) < 0 => return None,
+                TapeMode::Bidirectional if (index as isize) < 0 => {
+                    self.grow_left(index.wrapping_neg())?;
+                    return self.cells.get_mut(self.origin.wrapping_add(pointer));
+                }
+                TapeMode::Growing | TapeMode::Bidirectional => self.grow_right(index)?,
+            }
+        }
+        
+        self.cells.get_mut(index)
+    }
+    
+    /// Steps right from `pointer` until a zero cell, or returns `None` if the scan leaves the tape.
+    ///
+    /// On a circular tape without a zero cell in reach, this returns `pointer` itself, whose cell is nonzero.
+    pub(crate) fn scan_right(&self, pointer: usize, step: usize) -> Option<usize> {
+        if self.get(pointer)? == C::default() {
+            return Some(pointer);
+        }
+        if self.mode == TapeMode::Circular {
+            return self.scan_circular(pointer, step % self.cells.len());
+        }
+        
+        let index = self.origin.wrapping_add(pointer);
+        let end = self.cells.len().min(self.origin.saturating_add(self.limit as usize));
+        match self.cells[index..end].iter().step_by(step).position(|&cell| cell == C::default()) {
+            Some(steps) => Some(pointer + steps * step),
+            // Everything past the end reads as zero on tapes that grow, up to the limit.
+            None => match self.mode {
+                TapeMode::Fixed | TapeMode::Circular => None,
+                TapeMode::Growing | TapeMode::Bidirectional => Some(pointer + (end - index).div_ceil(step) * step).filter(|&pointer| (pointer as isize) < self.limit),
+            }
+        }
+    }
+    
+    /// Steps left from `pointer` until a zero cell, or returns `None` if the scan leaves the tape.
+    ///
+    /// On a circular tape without a zero cell in reach, this returns `pointer` itself, whose cell is nonzero.
+    pub(crate) fn scan_left(&self, pointer: usize, step: usize) -> Option<usize> {
+        if self.get(pointer)? == C::default() {
+            return Some(pointer);
+        }
+        if self.mode == TapeMode::Circular {
+            return self.scan_circular(pointer, self.cells.len() - step % self.cells.len());
+        }
+        
+        let index = self.origin.wrapping_add(pointer);
+        match self.cells[..=index].iter().rev().step_by(step).position(|&cell| cell == C::default()) {
+            Some(steps) => Some(pointer - steps * step),
+            None => match self.mode {
+                TapeMode::Fixed | TapeMode::Growing | TapeMode::Circular => None,
+                TapeMode::Bidirectional => Some(pointer.wrapping_sub((index / step + 1) * step)),
+            }
+        }
+    }
+    
+    /// Steps right by `stride` around a circular tape, visiting at most every cell once, or returns `None` if it
+    /// reaches a cell past the limit.
+    fn scan_circular(&self, pointer: usize, stride: usize) -> Option<usize> {
+        let start = self.normalize(pointer);
+        let mut index = start;
+        for _ in 0..self.cells.len() {
+            if index as isize >= self.limit {
+                return None;
+            }
+            if self.cells[index] == C::default() {
+                return Some(index);
+            }
+            index = (index + stride) % self.cells.len();
+        }
+        Some(start)
+    }
+    
+    /// The cells from the starting one rightwards.
+    pub(crate) fn cells(&self) -> impl Iterator<Item=C> + '_ {
+        self.cells[self.origin..].iter().copied()
+    }
+    
+    fn grow_right(&mut self, index: usize) -> Option<()> {
+        let new_len = self.cells.len().saturating_mul(2).max(index + 1);
+        self.cells.try_reserve_exact(new_len - self.cells.len()).ok()?;
+        self.cells.resize(new_len, C::default());
+        Some(())
+    }
+    
+    fn grow_left(&mut self, missing: usize) -> Option<()> {
+        let added = self.cells.len().max(missing);
+        let mut cells = Vec::new();
+        cells.try_reserve_exact(added.checked_add(self.cells.len())?).ok()?;
+        cells.resize(added, C::default());
+        cells.append(&mut self.cells);
+        self.cells = cells;
+        self.origin += added;
+        Some(())
+    }
+}
+
+pub(crate) trait TapeView {
+    fn cell(&self, pointer: usize) -> Option<u32>;
+    /// Every cell from the starting one rightwards.
+    fn memory(&self) -> Vec<u32>;
+    /// Every allocated cell, including any left of the starting one, and the index of the starting one.
+    fn snapshot(&self) -> (Vec<u32>, usize);
+}
+
+impl<C: Cell> TapeView for Tape<C> {
+    fn cell(&self, pointer: usize) -> Option<u32> {
+        self.get(pointer).map(C::to_u32)
+    }
+    
+    fn memory(&self) -> Vec<u32> {
+        self.cells().map(C::to_u32).collect()
+    }
+    
+    fn snapshot(&self) -> (Vec<u32>, usize) {
+        (self.cells.iter().map(|&cell| cell.to_u32()).collect(), self.origin)
+    }
+}
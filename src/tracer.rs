@@ -0,0 +1,127 @@
+use std::io;
+use std::io::Write;
+use std::ops::ControlFlow;
+
+use crate::interpreter::{ExecutionResult, Hook, MachineState};
+use crate::parser::BFInstruction;
+
+/// The instruction being traced, captured before it runs.
+struct Step {
+    program_counter: usize,
+    data_pointer: usize,
+    /// The cell the instruction changes, or the current one for instructions that don't write.
+    cell: usize,
+    before: Option<u32>,
+}
+
+/// Writes a line to `out` for every executed instruction: its index, the instruction,
+/// the data pointer and the value of the cell it affects before and after.
+pub struct Tracer<W> {
+    out: W,
+    /// Stop tracing after this many lines.
+    limit: Option<u64>,
+    lines: u64,
+    step: Option<Step>,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Creates a tracer that writes at most `limit` lines, if given.
+    pub fn new(out: W, limit: Option<u64>) -> Self {
+        Self {
+            out,
+            limit,
+            lines: 0,
+            step: None,
+        }
+    }
+    
+    fn write_step(&mut self, program: &[BFInstruction], step: &Step, after: Option<Option<u32>>) -> io::Result<()> {
+        if self.limit.is_some_and(|limit| self.lines >= limit) {
+            return Ok(());
+        }
+        self.lines += 1;
+        
+        let value = |value: Option<u32>| value.map_or("-".to_string(), |value| value.to_string());
+        write!(self.out, "pc {}  {:?}  dp {}  ", step.program_counter, program[step.program_counter], step.data_pointer as isize)?;
+        match after {
+            Some(after) => writeln!(self.out, "[{}] {} -> {}", step.cell as isize, value(step.before), value(after))?,
+            None => writeln!(self.out, "[{}] {} -> stopped", step.cell as isize, value(step.before))?,
+        }
+        if self.limit == Some(self.lines) {
+            writeln!(self.out, "trace limit reached")?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Hook for Tracer<W> {
+    fn before_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        let cell = match state.program[state.program_counter] {
+            BFInstruction::AddAtOffset { offset, .. } | BFInstruction::ClearCellAtOffset(offset) | BFInstruction::SetCellAtOffset { offset, .. } => {
+                state.data_pointer.wrapping_add_signed(offset)
+            }
+            _ => state.data_pointer,
+        };
+        self.step = Some(Step {
+            program_counter: state.program_counter,
+            data_pointer: state.data_pointer,
+            cell,
+            before: state.cell(cell),
+        });
+        ControlFlow::Continue(())
+    }
+    
+    fn after_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        let Some(step) = self.step.take() else {
+            return ControlFlow::Continue(());
+        };
+        
+        match self.write_step(state.program, &step, Some(state.cell(step.cell))) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(err) => ControlFlow::Break(ExecutionResult::IOError(err)),
+        }
+    }
+    
+    fn after_execution(&mut self, state: &MachineState<'_>, _result: &ExecutionResult) {
+        // The instruction that stopped the program never reached `after_instruction`.
+        if let Some(step) = self.step.take() {
+            let _ = self.write_step(state.program, &step, None);
+        }
+        let _ = self.out.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{RunOptions, run_program_with_hook};
+    use crate::parser::parse_data;
+    
+    fn trace(source: &[u8], limit: Option<u64>) -> (ExecutionResult, String) {
+        let mut out = Vec::new();
+        let mut tracer = Tracer::new(&mut out, limit);
+        let result = run_program_with_hook(&parse_data(source).unwrap(), 2, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut tracer);
+        (result, String::from_utf8(out).unwrap())
+    }
+    
+    #[test]
+    fn every_step() {
+        assert_eq!(trace(b"++[->+<]>.<<+", None), (ExecutionResult::MemoryUnderflow(5), "\
+pc 0  Add(2)  dp 0  [0] 0 -> 2
+pc 1  MulAdd([(1, 1)])  dp 0  [0] 2 -> 0
+pc 2  IncrementPointer(1)  dp 0  [0] 0 -> 0
+pc 3  Output  dp 1  [1] 2 -> 2
+pc 4  IncrementPointer(18446744073709551614)  dp 1  [1] 2 -> 2
+pc 5  Add(1)  dp -1  [-1] - -> stopped
+".to_string()));
+    }
+    
+    #[test]
+    fn limit() {
+        assert_eq!(trace(b"+>+>", Some(2)), (ExecutionResult::Ok, "\
+pc 0  Add(1)  dp 0  [0] 0 -> 1
+pc 1  IncrementPointer(1)  dp 0  [0] 1 -> 1
+trace limit reached
+".to_string()));
+    }
+}
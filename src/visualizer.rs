@@ -0,0 +1,100 @@
+use std::io;
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::thread;
+use std::time::Duration;
+
+use crate::cell::CellWidth;
+use crate::interpreter::{ExecutionResult, Hook, MachineState};
+
+/// How many cells on each side of the data pointer are shown.
+const WINDOW: isize = 8;
+/// How many lines a frame takes, to move back up over it.
+const LINES: usize = 3;
+
+/// Draws the tape around the data pointer before every instruction, pausing after each frame so a run can be watched.
+///
+/// Each frame is drawn over the last with ANSI escapes: moving the cursor up (`CSI n A`), clearing to the end of the
+/// line (`CSI K`) and reverse video (`SGR 7`) for the current cell. So `out` needs to be a terminal that understands
+/// them, and nothing else should write to it during the run; the program's own output on the same terminal scrolls the
+/// old frames away instead of being drawn over, so it's best redirected.
+pub struct Visualizer<W> {
+    out: W,
+    delay: Duration,
+    cell_width: CellWidth,
+    /// Whether a frame is on screen to be drawn over.
+    drawn: bool,
+}
+
+impl<W: Write> Visualizer<W> {
+    /// Creates a visualizer drawing to `out` and waiting `delay` after every frame.
+    pub fn new(out: W, delay: Duration, cell_width: CellWidth) -> Self {
+        Self { out, delay, cell_width, drawn: false }
+    }
+    
+    fn draw(&mut self, state: &MachineState<'_>) -> io::Result<()> {
+        if self.drawn {
+            write!(self.out, "\x1b[{LINES}A")?;
+        }
+        self.drawn = true;
+        
+        match state.program.get(state.program_counter) {
+            Some(instruction) => writeln!(self.out, "pc {}  {instruction:?}\x1b[K", state.program_counter)?,
+            None => writeln!(self.out, "pc {}  finished\x1b[K", state.program_counter)?,
+        }
+        // Pointers that wrapped below zero are easier to read as negative.
+        let data_pointer = state.data_pointer as isize;
+        let pointers = data_pointer.saturating_sub(WINDOW)..=data_pointer.saturating_add(WINDOW);
+        let width = [self.cell_width.mask().to_string().len(), pointers.start().to_string().len(), pointers.end().to_string().len()].into_iter().max().unwrap_or(0);
+        let mut indices = String::new();
+        let mut values = String::new();
+        for pointer in pointers {
+            let value = state.cell(pointer as usize).map_or("-".to_string(), |value| value.to_string());
+            let (open, close) = if pointer == data_pointer { ("\x1b[7m", "\x1b[0m") } else { ("", "") };
+            indices += &format!(" {open}{pointer:>width$}{close}");
+            values += &format!(" {open}{value:>width$}{close}");
+        }
+        writeln!(self.out, "{indices}\x1b[K")?;
+        writeln!(self.out, "{values}\x1b[K")?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Hook for Visualizer<W> {
+    fn before_instruction(&mut self, state: &MachineState<'_>) -> ControlFlow<ExecutionResult> {
+        if let Err(err) = self.draw(state) {
+            return ControlFlow::Break(ExecutionResult::IOError(err));
+        }
+        thread::sleep(self.delay);
+        ControlFlow::Continue(())
+    }
+    
+    fn after_execution(&mut self, state: &MachineState<'_>, _result: &ExecutionResult) {
+        // The last frame is best effort; the caller still reports how the run ended.
+        let _ = self.draw(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{RunOptions, run_program_with_hook};
+    use crate::parser::parse_data;
+    
+    #[test]
+    fn walks_the_tape() {
+        let mut out = Vec::new();
+        let mut visualizer = Visualizer::new(&mut out, Duration::ZERO, CellWidth::U8);
+        assert_eq!(run_program_with_hook(&parse_data(b"+>++").unwrap(), 4, &RunOptions::default(), &mut io::empty(), &mut Vec::new(), &mut visualizer), ExecutionResult::Ok);
+        let out = String::from_utf8(out).unwrap();
+        // A frame before each instruction and one at the end, each after the first drawn over the last.
+        let frames: Vec<_> = out.split("\x1b[3A").collect();
+        assert_eq!(frames.len(), 4);
+        assert!(frames[1].starts_with("pc 1  IncrementPointer(1)\x1b[K\n"));
+        assert_eq!(frames[3], "\
+pc 3  finished\x1b[K
+  -7  -6  -5  -4  -3  -2  -1   0 \x1b[7m  1\x1b[0m   2   3   4   5   6   7   8   9\x1b[K
+   -   -   -   -   -   -   -   1 \x1b[7m  2\x1b[0m   0   0   -   -   -   -   -   -\x1b[K
+");
+    }
+}